@@ -0,0 +1,22 @@
+#version 130
+
+// GLSL shader to draw one point light into the light accumulation target
+// v_tex_coords go from -1.0 to 1.0, the light is in the center
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform float window_width;
+uniform float window_height;
+uniform vec4 light_color;
+uniform float light_intensity;
+uniform sampler2D shadow_mask;
+
+void main() {
+    // same as lighting::light_falloff
+    float falloff = clamp(1.0 - length(v_tex_coords), 0.0, 1.0);
+    falloff = falloff * falloff;
+
+    float visibility = texture(shadow_mask, gl_FragCoord.xy / vec2(window_width, window_height)).r;
+    color = vec4(light_color.rgb * light_intensity * falloff * visibility, 1.0);
+}
@@ -0,0 +1,51 @@
+#version 130
+
+// GLSL shader to draw a texture with some of its colors replaced
+// row 0 of the palette contains the colors of the texture, the other rows the replacements
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform float transparency;
+// multiplied with the texture color
+uniform vec4 tint;
+// mixed into the texture color, alpha is the strength of the flash
+uniform vec4 flash;
+
+uniform sampler2D palette;
+// number of colors in each row of the palette
+uniform int palette_size;
+// which row of the palette replaces the colors of row 0
+uniform int palette_row;
+
+const int MAX_PALETTE_COLORS = 16;
+// texture colors further away than this from every palette color are kept
+const float MATCH_THRESHOLD = 0.15;
+
+void main() {
+    vec4 texel = texture(tex, v_tex_coords);
+
+    int best_match = -1;
+    float best_distance = MATCH_THRESHOLD;
+    for (int i = 0; i < MAX_PALETTE_COLORS; i++) {
+        if (i >= palette_size) {
+            break;
+        }
+        float d = distance(texelFetch(palette, ivec2(i, 0), 0).rgb, texel.rgb);
+        if (d < best_distance) {
+            best_distance = d;
+            best_match = i;
+        }
+    }
+
+    if (best_match >= 0) {
+        texel.rgb = texelFetch(palette, ivec2(best_match, palette_row), 0).rgb;
+    }
+
+    vec4 cur_color = texel * tint;
+    cur_color.rgb = mix(cur_color.rgb, flash.rgb, flash.a);
+    cur_color.a *= transparency;
+
+    color = cur_color;
+}
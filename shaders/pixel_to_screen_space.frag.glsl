@@ -7,9 +7,14 @@ out vec4 color;
 
 uniform sampler2D tex;
 uniform float transparency;
+// multiplied with the texture color
+uniform vec4 tint;
+// mixed into the texture color, alpha is the strength of the flash
+uniform vec4 flash;
 
 void main() {
-    vec4 cur_color = texture(tex, v_tex_coords);
+    vec4 cur_color = texture(tex, v_tex_coords) * tint;
+    cur_color.rgb = mix(cur_color.rgb, flash.rgb, flash.a);
     cur_color.a *= transparency;
 
     color = cur_color;
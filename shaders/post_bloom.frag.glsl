@@ -0,0 +1,33 @@
+#version 130
+
+// Post-processing: adds a blurred copy of the bright parts of the image
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform float window_width;
+uniform float window_height;
+uniform float bloom_threshold;
+uniform float bloom_intensity;
+
+void main() {
+    vec4 scene_color = texture(tex, v_tex_coords);
+    vec2 texel = vec2(1.0 / window_width, 1.0 / window_height);
+
+    vec3 bloom = vec3(0.0);
+    float total_weight = 0.0;
+
+    for (int x = -2; x <= 2; x++) {
+        for (int y = -2; y <= 2; y++) {
+            vec2 offset = vec2(float(x), float(y)) * texel * 2.0;
+            vec3 sample_color = texture(tex, v_tex_coords + offset).rgb;
+            float brightness = dot(sample_color, vec3(0.2126, 0.7152, 0.0722));
+            float weight = 1.0 / (1.0 + float(x * x + y * y));
+            bloom += sample_color * max(brightness - bloom_threshold, 0.0) * weight;
+            total_weight += weight;
+        }
+    }
+
+    color = vec4(scene_color.rgb + (bloom / total_weight) * bloom_intensity, scene_color.a);
+}
@@ -0,0 +1,21 @@
+#version 130
+
+// Post-processing: brightness, contrast, saturation and tint
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform float grading_brightness;
+uniform float grading_contrast;
+uniform float grading_saturation;
+uniform vec3 grading_tint;
+
+void main() {
+    vec4 scene_color = texture(tex, v_tex_coords);
+    vec3 graded = scene_color.rgb + vec3(grading_brightness);
+    graded = (graded - 0.5) * grading_contrast + 0.5;
+    float luminance = dot(graded, vec3(0.2126, 0.7152, 0.0722));
+    graded = mix(vec3(luminance), graded, grading_saturation);
+    color = vec4(clamp(graded * grading_tint, 0.0, 1.0), scene_color.a);
+}
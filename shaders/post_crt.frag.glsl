@@ -0,0 +1,27 @@
+#version 130
+
+// Post-processing: CRT screen curvature and scanlines
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform float window_height;
+uniform float crt_curvature;
+uniform float crt_scanline_intensity;
+
+void main() {
+    // bend the texture coordinates outwards, like on a curved glass screen
+    vec2 centered = v_tex_coords * 2.0 - 1.0;
+    vec2 offset = centered.yx * centered.yx * crt_curvature;
+    vec2 curved = (centered + centered * offset) * 0.5 + 0.5;
+
+    if (curved.x < 0.0 || curved.x > 1.0 || curved.y < 0.0 || curved.y > 1.0) {
+        color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec4 scene_color = texture(tex, curved);
+    float scanline = sin(curved.y * window_height * 3.14159) * 0.5 + 0.5;
+    color = vec4(scene_color.rgb * (1.0 - crt_scanline_intensity * scanline), scene_color.a);
+}
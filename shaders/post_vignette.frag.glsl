@@ -0,0 +1,17 @@
+#version 130
+
+// Post-processing: darkens the corners of the screen
+
+in vec2 v_tex_coords;
+out vec4 color;
+
+uniform sampler2D tex;
+uniform float vignette_radius;
+uniform float vignette_intensity;
+
+void main() {
+    vec4 scene_color = texture(tex, v_tex_coords);
+    float dist = distance(v_tex_coords, vec2(0.5, 0.5));
+    float vignette = smoothstep(vignette_radius, vignette_radius - 0.45, dist);
+    color = vec4(mix(scene_color.rgb, scene_color.rgb * vignette, vignette_intensity), scene_color.a);
+}
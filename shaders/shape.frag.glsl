@@ -0,0 +1,10 @@
+#version 130
+
+// GLSL shader to draw untextured, colored triangles
+
+in vec4 v_color;
+out vec4 color;
+
+void main() {
+    color = v_color;
+}
@@ -0,0 +1,26 @@
+#version 130
+
+#ifdef GL_ES
+precision mediump float;
+#else
+precision highp float;
+#endif
+
+// GLSL shader to draw untextured, colored triangles
+
+in vec2 position;
+in vec4 color;
+
+out vec4 v_color;
+
+uniform float window_width;
+uniform float window_height;
+uniform float z;
+
+void main() {
+    float x_pos = ((position[0] / window_width) * 2.0) - 1.0;
+    float y_pos = ((position[1] / window_height) * 2.0) - 1.0;
+
+    v_color = color;
+    gl_Position = vec4(x_pos, y_pos, z, 1.0);
+}
@@ -0,0 +1,52 @@
+#version 130
+
+// GLSL shader to draw many sprites from one texture at once,
+// same as pixel_to_screen_space.frag.glsl and palette_swap.frag.glsl combined
+
+in vec2 v_tex_coords;
+// multiplied with the texture color, the alpha contains the transparency
+in vec4 v_tint;
+// mixed into the texture color, alpha is the strength of the flash
+in vec4 v_flash;
+// which row of the palette replaces the colors of row 0, 0 = original colors
+in float v_palette_row;
+out vec4 color;
+
+uniform sampler2D tex;
+
+uniform sampler2D palette;
+// number of colors in each row of the palette, 0 if no sprite of the batch uses a palette
+uniform int palette_size;
+
+const int MAX_PALETTE_COLORS = 16;
+// texture colors further away than this from every palette color are kept
+const float MATCH_THRESHOLD = 0.15;
+
+void main() {
+    vec4 texel = texture(tex, v_tex_coords);
+    int palette_row = int(v_palette_row + 0.5);
+
+    if (palette_row > 0) {
+        int best_match = -1;
+        float best_distance = MATCH_THRESHOLD;
+        for (int i = 0; i < MAX_PALETTE_COLORS; i++) {
+            if (i >= palette_size) {
+                break;
+            }
+            float d = distance(texelFetch(palette, ivec2(i, 0), 0).rgb, texel.rgb);
+            if (d < best_distance) {
+                best_distance = d;
+                best_match = i;
+            }
+        }
+
+        if (best_match >= 0) {
+            texel.rgb = texelFetch(palette, ivec2(best_match, palette_row), 0).rgb;
+        }
+    }
+
+    vec4 cur_color = texel * v_tint;
+    cur_color.rgb = mix(cur_color.rgb, v_flash.rgb, v_flash.a);
+
+    color = cur_color;
+}
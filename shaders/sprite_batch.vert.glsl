@@ -0,0 +1,35 @@
+#version 130
+
+#ifdef GL_ES
+precision mediump float;
+#else
+precision highp float;
+#endif
+
+// GLSL shader to draw many sprites from one texture at once,
+// the parameters of each sprite are stored in its vertices
+
+in vec3 position;
+in vec2 tex_coords;
+in vec4 tint;
+in vec4 flash;
+in float palette_row;
+
+out vec2 v_tex_coords;
+out vec4 v_tint;
+out vec4 v_flash;
+out float v_palette_row;
+
+uniform float window_width;
+uniform float window_height;
+
+void main() {
+    float x_pos = ((position[0] / window_width) * 2.0) - 1.0;
+    float y_pos = ((position[1] / window_height) * 2.0) - 1.0;
+
+    v_tex_coords = tex_coords;
+    v_tint = tint;
+    v_flash = flash;
+    v_palette_row = palette_row;
+    gl_Position = vec4(x_pos, y_pos, position[2], 1.0);
+}
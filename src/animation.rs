@@ -0,0 +1,206 @@
+//! Sprite animations
+//!
+//! An `AnimationClip` is a named sequence of regions on a sprite sheet, each
+//! shown for a certain duration. An `Animator` plays one clip for one entity.
+
+use texture::{SourcePixelRegion, SourceTextureRegion, TextureId};
+
+/// What happens when the last frame of a clip is reached
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoopMode {
+    /// Stop at the last frame
+    Once,
+    /// Start again at the first frame
+    Loop,
+    /// Play the clip backwards, then forwards again
+    PingPong,
+}
+
+/// One frame of an animation: which region of the sprite sheet, and for how long
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AnimationFrame {
+    pub region: SourcePixelRegion,
+    pub duration_ms: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AnimationClip {
+    /// Name of the clip, used to check if a clip is already playing
+    pub name: &'static str,
+    /// The sprite sheet
+    pub texture_id: TextureId,
+    pub frames: &'static [AnimationFrame],
+    pub loop_mode: LoopMode,
+}
+
+impl AnimationClip {
+
+    /// Duration of one pass through all frames, in milliseconds
+    pub fn total_duration_ms(&self) -> u32 {
+        self.frames.iter().map(|f| f.duration_ms).sum()
+    }
+
+    /// Returns the index of the frame that should be shown after `elapsed_ms`
+    pub fn frame_index_at(&self, elapsed_ms: u32) -> usize {
+        let total = self.total_duration_ms();
+        if total == 0 || self.frames.len() < 2 {
+            return 0;
+        }
+
+        let (time_in_pass, backwards) = match self.loop_mode {
+            LoopMode::Once => (elapsed_ms.min(total - 1), false),
+            LoopMode::Loop => (elapsed_ms % total, false),
+            LoopMode::PingPong => {
+                let pass = elapsed_ms / total;
+                (elapsed_ms % total, pass % 2 == 1)
+            },
+        };
+
+        let mut remaining = time_in_pass;
+        let mut index = self.frames.len() - 1;
+        for (idx, frame) in self.frames.iter().enumerate() {
+            if remaining < frame.duration_ms {
+                index = idx;
+                break;
+            }
+            remaining -= frame.duration_ms;
+        }
+
+        if backwards { self.frames.len() - 1 - index } else { index }
+    }
+}
+
+/// Plays an animation clip for one entity
+#[derive(Debug, Copy, Clone)]
+pub struct Animator {
+    pub clip: AnimationClip,
+    /// Time since the clip was started, in milliseconds
+    pub elapsed_ms: f32,
+    /// Playback speed, 1.0 is normal speed
+    pub speed: f32,
+}
+
+impl Animator {
+
+    pub fn new(clip: AnimationClip) -> Self {
+        Self {
+            clip: clip,
+            elapsed_ms: 0.0,
+            speed: 1.0,
+        }
+    }
+
+    /// Switches to a different clip. Does nothing if the clip is already playing,
+    /// so this can be called on every frame
+    pub fn play(&mut self, clip: AnimationClip) {
+        if self.clip.name != clip.name {
+            self.clip = clip;
+            self.elapsed_ms = 0.0;
+        }
+    }
+
+    /// Advances the animation by `time_diff_secs` seconds
+    pub fn update(&mut self, time_diff_secs: f32) {
+        self.elapsed_ms += time_diff_secs * 1000.0 * self.speed;
+    }
+
+    /// Returns if a non-looping clip has reached its last frame
+    pub fn is_finished(&self) -> bool {
+        self.clip.loop_mode == LoopMode::Once && self.elapsed_ms as u32 >= self.clip.total_duration_ms()
+    }
+
+    /// The region of the sprite sheet that should currently be drawn
+    pub fn current_frame(&self) -> SourceTextureRegion {
+        let index = self.clip.frame_index_at(self.elapsed_ms as u32);
+        SourceTextureRegion {
+            texture_id: self.clip.texture_id,
+            region: self.clip.frames[index].region,
+        }
+    }
+}
+
+/// Which way a sprite is looking. Sprite sheets are drawn facing right
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Facing {
+    Left,
+    Right,
+}
+
+/// The state of the player, for selecting an animation clip
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayerAnimationState {
+    Idle,
+    Walk,
+    Jump,
+    Fall,
+    Carry,
+}
+
+impl PlayerAnimationState {
+    pub fn clip(&self) -> AnimationClip {
+        use assets::*;
+        match *self {
+            PlayerAnimationState::Idle => HERO_CLIP_IDLE,
+            PlayerAnimationState::Walk => HERO_CLIP_WALK,
+            PlayerAnimationState::Jump => HERO_CLIP_JUMP,
+            PlayerAnimationState::Fall => HERO_CLIP_FALL,
+            PlayerAnimationState::Carry => HERO_CLIP_CARRY,
+        }
+    }
+}
+
+/// State machine that picks the animation of the player character
+#[derive(Debug, Copy, Clone)]
+pub struct PlayerAnimator {
+    pub state: PlayerAnimationState,
+    pub facing: Facing,
+    pub animator: Animator,
+}
+
+impl Default for PlayerAnimator {
+    fn default() -> Self {
+        Self {
+            state: PlayerAnimationState::Idle,
+            facing: Facing::Right,
+            animator: Animator::new(PlayerAnimationState::Idle.clip()),
+        }
+    }
+}
+
+impl PlayerAnimator {
+
+    /// Selects the clip for this frame from how far the player moved, then advances the animation
+    pub fn update(&mut self, x_diff: f32, y_diff: f32, on_ground: bool, carrying_crate: bool, time_diff_secs: f32) {
+
+        if x_diff < 0.0 {
+            self.facing = Facing::Left;
+        } else if x_diff > 0.0 {
+            self.facing = Facing::Right;
+        }
+
+        self.state = if y_diff > 0.0 {
+            PlayerAnimationState::Jump
+        } else if !on_ground || y_diff < 0.0 {
+            PlayerAnimationState::Fall
+        } else if carrying_crate {
+            PlayerAnimationState::Carry
+        } else if x_diff != 0.0 {
+            PlayerAnimationState::Walk
+        } else {
+            PlayerAnimationState::Idle
+        };
+
+        self.animator.play(self.state.clip());
+        self.animator.update(time_diff_secs);
+    }
+
+    /// The current region of the hero sprite sheet
+    pub fn current_frame(&self) -> SourceTextureRegion {
+        self.animator.current_frame()
+    }
+
+    /// Returns if the sprite should be mirrored when drawing
+    pub fn is_flipped(&self) -> bool {
+        self.facing == Facing::Left
+    }
+}
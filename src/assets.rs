@@ -1,6 +1,10 @@
 //! Constants for easier access to the assets
 
-use texture::{SourcePixelRegion, TextureId, SourceTextureRegion, TextureInstanceId};
+use texture::{SourcePixelRegion, TextureId, SourceTextureRegion, TextureInstanceId, NineSliceRegion, NineSliceInsets, Palette};
+use animation::{AnimationClip, AnimationFrame, LoopMode};
+use lighting::{LevelLighting, LevelLight, LightAnchor};
+use level::Level;
+use color::Color;
 
 // fonts
 
@@ -16,15 +20,12 @@ pub const GAME_TITLE: &str = "StackBoxes!";
 // audio
 
 pub const TITLE_SCREEN_SONG_DATA: &[u8] = include_bytes!("../assets/sounds/music/title_screen.ogg");
-pub const AUDIO_MSG_PLAY_TITLE_SCREEN_SONG: &'static str = "../assets/sounds/music/title_screen.ogg";
 
 pub const GAME_SONG_1_DATA: &[u8] = include_bytes!("../assets/sounds/music/level_1.ogg");
-// pub const GAME_SONG_2_DATA: &[u8] = include_bytes!("../assets/sounds/music/level_2.ogg");
-// pub const GAME_SONG_3_DATA: &[u8] = include_bytes!("../assets/sounds/music/level_3.ogg");
-pub const AUDIO_MSG_PLAY_GAME_SONG: &'static str = "../assets/sounds/music/level_1.ogg";
+pub const GAME_SONG_2_DATA: &[u8] = include_bytes!("../assets/sounds/music/level_2.ogg");
+pub const GAME_SONG_3_DATA: &[u8] = include_bytes!("../assets/sounds/music/level_3.ogg");
 
 pub const ENDING_SONG_1_DATA: &[u8] = include_bytes!("../assets/sounds/music/ending.ogg");
-pub const AUDIO_MSG_PLAY_ENDING_SONG: &'static str = "../assets/sounds/music/ending.ogg";
 
 
 // -- textures
@@ -41,11 +42,20 @@ pub const START_SCREEN_BUTTON_00_TX_STR: SourceTextureRegion = SourceTextureRegi
         height: 49,
     }
 };
+// the rounded corners and the shadow at the bottom must not be stretched
+pub const START_SCREEN_BUTTON_00_NINE_SLICE: NineSliceRegion = NineSliceRegion {
+    source_texture_region: START_SCREEN_BUTTON_00_TX_STR,
+    insets: NineSliceInsets {
+        left: 10,
+        right: 10,
+        top: 10,
+        bottom: 14,
+    }
+};
 
 // hero texture
 pub const HERO_TEXTURE_ID: &str = "../assets/images/hero.png";
 pub const HERO_TEXTURE: &[u8] = include_bytes!("../assets/images/hero.png");
-/* todo: add source pixel regions*/
 pub const HERO_TX_NORMAL_STR: SourceTextureRegion = SourceTextureRegion {
     texture_id: TextureId { texture_id: HERO_TEXTURE_ID },
     region: SourcePixelRegion {
@@ -56,6 +66,91 @@ pub const HERO_TX_NORMAL_STR: SourceTextureRegion = SourceTextureRegion {
     }
 };
 
+// hero skins: the blue clothes (light and dark) and the yellow hair
+pub static HERO_PALETTE: Palette = Palette {
+    name: "hero_palette",
+    source_colors: &[
+        Color { r: 20, g: 159, b: 255, a: 255 },
+        Color { r: 0, g: 68, b: 252, a: 255 },
+        Color { r: 255, g: 206, b: 21, a: 255 },
+    ],
+    variants: &[
+        // red
+        &[
+            Color { r: 235, g: 60, b: 60, a: 255 },
+            Color { r: 150, g: 20, b: 30, a: 255 },
+            Color { r: 255, g: 206, b: 21, a: 255 },
+        ],
+        // green
+        &[
+            Color { r: 80, g: 200, b: 90, a: 255 },
+            Color { r: 20, g: 110, b: 40, a: 255 },
+            Color { r: 255, g: 120, b: 40, a: 255 },
+        ],
+        // purple
+        &[
+            Color { r: 180, g: 100, b: 240, a: 255 },
+            Color { r: 90, g: 30, b: 160, a: 255 },
+            Color { r: 255, g: 240, b: 120, a: 255 },
+        ],
+    ],
+};
+
+// hero animations, the sprite sheet is 6 x 5 frames of 16x16 pixels, facing right
+pub const HERO_CLIP_IDLE: AnimationClip = AnimationClip {
+    name: "hero_idle",
+    texture_id: TextureId { texture_id: HERO_TEXTURE_ID },
+    frames: &[
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 0, bottom_y: 64, width: 16, height: 16 }, duration_ms: 400 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 16, bottom_y: 64, width: 16, height: 16 }, duration_ms: 200 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 32, bottom_y: 64, width: 16, height: 16 }, duration_ms: 400 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 48, bottom_y: 64, width: 16, height: 16 }, duration_ms: 200 },
+    ],
+    loop_mode: LoopMode::Loop,
+};
+
+pub const HERO_CLIP_WALK: AnimationClip = AnimationClip {
+    name: "hero_walk",
+    texture_id: TextureId { texture_id: HERO_TEXTURE_ID },
+    frames: &[
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 0, bottom_y: 48, width: 16, height: 16 }, duration_ms: 90 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 16, bottom_y: 48, width: 16, height: 16 }, duration_ms: 90 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 32, bottom_y: 48, width: 16, height: 16 }, duration_ms: 90 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 48, bottom_y: 48, width: 16, height: 16 }, duration_ms: 90 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 64, bottom_y: 48, width: 16, height: 16 }, duration_ms: 90 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 80, bottom_y: 48, width: 16, height: 16 }, duration_ms: 90 },
+    ],
+    loop_mode: LoopMode::Loop,
+};
+
+pub const HERO_CLIP_JUMP: AnimationClip = AnimationClip {
+    name: "hero_jump",
+    texture_id: TextureId { texture_id: HERO_TEXTURE_ID },
+    frames: &[
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 0, bottom_y: 32, width: 16, height: 16 }, duration_ms: 150 },
+    ],
+    loop_mode: LoopMode::Once,
+};
+
+pub const HERO_CLIP_FALL: AnimationClip = AnimationClip {
+    name: "hero_fall",
+    texture_id: TextureId { texture_id: HERO_TEXTURE_ID },
+    frames: &[
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 32, bottom_y: 32, width: 16, height: 16 }, duration_ms: 150 },
+    ],
+    loop_mode: LoopMode::Once,
+};
+
+pub const HERO_CLIP_CARRY: AnimationClip = AnimationClip {
+    name: "hero_carry",
+    texture_id: TextureId { texture_id: HERO_TEXTURE_ID },
+    frames: &[
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 16, bottom_y: 0, width: 16, height: 16 }, duration_ms: 250 },
+        AnimationFrame { region: SourcePixelRegion { bottom_x: 32, bottom_y: 0, width: 16, height: 16 }, duration_ms: 250 },
+    ],
+    loop_mode: LoopMode::PingPong,
+};
+
 // crate texture
 pub const CRATE_TEXTURE_ID: &str = "../assets/images/crate.png";
 pub const CRATE_TEXTURE_DATA: &[u8] = include_bytes!("../assets/images/crate.png");
@@ -69,6 +164,76 @@ pub const CRATE_TEXTURE_TX_STR: SourceTextureRegion = SourceTextureRegion {
     }
 };
 
+// crate color variants: wood shades from light to dark, then the nails
+pub static CRATE_PALETTE: Palette = Palette {
+    name: "crate_palette",
+    source_colors: &[
+        Color { r: 185, g: 122, b: 87, a: 255 },
+        Color { r: 157, g: 98, b: 66, a: 255 },
+        Color { r: 118, g: 73, b: 50, a: 255 },
+        Color { r: 92, g: 58, b: 39, a: 255 },
+        Color { r: 44, g: 27, b: 18, a: 255 },
+        Color { r: 29, g: 18, b: 12, a: 255 },
+        Color { r: 147, g: 58, b: 0, a: 255 },
+    ],
+    variants: &[
+        // painted red
+        &[
+            Color { r: 225, g: 100, b: 85, a: 255 },
+            Color { r: 200, g: 70, b: 60, a: 255 },
+            Color { r: 160, g: 50, b: 45, a: 255 },
+            Color { r: 125, g: 38, b: 34, a: 255 },
+            Color { r: 60, g: 18, b: 16, a: 255 },
+            Color { r: 40, g: 12, b: 11, a: 255 },
+            Color { r: 190, g: 40, b: 0, a: 255 },
+        ],
+        // painted green
+        &[
+            Color { r: 120, g: 175, b: 95, a: 255 },
+            Color { r: 95, g: 145, b: 75, a: 255 },
+            Color { r: 70, g: 110, b: 55, a: 255 },
+            Color { r: 55, g: 88, b: 43, a: 255 },
+            Color { r: 26, g: 42, b: 20, a: 255 },
+            Color { r: 17, g: 28, b: 13, a: 255 },
+            Color { r: 60, g: 120, b: 0, a: 255 },
+        ],
+        // steel
+        &[
+            Color { r: 140, g: 160, b: 190, a: 255 },
+            Color { r: 115, g: 135, b: 165, a: 255 },
+            Color { r: 85, g: 100, b: 125, a: 255 },
+            Color { r: 66, g: 78, b: 98, a: 255 },
+            Color { r: 32, g: 37, b: 47, a: 255 },
+            Color { r: 21, g: 25, b: 31, a: 255 },
+            Color { r: 40, g: 80, b: 160, a: 255 },
+        ],
+    ],
+};
+
+// piece of the crate texture, for splinter particles
+pub const CRATE_SPLINTER_TX_STR: SourceTextureRegion = SourceTextureRegion {
+    texture_id: TextureId { texture_id: CRATE_TEXTURE_ID },
+    region: SourcePixelRegion {
+        bottom_x: 4,
+        bottom_y: 12,
+        width: 8,
+        height: 8,
+    }
+};
+
+// plain white texture, generated at startup, for tinted particles
+pub const WHITE_TEXTURE_ID: &str = "generated_white_texture";
+pub const WHITE_TEXTURE_SIZE: u32 = 4;
+pub const WHITE_TEXTURE_TX_STR: SourceTextureRegion = SourceTextureRegion {
+    texture_id: TextureId { texture_id: WHITE_TEXTURE_ID },
+    region: SourcePixelRegion {
+        bottom_x: 0,
+        bottom_y: 0,
+        width: WHITE_TEXTURE_SIZE,
+        height: WHITE_TEXTURE_SIZE,
+    }
+};
+
 // background texture
 pub const BACKGROUND_3_TEXTURE_ID: &str = "../assets/images/background/3.png";
 pub const BACKGROUND_3_TEXTURE_DATA: &[u8] = include_bytes!("../assets/images/background/3.png");
@@ -81,3 +246,43 @@ pub const BACKGROUND_3_TEXTURE_TX_STR: SourceTextureRegion = SourceTextureRegion
         height: 182,
     }
 };
+
+// -- levels
+
+pub const LEVEL_DAY: Level = Level {
+    name: "day",
+    lighting: None,
+};
+
+// the hero carries a lantern, two street lamps light up the ground
+pub const LEVEL_NIGHT_LIGHTS: &[LevelLight] = &[
+    LevelLight {
+        anchor: LightAnchor::Player { offset_x: 30.0, offset_y: 0.0 },
+        radius: 260.0,
+        color: Color { r: 255, g: 210, b: 140, a: 255 },
+        intensity: 1.2,
+    },
+    LevelLight {
+        anchor: LightAnchor::World { x: 120.0, y: 180.0 },
+        radius: 320.0,
+        color: Color { r: 170, g: 190, b: 255, a: 255 },
+        intensity: 0.8,
+    },
+    LevelLight {
+        anchor: LightAnchor::World { x: 680.0, y: 180.0 },
+        radius: 320.0,
+        color: Color { r: 170, g: 190, b: 255, a: 255 },
+        intensity: 0.8,
+    },
+];
+
+pub const LEVEL_NIGHT: Level = Level {
+    name: "night",
+    lighting: Some(LevelLighting {
+        ambient: Color { r: 35, g: 40, b: 70, a: 255 },
+        lights: LEVEL_NIGHT_LIGHTS,
+    }),
+};
+
+/// All level variants, F9 switches to the next one
+pub const LEVELS: &[Level] = &[LEVEL_DAY, LEVEL_NIGHT];
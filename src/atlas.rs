@@ -0,0 +1,150 @@
+//! Rectangle packing for texture atlases
+//!
+//! Images are placed on horizontal "shelves": each shelf is as high as the first
+//! image placed on it, new images go to the right of the previous one. Works well
+//! for sprites and glyphs, which have similar heights. Sprites on the same page
+//! can be drawn in one draw call, see `TextureSystem::draw_texture_batch`.
+
+use image::{GenericImage, RgbaImage};
+
+/// Size of one atlas page in pixels (width and height)
+pub const ATLAS_PAGE_SIZE: u32 = 1024;
+
+/// Empty pixels between two images, so that linear filtering doesn't bleed into the neighbour
+pub const ATLAS_PADDING: u32 = 2;
+
+/// IDs of the atlas pages in the `TextureSystem`
+pub const ATLAS_PAGE_IDS: &[&str] = &[
+    "texture_atlas_0",
+    "texture_atlas_1",
+    "texture_atlas_2",
+    "texture_atlas_3",
+];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Shelf {
+    /// Top of the shelf, counted from the top of the atlas
+    y: u32,
+    height: u32,
+    /// Where the next image on this shelf starts
+    x_cursor: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShelfPacker {
+    pub width: u32,
+    pub height: u32,
+    pub padding: u32,
+    shelves: Vec<Shelf>,
+}
+
+impl ShelfPacker {
+
+    pub fn new(width: u32, height: u32, padding: u32) -> Self {
+        Self {
+            width: width,
+            height: height,
+            padding: padding,
+            shelves: Vec::new(),
+        }
+    }
+
+    /// Reserves space for a rectangle, returns the top left corner (from the top of the atlas)
+    /// or None if the rectangle doesn't fit anymore
+    pub fn pack(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let padded_width = width + self.padding;
+        let padded_height = height + self.padding;
+
+        if padded_width > self.width || padded_height > self.height {
+            return None;
+        }
+
+        // use the lowest existing shelf that is high enough, to waste as little space as possible
+        let mut best_shelf: Option<usize> = None;
+        for (idx, shelf) in self.shelves.iter().enumerate() {
+            let fits = shelf.height >= padded_height && shelf.x_cursor + padded_width <= self.width;
+            let is_better = best_shelf.map(|b| shelf.height < self.shelves[b].height).unwrap_or(true);
+            if fits && is_better {
+                best_shelf = Some(idx);
+            }
+        }
+
+        if let Some(idx) = best_shelf {
+            let shelf = &mut self.shelves[idx];
+            let position = (shelf.x_cursor, shelf.y);
+            shelf.x_cursor += padded_width;
+            return Some(position);
+        }
+
+        // open a new shelf below the last one
+        let next_y = self.shelves.last().map(|s| s.y + s.height).unwrap_or(0);
+        if next_y + padded_height > self.height {
+            return None;
+        }
+
+        self.shelves.push(Shelf {
+            y: next_y,
+            height: padded_height,
+            x_cursor: padded_width,
+        });
+
+        Some((0, next_y))
+    }
+
+    /// Removes all rectangles
+    pub fn clear(&mut self) {
+        self.shelves.clear();
+    }
+}
+
+/// Where an image was placed in an atlas
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AtlasPlacement {
+    /// Index into `ATLAS_PAGE_IDS`
+    pub page: usize,
+    /// Top left corner, from the top of the atlas page
+    pub x: u32,
+    pub y: u32,
+}
+
+/// Packs all images into as few pages as possible. Returns the pages and where each image ended up
+/// (in the same order as the input). Images that are too big for a page get None
+pub fn pack_images(images: &[&RgbaImage]) -> (Vec<RgbaImage>, Vec<Option<AtlasPlacement>>) {
+
+    // pack the highest images first, so the shelves are filled evenly
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    order.sort_by(|a, b| images[*b].height().cmp(&images[*a].height()));
+
+    let mut packers = Vec::<ShelfPacker>::new();
+    let mut pages = Vec::<RgbaImage>::new();
+    let mut placements = vec![None; images.len()];
+
+    for idx in order {
+        let image = images[idx];
+        let (w, h) = image.dimensions();
+
+        let mut placement = None;
+        for (page, packer) in packers.iter_mut().enumerate() {
+            if let Some((x, y)) = packer.pack(w, h) {
+                placement = Some(AtlasPlacement { page: page, x: x, y: y });
+                break;
+            }
+        }
+
+        let fits_on_page = w + ATLAS_PADDING <= ATLAS_PAGE_SIZE && h + ATLAS_PADDING <= ATLAS_PAGE_SIZE;
+        if placement.is_none() && fits_on_page && packers.len() < ATLAS_PAGE_IDS.len() {
+            let mut packer = ShelfPacker::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE, ATLAS_PADDING);
+            placement = packer.pack(w, h).map(|(x, y)| AtlasPlacement { page: packers.len(), x: x, y: y });
+            packers.push(packer);
+            pages.push(RgbaImage::new(ATLAS_PAGE_SIZE, ATLAS_PAGE_SIZE));
+        }
+
+        if let Some(placement) = placement {
+            pages[placement.page].copy_from(image, placement.x, placement.y);
+        }
+
+        placements[idx] = placement;
+    }
+
+    (pages, placements)
+}
@@ -1,135 +1,354 @@
 //! The AudioContext is a channeled receiver than runs on a different thread.
 //!
-//! This makes the music independent from the game loop
+//! This makes the music independent from the game loop. All sounds are mixed
+//! by the `Mixer` and played through one output stream.
 
 use std::thread;
-use std::sync::mpsc;
-use cpal;
+use std::cell::Cell;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+use std::io::Cursor;
+use std::collections::VecDeque;
 use lewton::inside_ogg::OggStreamReader;
+use lewton::VorbisError;
+use lewton::audio::AudioReadError;
+use mixer::{Mixer, Voice, SampleSource, PcmSource, MusicTempo, Listener};
+use audio_backend::AudioOutput;
+use FastHashMap;
+
+/// The songs of the game
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SongId {
+    TitleScreen,
+    Level1,
+    Level2,
+    Level3,
+    Ending,
+}
+
+impl SongId {
+    /// The encoded OGG file of the song
+    pub fn data(&self) -> &'static [u8] {
+        match *self {
+            SongId::TitleScreen => ::assets::TITLE_SCREEN_SONG_DATA,
+            SongId::Level1 => ::assets::GAME_SONG_1_DATA,
+            SongId::Level2 => ::assets::GAME_SONG_2_DATA,
+            SongId::Level3 => ::assets::GAME_SONG_3_DATA,
+            SongId::Ending => ::assets::ENDING_SONG_1_DATA,
+        }
+    }
+
+    /// Tempo of the track, songs are switched on its bar boundaries.
+    ///
+    /// The tracks don't state their tempo. They are "loop ready", so each one is a whole
+    /// number of 4/4 bars long, the tempo is calculated from the length of the loop
+    /// (granule position of the last OGG page / 44100 Hz, all of them match to one sample):
+    ///
+    /// - title_screen.ogg: 498071 samples = 4 bars at 85 BPM
+    /// - level_1.ogg: 3274608 samples = 28 bars at 90.5 BPM
+    /// - level_2.ogg: 3210313 samples = 32 bars at 105.5 BPM
+    /// - level_3.ogg: 3611603 samples = 36 bars at 105.5 BPM
+    /// - ending.ogg: 1969117 samples = 16 bars at 86 BPM
+    pub fn tempo(&self) -> MusicTempo {
+        let beats_per_minute = match *self {
+            SongId::TitleScreen => 85.0,
+            SongId::Level1 => 90.5,
+            SongId::Level2 => 105.5,
+            SongId::Level3 => 105.5,
+            SongId::Ending => 86.0,
+        };
+        MusicTempo { beats_per_minute: beats_per_minute, beats_per_bar: 4 }
+    }
+
+    /// If the song is one of the in-game songs that are switched depending on the tower height
+    pub fn is_level_song(&self) -> bool {
+        match *self {
+            SongId::Level1 | SongId::Level2 | SongId::Level3 => true,
+            SongId::TitleScreen | SongId::Ending => false,
+        }
+    }
+
+    /// Voice that streams the song in a loop
+    pub fn voice(&self) -> Voice {
+        Voice::new(Box::new(OggStreamSource::new(self.data())), true)
+    }
+}
+
+/// The sound effects of the game
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum SfxId {
+    Jump,
+    Pickup,
+    Drop,
+    CrateLanding,
+    Collapse,
+    NewHighscore,
+}
+
+/// Identifies a playing sound effect, for moving it while it plays
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SoundHandle(pub u32);
+
+/// Messages from the game to the audio thread
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AudioCommand {
+    /// Starts looping a song. Does nothing if the song is already playing
+    PlayMusic(SongId),
+    StopMusic,
+    /// Plays a sound effect once, on top of the music.
+    /// `volume`: 0.0 - 1.0, `pan`: -1.0 (left) - 1.0 (right), `pitch`: playback speed, 1.0 = original
+    PlaySfx { id: SfxId, volume: f32, pan: f32, pitch: f32 },
+    /// Plays a sound effect at a position in the world, pan and volume depend on the listener.
+    /// With a handle, the sound can be moved with `MoveSfx`
+    PlaySfxAt { id: SfxId, volume: f32, pitch: f32, x: f32, y: f32, handle: Option<SoundHandle> },
+    /// Moves a playing sound effect
+    MoveSfx { handle: SoundHandle, x: f32, y: f32 },
+    /// Where the positioned sounds are heard from, usually the center of the camera
+    SetListener(Listener),
+    /// Sets the volume of all sounds, the songs and the sound effects, 0.0 - 1.0 each
+    SetVolume { master: f32, music: f32, sfx: f32 },
+    /// Silences the output, the sounds continue where they stopped on `Resume`
+    Pause,
+    Resume,
+    /// Crossfades from the current song to another one over the given time
+    FadeTo(SongId, Duration),
+    /// Fades the current song out over the given time, then starts the song
+    FadeOutAndPlay(SongId, Duration),
+    /// Crossfades to the song at the next bar of the current song
+    SwitchAtBar(SongId, Duration),
+    /// How tense the game is, 0.0 - 1.0. The music plays faster the more tense it is
+    SetMusicTension(f32),
+}
+
+/// How much faster the music plays at full tension
+pub const MAX_TENSION_SPEEDUP: f32 = 0.08;
+
+/// How long the music fades out when the game is lost, before the ending song starts
+pub const GAME_OVER_FADE_OUT_MS: u64 = 2500;
 
 pub struct AudioContext {
-    sender: mpsc::Sender<&'static str>,
+    sender: mpsc::Sender<AudioCommand>,
+    /// Next free id for `SoundHandle`
+    next_sound_handle: Cell<u32>,
     thread_handle: thread::JoinHandle<()>,
 }
 
 impl AudioContext {
 
-    /// Starts a thread, returns the context
+    /// Starts a thread, returns the context. The audio goes to the sound device
+    /// or to a WAV file, see `AudioOutput::from_env`
     pub fn new() -> Self {
+        Self::with_output(AudioOutput::from_env())
+    }
 
-        let (tx, rx) = mpsc::channel();
+    pub fn with_output(output: AudioOutput) -> Self {
 
-        let thread_handle = thread::spawn(move || Self::async_music_loop(rx));
+        let (tx, rx) = mpsc::channel();
+        let thread_handle = thread::spawn(move || Self::async_music_loop(rx, output));
 
         Self {
             sender: tx,
+            next_sound_handle: Cell::new(0),
             thread_handle: thread_handle,
         }
     }
 
-    pub fn send_msg(&self, msg: &'static str) -> Result<(), mpsc::SendError<&'static str>> {
+    /// Creates a new id for a sound that should be moved while playing
+    pub fn new_sound_handle(&self) -> SoundHandle {
+        let handle = self.next_sound_handle.get();
+        self.next_sound_handle.set(handle.wrapping_add(1));
+        SoundHandle(handle)
+    }
+
+    pub fn send_msg(&self, msg: AudioCommand) -> Result<(), mpsc::SendError<AudioCommand>> {
         self.sender.send(msg)
     }
 
-    fn async_music_loop(rx: mpsc::Receiver<&'static str>) {
-
-        use cpal::{UnknownTypeBuffer, Sample};
-        use std::thread;
-        use std::sync::Arc;
-        use std::time::{Instant, Duration};
-
-        let songs = [::assets::TITLE_SCREEN_SONG_DATA, ::assets::GAME_SONG_1_DATA, ::assets::ENDING_SONG_1_DATA];
-        
-        // decode all the songs
-        let decoded_songs = songs.into_iter().enumerate().map(|(song_id, song_data)| {
-            let time_start = Instant::now();
-            let current_song = Song::decode_from_bytes(song_data);
-            let duration_decoding = (Instant::now() - time_start).subsec_nanos() as f32 / 1_000_000.0;
-            println!("decoded song {:?} in {:?} ms", song_id, duration_decoding);
-            Arc::new(current_song)
-        }).collect::<Vec<Arc<Song>>>();
-
-        let title_screen_song = &decoded_songs[0];
-        let game_song_1 = &decoded_songs[1];
-        let ending_screen_song = &decoded_songs[2];
-
-        let mut last_song_tx: Option<mpsc::Sender<()>> = None;
-        let mut last_song_id = "";
-
-        while let Ok(event) = rx.recv() {
-
-            if event == last_song_id { continue; }
-            let mut current_song = title_screen_song.clone();
-
-            match event {
-                ::assets::AUDIO_MSG_PLAY_TITLE_SCREEN_SONG => { current_song = title_screen_song.clone(); },
-                ::assets::AUDIO_MSG_PLAY_GAME_SONG => { current_song = game_song_1.clone(); },
-                ::assets::AUDIO_MSG_PLAY_ENDING_SONG => { current_song = ending_screen_song.clone(); },
-                _ => { println!("received garbage on audio thread: {:?}", event); }
+    fn async_music_loop(rx: mpsc::Receiver<AudioCommand>, output: AudioOutput) {
+
+        // PCM data of the sound effects
+        let sound_effects = ::sfx::build_sound_bank().into_iter().collect::<FastHashMap<SfxId, PcmSource>>();
+
+        // one output stream for the whole game, the voices are converted to the format of the output
+        let shared_mixer = Arc::new(Mutex::new(Mixer::new(2, DEFAULT_OUTPUT_SAMPLE_RATE)));
+        let output_mixer = shared_mixer.clone();
+        thread::spawn(move || {
+            let mut backend = output.create_backend();
+            println!("playing audio on: {}", backend.name());
+            backend.run(output_mixer);
+        });
+
+        let mut current_song = None;
+
+        while let Ok(command) = rx.recv() {
+
+            // reading the OGG headers takes a while, so the voice of a new song is created
+            // before the mixer is locked. Otherwise the output would have to wait for it
+            let song_voice = match command {
+                AudioCommand::PlayMusic(song_id) |
+                AudioCommand::FadeTo(song_id, _) |
+                AudioCommand::FadeOutAndPlay(song_id, _) |
+                AudioCommand::SwitchAtBar(song_id, _) => {
+                    if current_song != Some(song_id) { Some(song_id.voice()) } else { None }
+                },
+                _ => None,
+            };
+
+            let mut mixer = match shared_mixer.lock() {
+                Ok(mixer) => mixer,
+                Err(_) => return,
+            };
+
+            match command {
+                AudioCommand::PlayMusic(song_id) => {
+                    if let Some(voice) = song_voice {
+                        mixer.set_music(Some(voice));
+                        current_song = Some(song_id);
+                    }
+                },
+                AudioCommand::FadeTo(song_id, duration) => {
+                    if let Some(voice) = song_voice {
+                        mixer.crossfade_music(Some(voice), duration);
+                        current_song = Some(song_id);
+                    }
+                },
+                AudioCommand::FadeOutAndPlay(song_id, fade_out) => {
+                    if let Some(voice) = song_voice {
+                        mixer.fade_out_then_play(voice, fade_out);
+                        current_song = Some(song_id);
+                    }
+                },
+                AudioCommand::SwitchAtBar(song_id, crossfade) => {
+                    if let Some(voice) = song_voice {
+                        let tempo = current_song.unwrap_or(song_id).tempo();
+                        mixer.switch_music_at_bar(voice, tempo, crossfade);
+                        current_song = Some(song_id);
+                    }
+                },
+                AudioCommand::SetMusicTension(tension) => {
+                    mixer.music_speed = 1.0 + tension.max(0.0).min(1.0) * MAX_TENSION_SPEEDUP;
+                },
+                AudioCommand::StopMusic => {
+                    mixer.set_music(None);
+                    current_song = None;
+                },
+                AudioCommand::PlaySfx { id, volume, pan, pitch } => {
+                    if let Some(voice) = sfx_voice(&sound_effects, id, volume, pitch) {
+                        mixer.play_sfx(voice.with_pan(pan));
+                    }
+                },
+                AudioCommand::PlaySfxAt { id, volume, pitch, x, y, handle } => {
+                    if let Some(voice) = sfx_voice(&sound_effects, id, volume, pitch) {
+                        mixer.play_sfx(voice.at_position(x, y, handle.map(|h| h.0)));
+                    }
+                },
+                AudioCommand::MoveSfx { handle, x, y } => {
+                    mixer.move_sfx(handle.0, x, y);
+                },
+                AudioCommand::SetListener(listener) => {
+                    mixer.listener = Some(listener);
+                },
+                AudioCommand::SetVolume { master, music, sfx } => {
+                    mixer.volume = master.max(0.0).min(1.0);
+                    mixer.music_volume = music.max(0.0).min(1.0);
+                    mixer.sfx_volume = sfx.max(0.0).min(1.0);
+                },
+                AudioCommand::Pause => {
+                    mixer.paused = true;
+                },
+                AudioCommand::Resume => {
+                    mixer.paused = false;
+                },
             }
+        }
+    }
+}
 
-            let csong = current_song.clone();
-            let (new_tx, new_rx) = mpsc::channel();
-
-            thread::spawn(move || {
-
-                let endpoint = cpal::default_endpoint().unwrap();
-                let event_loop = cpal::EventLoop::new();
-
-                let mut iter = (&*csong).decoded.iter().cycle();
-
-                let supported_formats_range = endpoint.supported_formats().unwrap().next().unwrap();
-                let mut format = supported_formats_range.with_max_samples_rate();
-                format.samples_rate = cpal::SamplesRate(current_song.sample_rate);
-                let voice_id = event_loop.build_voice(&endpoint, &format).unwrap();
-                event_loop.play(voice_id);
-
-                // event loop blocks
-                event_loop.run(move |_voice_id, mut buffer| {
+/// Voice for a sound effect from the bank
+fn sfx_voice(sound_effects: &FastHashMap<SfxId, PcmSource>, id: SfxId, volume: f32, pitch: f32) -> Option<Voice> {
+    sound_effects.get(&id).map(|sound| {
+        // playing the sound at a higher rate makes it higher and shorter
+        let mut sound = sound.clone();
+        sound.sample_rate = (sound.sample_rate as f32 * pitch.max(0.1)) as u32;
+        Voice::new(Box::new(sound), false).with_volume(volume)
+    })
+}
 
-                    // can only be i16
-                    if let UnknownTypeBuffer::I16(ref mut buffer) = buffer {
-                        'outer: for (idx, d) in buffer.iter_mut().enumerate() {
-                            *d = iter.next().map(|s| s.to_i16()).unwrap_or(0_i16);
-                        }
-                    }
+/// Sample rate of the output if the device supports it, the songs are recorded at this rate
+pub const DEFAULT_OUTPUT_SAMPLE_RATE: u32 = 44_100;
 
-                    if let Err(mpsc::TryRecvError::Disconnected) = new_rx.try_recv() {
-                        return None;
-                    }
+/// Decodes an OGG file packet by packet while it is playing
+pub struct OggStreamSource {
+    /// None if the file could not be read
+    reader: Option<OggStreamReader<Cursor<&'static [u8]>>>,
+    /// Decoded samples that haven't been played yet (the rest of the last packet)
+    ring_buffer: VecDeque<i16>,
+}
 
-                    Some(())
-                });
+impl OggStreamSource {
 
-            });
+    pub fn new(data: &'static [u8]) -> Self {
+        let reader = OggStreamReader::new(Cursor::new(data))
+            .map_err(|e| println!("could not read ogg stream: {:?}", e))
+            .ok();
 
-            // save the transmitting end in order to kill the song when we start a new song
-            last_song_id = event;
-            last_song_tx = Some(new_tx);
+        Self {
+            reader: reader,
+            ring_buffer: VecDeque::new(),
         }
     }
-}
 
-pub struct Song {
-    pub decoded: Vec<i16>,
-    pub sample_rate: u32,
+    /// Decodes packets until at least `sample_count` samples are buffered or the stream has ended
+    fn fill(&mut self, sample_count: usize) {
+        while self.ring_buffer.len() < sample_count {
+            let packet = match self.reader {
+                Some(ref mut srr) => srr.read_dec_packet_itl(),
+                None => return,
+            };
+            match packet {
+                Ok(Some(samples)) => self.ring_buffer.extend(samples),
+                // after seeking to the start, the header packets are read again
+                Err(VorbisError::BadAudio(AudioReadError::AudioIsHeader)) => { },
+                _ => return,
+            }
+        }
+    }
 }
 
-impl Song {
+impl SampleSource for OggStreamSource {
+    fn read(&mut self, buffer: &mut [i16]) -> usize {
+        self.fill(buffer.len());
+        let count = self.ring_buffer.len().min(buffer.len());
+        for (out, sample) in buffer.iter_mut().zip(self.ring_buffer.drain(..count)) {
+            *out = sample;
+        }
+        count
+    }
 
-    /// DO NOT TOUCH THIS DECODING FUNCTION IT IS MAGIC
-    pub fn decode_from_bytes(data: &[u8]) -> Self {
-        let mut srr = OggStreamReader::new(::std::io::Cursor::new(data)).unwrap();
-        let sample_rate = srr.ident_hdr.audio_channels as f32 * srr.ident_hdr.audio_sample_rate as f32;
+    fn rewind(&mut self) {
+        // seeking keeps the decoded headers, parsing them again would block the output
+        self.ring_buffer.clear();
+        let seek_failed = match self.reader {
+            Some(ref mut srr) => srr.seek_absgp_pg(0).is_err(),
+            None => false,
+        };
+        if seek_failed {
+            println!("could not rewind ogg stream");
+            self.reader = None;
+        }
+    }
 
-        let mut decoded = Vec::<i16>::new();
-        while let Ok(Some(mut pck_samples)) = srr.read_dec_packet_itl() {
-            decoded.append(&mut pck_samples);
+    fn channels(&self) -> usize {
+        match self.reader {
+            Some(ref srr) => srr.ident_hdr.audio_channels as usize,
+            None => 2,
         }
+    }
 
-        Self {
-            decoded: decoded,
-            sample_rate: sample_rate as u32,
+    fn sample_rate(&self) -> u32 {
+        match self.reader {
+            Some(ref srr) => srr.ident_hdr.audio_sample_rate,
+            None => DEFAULT_OUTPUT_SAMPLE_RATE,
         }
     }
 }
@@ -0,0 +1,409 @@
+//! Outputs for the mixed audio
+//!
+//! The sound device is used if there is one. Without a device, the mixer is still
+//! running (silently), so that the game behaves the same. The WAV file output is for
+//! listening to the audio of headless runs, i.e. on CI.
+
+use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::fs::File;
+use std::io::{self, Write, Seek, SeekFrom, BufWriter};
+use std::time::{Duration, Instant};
+use cpal;
+use mixer::Mixer;
+use audio::DEFAULT_OUTPUT_SAMPLE_RATE;
+
+/// If this environment variable is set, the audio is written to the WAV file at that path
+pub const WAV_OUTPUT_ENV_VAR: &str = "STACKBOXES_WAV_OUTPUT";
+
+/// How often the null and the file backend pull samples from the mixer
+const PACED_OUTPUT_INTERVAL_MS: u64 = 10;
+
+/// Plays the output of the mixer somewhere
+pub trait AudioBackend {
+    /// Name for log messages
+    fn name(&self) -> &'static str;
+    /// Pulls samples from the mixer until nobody else holds the mixer anymore. Blocks
+    fn run(&mut self, mixer: Arc<Mutex<Mixer>>);
+}
+
+/// Which backend the audio should be played with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AudioOutput {
+    /// The default sound device, silent if there is none
+    Device,
+    /// Discard the audio
+    Null,
+    /// Render the audio to a WAV file
+    WavFile(PathBuf),
+}
+
+impl AudioOutput {
+    /// WAV file if `WAV_OUTPUT_ENV_VAR` is set, the sound device otherwise
+    pub fn from_env() -> Self {
+        match ::std::env::var_os(WAV_OUTPUT_ENV_VAR) {
+            Some(path) => AudioOutput::WavFile(PathBuf::from(path)),
+            None => AudioOutput::Device,
+        }
+    }
+
+    /// Creates the backend. Falls back to the null backend if the output can't be opened
+    pub fn create_backend(&self) -> Box<AudioBackend> {
+        let backend: Option<Box<AudioBackend>> = match *self {
+            AudioOutput::Device => CpalBackend::new().map(|b| -> Box<AudioBackend> { Box::new(b) }),
+            AudioOutput::Null => None,
+            AudioOutput::WavFile(ref path) => {
+                WavFileBackend::new(path.clone())
+                    .map_err(|e| println!("could not create {}: {}", path.display(), e))
+                    .ok()
+                    .map(|b| -> Box<AudioBackend> { Box::new(b) })
+            },
+        };
+
+        backend.unwrap_or_else(|| Box::new(NullBackend::new()))
+    }
+}
+
+/// Plays the audio on the default sound device
+pub struct CpalBackend {
+    endpoint: cpal::Endpoint,
+    format: cpal::Format,
+}
+
+impl CpalBackend {
+
+    /// None if there is no sound device or it has no usable format
+    pub fn new() -> Option<Self> {
+        let endpoint = match cpal::default_endpoint() {
+            Some(endpoint) => endpoint,
+            None => { println!("no audio device found, the game will be silent"); return None; },
+        };
+
+        choose_output_format(&endpoint).map(|format| Self {
+            endpoint: endpoint,
+            format: format,
+        })
+    }
+}
+
+impl AudioBackend for CpalBackend {
+
+    fn name(&self) -> &'static str {
+        "sound device"
+    }
+
+    fn run(&mut self, mixer: Arc<Mutex<Mixer>>) {
+
+        use cpal::UnknownTypeBuffer;
+
+        let event_loop = cpal::EventLoop::new();
+
+        let voice_id = match event_loop.build_voice(&self.endpoint, &self.format) {
+            Ok(voice_id) => voice_id,
+            Err(e) => {
+                println!("could not open the audio device: {:?}, the game will be silent", e);
+                NullBackend::new().run(mixer);
+                return;
+            }
+        };
+
+        if let Ok(mut mixer) = mixer.lock() {
+            let cpal::SamplesRate(sample_rate) = self.format.samples_rate;
+            mixer.channels = self.format.channels.len().max(1);
+            mixer.sample_rate = sample_rate;
+        }
+
+        event_loop.play(voice_id);
+
+        // event loop blocks
+        event_loop.run(move |_voice_id, mut buffer| {
+
+            let mut mixer = match mixer.lock() {
+                Ok(mixer) => mixer,
+                Err(_) => return Some(()),
+            };
+
+            match buffer {
+                UnknownTypeBuffer::U16(ref mut buffer) => mixer.mix_u16(&mut **buffer),
+                UnknownTypeBuffer::I16(ref mut buffer) => mixer.mix_i16(&mut **buffer),
+                UnknownTypeBuffer::F32(ref mut buffer) => mixer.mix_f32(&mut **buffer),
+            }
+
+            Some(())
+        });
+    }
+}
+
+/// Picks the stereo format of the device that is closest to `DEFAULT_OUTPUT_SAMPLE_RATE`.
+/// Other channel counts are used if the device has no stereo output
+fn choose_output_format(endpoint: &cpal::Endpoint) -> Option<cpal::Format> {
+    let formats = match endpoint.supported_formats() {
+        Ok(formats) => formats.collect::<Vec<_>>(),
+        Err(e) => { println!("could not query audio formats: {:?}", e); return None; },
+    };
+
+    let supports_rate = |format: &cpal::SupportedFormat| {
+        format.min_samples_rate.0 <= DEFAULT_OUTPUT_SAMPLE_RATE && format.max_samples_rate.0 >= DEFAULT_OUTPUT_SAMPLE_RATE
+    };
+
+    let best = formats.iter().max_by_key(|format| (format.channels.len() == 2, supports_rate(format)));
+
+    best.map(|format| {
+        let sample_rate = DEFAULT_OUTPUT_SAMPLE_RATE.max(format.min_samples_rate.0).min(format.max_samples_rate.0);
+        cpal::Format {
+            channels: format.channels.clone(),
+            samples_rate: cpal::SamplesRate(sample_rate),
+            data_type: format.data_type,
+        }
+    })
+}
+
+/// Mixes the audio in real time, but throws it away
+#[derive(Debug, Copy, Clone)]
+pub struct NullBackend {
+    pub channels: usize,
+    pub sample_rate: u32,
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        Self {
+            channels: 2,
+            sample_rate: DEFAULT_OUTPUT_SAMPLE_RATE,
+        }
+    }
+}
+
+impl AudioBackend for NullBackend {
+
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn run(&mut self, mixer: Arc<Mutex<Mixer>>) {
+        run_paced(&mixer, self.channels, self.sample_rate, |_samples| { });
+    }
+}
+
+/// Writes the mixed audio to a 16 bit PCM WAV file, in real time
+pub struct WavFileBackend {
+    pub path: PathBuf,
+    writer: WavWriter<BufWriter<File>>,
+}
+
+impl WavFileBackend {
+
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        let writer = WavWriter::new(BufWriter::new(File::create(&path)?), 2, DEFAULT_OUTPUT_SAMPLE_RATE)?;
+        Ok(Self {
+            path: path,
+            writer: writer,
+        })
+    }
+}
+
+impl AudioBackend for WavFileBackend {
+
+    fn name(&self) -> &'static str {
+        "WAV file"
+    }
+
+    fn run(&mut self, mixer: Arc<Mutex<Mixer>>) {
+        let (channels, sample_rate) = (self.writer.channels, self.writer.sample_rate);
+        let mut failed = false;
+        run_paced(&mixer, channels, sample_rate, |samples| {
+            if failed {
+                return;
+            }
+            if let Err(e) = self.writer.write_samples(samples).and_then(|_| self.writer.flush()) {
+                println!("could not write {}: {}", self.path.display(), e);
+                failed = true;
+            }
+        });
+    }
+}
+
+/// Writes 16 bit PCM samples in the WAV format. The header is updated after
+/// every block, so that the file is valid even if the game is killed
+pub struct WavWriter<W: Write + Seek> {
+    pub channels: usize,
+    pub sample_rate: u32,
+    writer: W,
+    /// Number of bytes in the data chunk
+    data_len: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+
+    pub fn new(writer: W, channels: usize, sample_rate: u32) -> io::Result<Self> {
+        let mut wav = Self {
+            channels: channels.max(1),
+            sample_rate: sample_rate,
+            writer: writer,
+            data_len: 0,
+        };
+        wav.write_header()?;
+        Ok(wav)
+    }
+
+    /// Writes the RIFF header
+    fn write_header(&mut self) -> io::Result<()> {
+        let channels = self.channels as u16;
+        let block_align = channels * 2;
+
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(b"RIFF")?;
+        write_u32(&mut self.writer, 36 + self.data_len)?;
+        self.writer.write_all(b"WAVEfmt ")?;
+        write_u32(&mut self.writer, 16)?;
+        write_u16(&mut self.writer, 1)?; // PCM
+        write_u16(&mut self.writer, channels)?;
+        write_u32(&mut self.writer, self.sample_rate)?;
+        write_u32(&mut self.writer, self.sample_rate * u32::from(block_align))?;
+        write_u16(&mut self.writer, block_align)?;
+        write_u16(&mut self.writer, 16)?;
+        self.writer.write_all(b"data")?;
+        write_u32(&mut self.writer, self.data_len)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    /// Appends interleaved samples
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            write_u16(&mut self.writer, *sample as u16)?;
+        }
+        self.data_len += samples.len() as u32 * 2;
+        self.write_header()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// How many frames `render_to_wav` mixes at once
+#[cfg(test)]
+const RENDER_BLOCK_FRAMES: usize = 1024;
+
+/// Mixes `frame_count` frames as fast as possible (without waiting for the
+/// time to pass) and writes them as a WAV file. The output only depends on the
+/// voices in the mixer, so it is the same on every run. Only for tests, the game
+/// writes WAV files in real time with the `WavFileBackend`
+#[cfg(test)]
+pub fn render_to_wav<W: Write + Seek>(mixer: &mut Mixer, frame_count: usize, writer: W) -> io::Result<W> {
+    let mut wav = WavWriter::new(writer, mixer.channels, mixer.sample_rate)?;
+    let mut buffer = Vec::<i16>::new();
+    let mut frames_left = frame_count;
+
+    while frames_left > 0 {
+        let frames = frames_left.min(RENDER_BLOCK_FRAMES);
+        buffer.resize(frames * mixer.channels, 0);
+        mixer.mix_i16(&mut buffer);
+        wav.write_samples(&buffer)?;
+        frames_left -= frames;
+    }
+
+    wav.flush()?;
+    Ok(wav.into_inner())
+}
+
+fn write_u16<W: Write>(writer: &mut W, value: u16) -> io::Result<()> {
+    writer.write_all(&[value as u8, (value >> 8) as u8])
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&[value as u8, (value >> 8) as u8, (value >> 16) as u8, (value >> 24) as u8])
+}
+
+/// Mixes as many samples as would have been played by a sound device since the start,
+/// until the audio thread has dropped the mixer
+fn run_paced<F>(mixer: &Arc<Mutex<Mixer>>, channels: usize, sample_rate: u32, mut output: F) where F: FnMut(&[i16]) {
+
+    if let Ok(mut mixer) = mixer.lock() {
+        mixer.channels = channels;
+        mixer.sample_rate = sample_rate;
+    }
+
+    let start = Instant::now();
+    let mut frames_played = 0_u64;
+    let mut buffer = Vec::<i16>::new();
+
+    while Arc::strong_count(mixer) > 1 {
+        ::std::thread::sleep(Duration::from_millis(PACED_OUTPUT_INTERVAL_MS));
+
+        let elapsed = start.elapsed();
+        let elapsed_frames = elapsed.as_secs() * u64::from(sample_rate)
+                           + u64::from(elapsed.subsec_nanos()) * u64::from(sample_rate) / 1_000_000_000;
+        let frame_count = (elapsed_frames - frames_played) as usize;
+        frames_played = elapsed_frames;
+
+        buffer.resize(frame_count * channels, 0);
+        match mixer.lock() {
+            Ok(mut mixer) => mixer.mix_i16(&mut buffer),
+            Err(_) => return,
+        }
+        output(&buffer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::io::Cursor;
+    use mixer::{PcmSource, Voice};
+
+    fn read_u16(bytes: &[u8], at: usize) -> u16 {
+        u16::from(bytes[at]) | u16::from(bytes[at + 1]) << 8
+    }
+
+    fn read_u32(bytes: &[u8], at: usize) -> u32 {
+        u32::from(read_u16(bytes, at)) | u32::from(read_u16(bytes, at + 2)) << 16
+    }
+
+    #[test]
+    fn wav_header_describes_the_samples() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::<u8>::new()), 2, 44100).unwrap();
+        wav.write_samples(&[1, -1, 300, -300]).unwrap();
+        let bytes = wav.into_inner().into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(read_u32(&bytes, 4), 36 + 8);
+        assert_eq!(&bytes[8..16], b"WAVEfmt ");
+        assert_eq!(read_u32(&bytes, 16), 16);
+        assert_eq!(read_u16(&bytes, 20), 1);
+        assert_eq!(read_u16(&bytes, 22), 2);
+        assert_eq!(read_u32(&bytes, 24), 44100);
+        assert_eq!(read_u32(&bytes, 28), 44100 * 4);
+        assert_eq!(read_u16(&bytes, 32), 4);
+        assert_eq!(read_u16(&bytes, 34), 16);
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(read_u32(&bytes, 40), 8);
+        assert_eq!(read_u16(&bytes, 44) as i16, 1);
+        assert_eq!(read_u16(&bytes, 46) as i16, -1);
+        assert_eq!(read_u16(&bytes, 48) as i16, 300);
+        assert_eq!(read_u16(&bytes, 50) as i16, -300);
+    }
+
+    #[test]
+    fn rendering_is_deterministic() {
+        let render = || {
+            let samples = Arc::new((0..300_i16).map(|i| i * 100).collect::<Vec<i16>>());
+            let mut mixer = Mixer::new(2, 100);
+            mixer.set_music(Some(Voice::new(Box::new(PcmSource::new(samples, 1, 50)), true)));
+            render_to_wav(&mut mixer, 2500, Cursor::new(Vec::<u8>::new())).unwrap().into_inner()
+        };
+
+        let first = render();
+        assert_eq!(first.len(), 44 + 2500 * 2 * 2);
+        assert_eq!(read_u32(&first, 40), 2500 * 2 * 2);
+        assert!(first[44..].iter().any(|b| *b != 0));
+        assert_eq!(first, render());
+    }
+}
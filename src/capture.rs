@@ -0,0 +1,137 @@
+//! Screenshots and GIF recordings of the game
+//!
+//! The `FrameRecorder` keeps the last few seconds of gameplay (downscaled,
+//! at a low frame rate) in memory, so that it can be exported as a GIF
+//! after something interesting happened.
+
+use image::{self, RgbaImage};
+use gif::{self, SetParameter};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Render target the window contents get copied into before reading them back
+pub const CAPTURE_TARGET_ID: &str = "render_target_capture";
+
+/// How many seconds of gameplay are kept for the GIF export
+pub const DEFAULT_RECORDING_SECONDS: u64 = 8;
+/// Frames per second of the GIF recording
+pub const DEFAULT_RECORDING_FPS: u64 = 10;
+/// The recorded frames are downscaled by this factor to save memory
+pub const DEFAULT_RECORDING_DOWNSCALE: u32 = 2;
+
+pub struct FrameRecorder {
+    /// Recorded frames, oldest first
+    pub frames: VecDeque<RgbaImage>,
+    /// Maximum number of frames to keep
+    pub max_frames: usize,
+    /// Time between two recorded frames
+    pub capture_interval: Duration,
+    pub downscale: u32,
+    pub last_capture: Option<Instant>,
+}
+
+impl Default for FrameRecorder {
+    fn default() -> Self {
+        Self {
+            frames: VecDeque::new(),
+            max_frames: (DEFAULT_RECORDING_SECONDS * DEFAULT_RECORDING_FPS) as usize,
+            capture_interval: Duration::from_millis(1000 / DEFAULT_RECORDING_FPS),
+            downscale: DEFAULT_RECORDING_DOWNSCALE,
+            last_capture: None,
+        }
+    }
+}
+
+impl FrameRecorder {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns if enough time has passed since the last recorded frame
+    pub fn wants_frame(&self, now: Instant) -> bool {
+        match self.last_capture {
+            Some(last) => now - last >= self.capture_interval,
+            None => true,
+        }
+    }
+
+    /// Adds a frame to the recording, drops the oldest frame if the buffer is full
+    pub fn push_frame(&mut self, image: &RgbaImage, now: Instant) {
+        let (w, h) = image.dimensions();
+        let scaled = image::imageops::resize(image, (w / self.downscale).max(1), (h / self.downscale).max(1),
+                                             image::FilterType::Nearest);
+
+        // all frames of a GIF must have the same size, restart the recording if the window was resized
+        if self.frames.back().map(|f| f.dimensions() != scaled.dimensions()).unwrap_or(false) {
+            self.frames.clear();
+        }
+
+        self.frames.push_back(scaled);
+        while self.frames.len() > self.max_frames {
+            self.frames.pop_front();
+        }
+
+        self.last_capture = Some(now);
+    }
+
+    /// Writes the recorded frames as a looping GIF. Quantizing the frames takes a while,
+    /// so the GIF is written on a worker thread, which prints the result when it is done
+    pub fn export_gif(&self, path: String) {
+        if self.frames.is_empty() {
+            println!("could not save recording: no frames recorded yet");
+            return;
+        }
+
+        let frames = self.frames.iter().cloned().collect::<Vec<RgbaImage>>();
+        let capture_interval = self.capture_interval;
+
+        thread::spawn(move || {
+            match write_gif(&frames, capture_interval, &path) {
+                Ok(()) => println!("saved recording to {}", path),
+                Err(e) => println!("could not save recording: {}", e),
+            }
+        });
+    }
+}
+
+/// Writes the frames as a looping GIF, all frames must have the same size
+fn write_gif(frames: &[RgbaImage], frame_time: Duration, path: &str) -> io::Result<()> {
+    let (w, h) = match frames.first() {
+        Some(frame) => frame.dimensions(),
+        None => return Ok(()),
+    };
+
+    let file = File::create(path)?;
+    let mut encoder = gif::Encoder::new(file, w as u16, h as u16, &[])?;
+    encoder.set(gif::Repeat::Infinite)?;
+
+    // GIF delays are in 1/100th of a second
+    let delay = (frame_time.subsec_nanos() / 10_000_000) as u16;
+
+    for frame in frames {
+        let mut pixels = frame.clone().into_raw();
+        let mut gif_frame = gif::Frame::from_rgba(w as u16, h as u16, &mut pixels);
+        gif_frame.delay = delay;
+        encoder.write_frame(&gif_frame)?;
+    }
+
+    Ok(())
+}
+
+/// Saves a screenshot as a PNG file
+pub fn save_screenshot(image: &RgbaImage, path: &str) -> io::Result<()> {
+    image.save(path)
+}
+
+/// Returns a file name like "screenshot_1513000000123.png" (milliseconds since 1970),
+/// so that screenshots don't overwrite each other
+pub fn timestamped_file_name(prefix: &str, extension: &str) -> String {
+    let millis = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_nanos() / 1_000_000))
+        .unwrap_or(0);
+    format!("{}_{}.{}", prefix, millis, extension)
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Color {
     pub r: u8,
     pub b: u8,
@@ -7,6 +7,14 @@ pub struct Color {
 }
 
 impl Color {
+    /// Returns r, g, b, a in the range of 0.0 - 1.0 (for shaders)
+    pub fn to_f32_array(&self) -> [f32;4] {
+        [self.r as f32 / 255.0,
+         self.g as f32 / 255.0,
+         self.b as f32 / 255.0,
+         self.a as f32 / 255.0]
+    }
+
     pub fn light_blue() -> Self {
         Self {
             r: 0,
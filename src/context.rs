@@ -2,26 +2,132 @@ use glium::{self, Display, Frame, IndexBuffer, PolygonMode, Program, VertexBuffe
 use glium::backend::Facade;
 use glium::index::{NoIndices, PrimitiveType};
 use glium::texture::CompressedSrgbTexture2d;
-use glium_text::{TextSystem, FontTexture};
 
-use std::io::{BufRead, Seek, Read};
+use std::io::{BufRead, Seek};
 
 use errors::Error as AppError;
 use font::FontSystem;
 use ShaderHashMap;
-use font::FontInstanceId;
 use texture::{TextureId, TextureSystem};
+use post_processing::RenderTargets;
+use render_backend::AssetLoader;
 
 /// No indices, Triangle Strip
 pub const NO_INDICES_BUFFER_TRIANGLE: NoIndices = NoIndices(PrimitiveType::TriangleStrip);
-pub const NO_INDICES_BUFFER_LINE: NoIndices = NoIndices(PrimitiveType::LinesList);
+pub const NO_INDICES_BUFFER_TRIANGLE_LIST: NoIndices = NoIndices(PrimitiveType::TrianglesList);
 
 pub const PIXEL_TO_SCREEN_SHADER_ID: &str = "pixel_to_screen_shader";
 pub const PIXEL_TO_SCREEN_VERT_SHADER_SOURCE: &str = include_str!("../shaders/pixel_to_screen_space.vert.glsl");
 pub const PIXEL_TO_SCREEN_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/pixel_to_screen_space.frag.glsl");
 
-pub const PIXEL_TO_SCREEN_SHADER_LINE_ONLY_ID: &str = "pixel_to_screen_shader_line_only";
-pub const PIXEL_TO_SCREEN_FRAG_SHADER_LINE_ONLY_SOURCE: &str = include_str!("../shaders/pixel_to_screen_space_line_only.frag.glsl");
+pub const SHAPE_SHADER_ID: &str = "shape_shader";
+pub const SHAPE_VERT_SHADER_SOURCE: &str = include_str!("../shaders/shape.vert.glsl");
+pub const SHAPE_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/shape.frag.glsl");
+
+pub const PALETTE_SWAP_SHADER_ID: &str = "palette_swap_shader";
+pub const PALETTE_SWAP_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/palette_swap.frag.glsl");
+
+pub const SPRITE_BATCH_SHADER_ID: &str = "sprite_batch_shader";
+pub const SPRITE_BATCH_VERT_SHADER_SOURCE: &str = include_str!("../shaders/sprite_batch.vert.glsl");
+pub const SPRITE_BATCH_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/sprite_batch.frag.glsl");
+
+// lighting, uses the pixel_to_screen_space vertex shader
+
+pub const LIGHT_SHADER_ID: &str = "light_shader";
+pub const LIGHT_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/light.frag.glsl");
+
+// post-processing shaders, all of them use the pixel_to_screen_space vertex shader
+
+pub const POST_BLOOM_SHADER_ID: &str = "post_bloom_shader";
+pub const POST_BLOOM_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/post_bloom.frag.glsl");
+
+pub const POST_VIGNETTE_SHADER_ID: &str = "post_vignette_shader";
+pub const POST_VIGNETTE_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/post_vignette.frag.glsl");
+
+pub const POST_CRT_SHADER_ID: &str = "post_crt_shader";
+pub const POST_CRT_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/post_crt.frag.glsl");
+
+pub const POST_COLOR_GRADING_SHADER_ID: &str = "post_color_grading_shader";
+pub const POST_COLOR_GRADING_FRAG_SHADER_SOURCE: &str = include_str!("../shaders/post_color_grading.frag.glsl");
+
+/// A shader program, built from a vertex and a fragment shader in the `shaders/` directory
+#[derive(Debug, Copy, Clone)]
+pub struct ShaderDefinition {
+    pub id: &'static str,
+    /// File names in the `shaders/` directory, for reloading the shaders at runtime
+    pub vert_file: &'static str,
+    pub frag_file: &'static str,
+    /// Sources that are compiled into the game
+    pub vert_source: &'static str,
+    pub frag_source: &'static str,
+}
+
+/// All shader programs, compiled at startup
+pub const SHADER_DEFINITIONS: &[ShaderDefinition] = &[
+    ShaderDefinition {
+        id: PIXEL_TO_SCREEN_SHADER_ID,
+        vert_file: "pixel_to_screen_space.vert.glsl",
+        frag_file: "pixel_to_screen_space.frag.glsl",
+        vert_source: PIXEL_TO_SCREEN_VERT_SHADER_SOURCE,
+        frag_source: PIXEL_TO_SCREEN_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: SHAPE_SHADER_ID,
+        vert_file: "shape.vert.glsl",
+        frag_file: "shape.frag.glsl",
+        vert_source: SHAPE_VERT_SHADER_SOURCE,
+        frag_source: SHAPE_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: PALETTE_SWAP_SHADER_ID,
+        vert_file: "pixel_to_screen_space.vert.glsl",
+        frag_file: "palette_swap.frag.glsl",
+        vert_source: PIXEL_TO_SCREEN_VERT_SHADER_SOURCE,
+        frag_source: PALETTE_SWAP_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: SPRITE_BATCH_SHADER_ID,
+        vert_file: "sprite_batch.vert.glsl",
+        frag_file: "sprite_batch.frag.glsl",
+        vert_source: SPRITE_BATCH_VERT_SHADER_SOURCE,
+        frag_source: SPRITE_BATCH_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: LIGHT_SHADER_ID,
+        vert_file: "pixel_to_screen_space.vert.glsl",
+        frag_file: "light.frag.glsl",
+        vert_source: PIXEL_TO_SCREEN_VERT_SHADER_SOURCE,
+        frag_source: LIGHT_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: POST_BLOOM_SHADER_ID,
+        vert_file: "pixel_to_screen_space.vert.glsl",
+        frag_file: "post_bloom.frag.glsl",
+        vert_source: PIXEL_TO_SCREEN_VERT_SHADER_SOURCE,
+        frag_source: POST_BLOOM_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: POST_VIGNETTE_SHADER_ID,
+        vert_file: "pixel_to_screen_space.vert.glsl",
+        frag_file: "post_vignette.frag.glsl",
+        vert_source: PIXEL_TO_SCREEN_VERT_SHADER_SOURCE,
+        frag_source: POST_VIGNETTE_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: POST_CRT_SHADER_ID,
+        vert_file: "pixel_to_screen_space.vert.glsl",
+        frag_file: "post_crt.frag.glsl",
+        vert_source: PIXEL_TO_SCREEN_VERT_SHADER_SOURCE,
+        frag_source: POST_CRT_FRAG_SHADER_SOURCE,
+    },
+    ShaderDefinition {
+        id: POST_COLOR_GRADING_SHADER_ID,
+        vert_file: "pixel_to_screen_space.vert.glsl",
+        frag_file: "post_color_grading.frag.glsl",
+        vert_source: PIXEL_TO_SCREEN_VERT_SHADER_SOURCE,
+        frag_source: POST_COLOR_GRADING_FRAG_SHADER_SOURCE,
+    },
+];
 
 pub struct OpenGlContext
 {
@@ -29,10 +135,12 @@ pub struct OpenGlContext
     pub display: glium::backend::glutin_backend::GlutinFacade,
     // Shaders (tied to the current display)
     pub shader_programs: ShaderHashMap,
-    // The text cache + text shaders (from glium_text)
+    // The loaded fonts + the cache of rendered glyphs
     pub font_system: FontSystem,
     /// Textures
     pub texture_system: TextureSystem,
+    /// Offscreen textures for compositing screen effects
+    pub render_targets: RenderTargets,
 }
 
 impl OpenGlContext {
@@ -41,6 +149,7 @@ impl OpenGlContext {
     pub fn new(
         width: u32,
         height: u32,
+        vsync: bool,
     ) -> Result<Self, AppError>
     {
         use glium::DisplayBuild;
@@ -49,9 +158,13 @@ impl OpenGlContext {
         use glium::Surface;
         use glium::glutin;
 
-        let display = glutin::WindowBuilder::new()
+        let mut window_builder = glutin::WindowBuilder::new();
+        if vsync {
+            window_builder = window_builder.with_vsync();
+        }
+
+        let display = window_builder
             .with_gl(GlRequest::Latest)
-            .with_vsync()
             .with_dimensions(width, height)
             .with_min_dimensions(600, 400)
             .with_srgb(None)
@@ -61,12 +174,11 @@ impl OpenGlContext {
             .map_err(|_e| AppError { })?;
 
         let mut shader_programs = ShaderHashMap::default();
-        shader_programs.insert(PIXEL_TO_SCREEN_SHADER_ID, Program::from_source(
-            &display, PIXEL_TO_SCREEN_VERT_SHADER_SOURCE, PIXEL_TO_SCREEN_FRAG_SHADER_SOURCE, None
-        ).unwrap());
-        shader_programs.insert(PIXEL_TO_SCREEN_SHADER_LINE_ONLY_ID, Program::from_source(
-            &display, PIXEL_TO_SCREEN_VERT_SHADER_SOURCE, PIXEL_TO_SCREEN_FRAG_SHADER_LINE_ONLY_SOURCE, None
-        ).unwrap());
+        for shader in SHADER_DEFINITIONS {
+            shader_programs.insert(shader.id, Program::from_source(
+                &display, shader.vert_source, shader.frag_source, None
+            ).unwrap());
+        }
 
         let texture_system = TextureSystem::new();
         let font_system = FontSystem::new(&display);
@@ -76,20 +188,38 @@ impl OpenGlContext {
             shader_programs: shader_programs,
             font_system: font_system,
             texture_system: texture_system,
+            render_targets: RenderTargets::new(),
         })
     }
 
-    // load a font
-    pub fn add_font<R>(&mut self, id: &'static str, size: u32, source: R)
-        -> FontInstanceId where R: Read
-    {
-        self.font_system.add_font(id, size, source, &self.display)
+    // load a font, it can be drawn in any size
+    pub fn add_font(&mut self, id: &'static str, data: &'static [u8]) {
+        self.font_system.add_font(id, data)
     }
 
     pub fn add_texture_png<R>(&mut self, id: &'static str, source: R)
         -> TextureId
         where R: BufRead + Seek
     {
-        self.texture_system.add_png_texture(id, source, &self.display)
+        self.texture_system.add_png_texture(id, source)
+    }
+}
+
+impl AssetLoader for OpenGlContext {
+
+    fn load_font(&mut self, id: &'static str, data: &'static [u8]) {
+        self.add_font(id, data)
+    }
+
+    fn load_texture_png(&mut self, id: &'static str, data: &'static [u8]) -> TextureId {
+        self.add_texture_png(id, ::std::io::Cursor::new(data))
+    }
+
+    fn load_texture_rgba(&mut self, id: &'static str, width: u32, height: u32, pixels: Vec<u8>) -> TextureId {
+        self.texture_system.add_rgba_texture(id, width, height, pixels)
+    }
+
+    fn finish_loading(&mut self) {
+        self.texture_system.build_atlases(&self.display);
     }
 }
@@ -1,83 +1,314 @@
 //! Font data that needs to be rendered
+//!
+//! Glyphs are rasterized on demand, at any size, and cached in one texture.
+//! When the texture is full, the glyphs that weren't drawn for the longest time are evicted.
 
-use glium_text::{self, TextSystem, FontTexture, TextDisplay};
+use glium::{Frame, Texture2d, Rect, VertexBuffer};
 use glium::backend::Facade;
-use glium::Frame;
+use glium::texture::RawImage2d;
+use rusttype::{Font, FontCollection, Scale, PositionedGlyph, point};
 
-use std::io::Read;
+use std::cell::RefCell;
 
+use atlas::ShelfPacker;
 use color::Color;
-use FastHashMap;
+use texture::PixelScreenVert;
+use {FastHashMap, ShaderHashMap};
+
+/// Width and height of the glyph cache texture in pixels
+pub const GLYPH_CACHE_SIZE: u32 = 1024;
+
+/// Empty pixels between two glyphs in the cache texture
+pub const GLYPH_CACHE_PADDING: u32 = 1;
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct FontInstanceId {
     pub font_name: &'static str,
+    /// Height of the font in pixels
     pub font_size: u32,
 }
 
-/// Contains the fonts, rendered at startup time
+impl FontInstanceId {
+    /// The same font in a different size
+    pub fn with_size(&self, font_size: u32) -> Self {
+        Self {
+            font_name: self.font_name,
+            font_size: font_size.max(1),
+        }
+    }
+
+    /// The same font, scaled by a factor (i.e. for scaling the UI with the window size)
+    pub fn scaled(&self, factor: f32) -> Self {
+        self.with_size((self.font_size as f32 * factor).round() as u32)
+    }
+}
+
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
+struct GlyphKey {
+    font_name: &'static str,
+    font_size: u32,
+    glyph_id: u32,
+}
+
+/// Where a glyph is in the cache texture
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct GlyphRegion {
+    /// Bottom left corner in the cache texture (from the bottom, like texture coordinates)
+    texture_x: u32,
+    texture_y: u32,
+    width: u32,
+    height: u32,
+    /// Top left corner of the glyph, relative to the pen position on the baseline (y pointing down)
+    offset_x: i32,
+    offset_y: i32,
+}
+
+#[derive(Debug, Clone)]
+struct CachedGlyph {
+    region: GlyphRegion,
+    /// RGBA pixels (white, coverage in the alpha channel), top row first.
+    /// Kept so that the glyph can be packed again after an eviction
+    pixels: Vec<u8>,
+    /// Value of `GlyphCache::use_counter` when the glyph was last drawn
+    last_used: u64,
+}
+
+/// Glyphs of all fonts and sizes, packed into one texture
+pub struct GlyphCache {
+    pub texture: Texture2d,
+    packer: ShelfPacker,
+    glyphs: FastHashMap<GlyphKey, CachedGlyph>,
+    /// Incremented for every drawn text, to find the least recently used glyphs
+    use_counter: u64,
+    /// How often glyphs were evicted, all regions looked up before an eviction are invalid
+    evictions: u64,
+}
+
+impl GlyphCache {
+
+    pub fn new<F>(display: &F) -> Self where F: Facade {
+        Self {
+            texture: Texture2d::new(display, empty_cache_image()).unwrap(),
+            packer: ShelfPacker::new(GLYPH_CACHE_SIZE, GLYPH_CACHE_SIZE, GLYPH_CACHE_PADDING),
+            glyphs: FastHashMap::<GlyphKey, CachedGlyph>::default(),
+            use_counter: 0,
+            evictions: 0,
+        }
+    }
+
+    /// Returns where the glyph is in the texture, rasterizes it if it isn't cached yet.
+    /// Returns None for empty glyphs (spaces) and glyphs that are bigger than the texture
+    fn lookup(&mut self, key: GlyphKey, glyph: &PositionedGlyph) -> Option<GlyphRegion> {
+
+        if let Some(cached) = self.glyphs.get_mut(&key) {
+            cached.last_used = self.use_counter;
+            return Some(cached.region);
+        }
+
+        // rasterize at the origin, the position on the screen is added when drawing
+        let glyph = glyph.unpositioned().clone().positioned(point(0.0, 0.0));
+        let bounding_box = match glyph.pixel_bounding_box() {
+            Some(bb) => bb,
+            None => return None,
+        };
+
+        let width = bounding_box.width() as u32;
+        let height = bounding_box.height() as u32;
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..(width * height) {
+            pixels.extend_from_slice(&[255, 255, 255, 0]);
+        }
+        glyph.draw(|x, y, v| {
+            pixels[((y * width + x) * 4 + 3) as usize] = (v * 255.0).round().max(0.0).min(255.0) as u8;
+        });
+
+        let position = match self.packer.pack(width, height) {
+            Some(position) => Some(position),
+            None => {
+                self.evict_least_recently_used();
+                self.packer.pack(width, height)
+            },
+        };
+
+        let (x, y) = match position {
+            Some(position) => position,
+            None => return None,
+        };
+
+        let region = GlyphRegion {
+            texture_x: x,
+            // the packer counts from the top, texture coordinates from the bottom
+            texture_y: GLYPH_CACHE_SIZE - y - height,
+            width: width,
+            height: height,
+            offset_x: bounding_box.min.x,
+            offset_y: bounding_box.min.y,
+        };
+
+        self.upload(&region, &pixels);
+        self.glyphs.insert(key, CachedGlyph {
+            region: region,
+            pixels: pixels,
+            last_used: self.use_counter,
+        });
+
+        Some(region)
+    }
+
+    /// Removes the least recently used half of the glyphs and packs the rest again
+    fn evict_least_recently_used(&mut self) {
+        let mut glyphs: Vec<(GlyphKey, CachedGlyph)> = self.glyphs.drain().collect();
+        glyphs.sort_by(|a, b| b.1.last_used.cmp(&a.1.last_used));
+        let keep = glyphs.len() / 2;
+        glyphs.truncate(keep);
+
+        self.packer.clear();
+        self.texture.write(Rect { left: 0, bottom: 0, width: GLYPH_CACHE_SIZE, height: GLYPH_CACHE_SIZE }, empty_cache_image());
+
+        for (key, mut cached) in glyphs {
+            let (width, height) = (cached.region.width, cached.region.height);
+            if let Some((x, y)) = self.packer.pack(width, height) {
+                cached.region.texture_x = x;
+                cached.region.texture_y = GLYPH_CACHE_SIZE - y - height;
+                self.upload(&cached.region, &cached.pixels);
+                self.glyphs.insert(key, cached);
+            }
+        }
+
+        self.evictions += 1;
+    }
+
+    fn upload(&self, region: &GlyphRegion, pixels: &[u8]) {
+        let rect = Rect {
+            left: region.texture_x,
+            bottom: region.texture_y,
+            width: region.width,
+            height: region.height,
+        };
+        self.texture.write(rect, RawImage2d::from_raw_rgba_reversed(pixels, (region.width, region.height)));
+    }
+}
+
+fn empty_cache_image<'a>() -> RawImage2d<'a, u8> {
+    let pixels = vec![0_u8; (GLYPH_CACHE_SIZE * GLYPH_CACHE_SIZE * 4) as usize];
+    RawImage2d::from_raw_rgba_reversed(&pixels, (GLYPH_CACHE_SIZE, GLYPH_CACHE_SIZE))
+}
+
+/// Contains the loaded fonts and the glyphs rendered so far
 pub struct FontSystem {
-    pub text_system: TextSystem,
-    // font instance, hashed by source (which font it is) + size (how big the font was rendered)
-    pub fonts: FastHashMap<FontInstanceId, FontTexture>,
+    /// Parsed fonts, by name. The size is chosen when drawing
+    pub fonts: FastHashMap<&'static str, Font<'static>>,
+    pub glyph_cache: RefCell<GlyphCache>,
 }
 
 impl FontSystem {
     pub fn new<F>(display: &F) -> Self where F: Facade  {
         Self {
-            // The `TextSystem` contains the shaders and elements used for text display
-            text_system: TextSystem::new(display),
-            fonts: FastHashMap::<FontInstanceId, FontTexture>::default(),
+            fonts: FastHashMap::<&'static str, Font<'static>>::default(),
+            glyph_cache: RefCell::new(GlyphCache::new(display)),
         }
     }
 
-    pub fn add_font<R, F>(&mut self, id: &'static str, size: u32, source: R, display: &F)
-        -> FontInstanceId where R: Read, F: Facade
-    {
-        let id = FontInstanceId {
-            font_name: id,
-            font_size: size,
-        };
-
-        let actual_font = FontTexture::new(display, source, size).unwrap(); // todo: remove unwrap
-        self.fonts.insert(id.clone(), actual_font);
-        id
+    /// Parses a TrueType font. It can be drawn in any size, using
+    /// a `FontInstanceId` with the same `font_name`
+    pub fn add_font(&mut self, id: &'static str, data: &'static [u8]) {
+        let font = FontCollection::from_bytes(data).into_font().unwrap(); // todo: remove unwrap
+        self.fonts.insert(id, font);
     }
 
     pub fn calculate_font_width(&self, id: &FontInstanceId, text: &str) -> f32 {
-        let font_height = id.font_size;
-        let glium_font = self.fonts.get(id).unwrap();
-        let glium_text = glium_text::TextDisplay::new(&self.text_system, glium_font, text);
-        glium_text.get_width() * (font_height as f32)
+        let font = self.fonts.get(id.font_name).unwrap();
+        let scale = Scale::uniform(id.font_size as f32);
+        font.layout(text, scale, point(0.0, 0.0)).last().map(|glyph| {
+            glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+        }).unwrap_or(0.0)
     }
 
     // draw the text to the screen
-    pub fn draw_font(&self, frame: &mut Frame, text: &Text, color: Color) {
-        use glium::Surface;
+    pub fn draw_font<F>(&self, frame: &mut Frame, display: &F, shaders: &ShaderHashMap, text: &Text, color: Color)
+        where F: Facade
+    {
+        use glium::{Surface, DrawParameters, Blend};
+        use glium::index::{NoIndices, PrimitiveType};
+        use glium::uniforms::MagnifySamplerFilter;
+
+        let font = self.fonts.get(text.font.font_name).unwrap();
+        let scale = Scale::uniform(text.font.font_size as f32);
+        let glyphs: Vec<PositionedGlyph> = font.layout(text.text, scale, point(0.0, 0.0)).collect();
 
-        let glium_font = self.fonts.get(&text.font).unwrap();
-        let glium_text = glium_text::TextDisplay::new(&self.text_system, glium_font, text.text);
+        let mut cache = self.glyph_cache.borrow_mut();
+        cache.use_counter += 1;
+
+        // if the cache was full, glyphs looked up before the eviction have moved: look them up again
+        let mut placed_glyphs = Vec::<(f32, GlyphRegion)>::with_capacity(glyphs.len());
+        for _ in 0..2 {
+            let evictions = cache.evictions;
+            placed_glyphs.clear();
+
+            for glyph in glyphs.iter() {
+                let key = GlyphKey {
+                    font_name: text.font.font_name,
+                    font_size: text.font.font_size,
+                    glyph_id: glyph.id().0,
+                };
+                if let Some(region) = cache.lookup(key, glyph) {
+                    placed_glyphs.push((glyph.position().x.round(), region));
+                }
+            }
+
+            if cache.evictions == evictions {
+                break;
+            }
+        }
+
+        if placed_glyphs.is_empty() {
+            return;
+        }
+
+        // two triangles per glyph, the text position is the baseline
+        let texture_size = GLYPH_CACHE_SIZE as f32;
+        let mut verts = Vec::with_capacity(placed_glyphs.len() * 6);
+
+        for &(pen_x, region) in placed_glyphs.iter() {
+            let left = text.screen_x as f32 + pen_x + region.offset_x as f32;
+            let right = left + region.width as f32;
+            let top = text.screen_y as f32 - region.offset_y as f32;
+            let bottom = top - region.height as f32;
+
+            let tex_left = region.texture_x as f32 / texture_size;
+            let tex_right = (region.texture_x + region.width) as f32 / texture_size;
+            let tex_bottom = region.texture_y as f32 / texture_size;
+            let tex_top = (region.texture_y + region.height) as f32 / texture_size;
+
+            let bottom_left = PixelScreenVert { position: [left, bottom, 0.0], tex_coords: [tex_left, tex_bottom] };
+            let top_left = PixelScreenVert { position: [left, top, 0.0], tex_coords: [tex_left, tex_top] };
+            let bottom_right = PixelScreenVert { position: [right, bottom, 0.0], tex_coords: [tex_right, tex_bottom] };
+            let top_right = PixelScreenVert { position: [right, top, 0.0], tex_coords: [tex_right, tex_top] };
+
+            verts.extend_from_slice(&[bottom_left, top_left, bottom_right, bottom_right, top_left, top_right]);
+        }
 
         let (w, h) = frame.get_dimensions();
-        let font_size = text.font.font_size;
-        let scale_factor = font_size as f32 / w as f32 * 2.0;
-        let pos_x = (text.screen_x as f32 / w as f32 * 2.0) - 1.0;
-        let pos_y = (text.screen_y as f32 / h as f32 * 2.0) - 1.0;
 
-        let matrix: [[f32; 4]; 4] =
-        [
-            [scale_factor, 0.0, 0.0, 0.0],
-            [0.0, scale_factor * (w as f32) / (h as f32), 0.0, 0.0],
-            [0.0, 0.0, 1.0, 0.0],
-            [pos_x, pos_y, 0.0, 1.0f32],
-        ];
+        // the glyphs are white, the tint gives them their color
+        let uniforms = uniform!(
+            window_width: w as f32,
+            window_height: h as f32,
+            transparency: 1.0_f32,
+            tint: color.to_f32_array(),
+            flash: [0.0_f32; 4],
+            tex: cache.texture.sampled().magnify_filter(MagnifySamplerFilter::Nearest),
+        );
 
-        let r = color.r as f32 / 255.0;
-        let g = color.g as f32 / 255.0;
-        let b = color.b as f32 / 255.0;
-        let a = color.a as f32 / 255.0;
+        let draw_parameters = DrawParameters {
+            blend: Blend::alpha_blending(),
+            .. Default::default()
+        };
 
-        glium_text::draw(&glium_text, &self.text_system, frame, matrix, (r, g, b, a));
+        let vbuf = VertexBuffer::new(display, &verts).unwrap();
+        let shader = shaders.get(::context::PIXEL_TO_SCREEN_SHADER_ID).unwrap();
+        frame.draw(&vbuf, NoIndices(PrimitiveType::TrianglesList), shader, &uniforms, &draw_parameters).unwrap();
     }
 }
 
@@ -101,3 +332,253 @@ impl<'a> Text<'a> {
         }
     }
 }
+
+/// Horizontal alignment of the lines of a text within its box
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TextAlignment {
+    Left,
+    Center,
+    Right,
+}
+
+/// Effect that is drawn behind the text, in one color
+#[derive(Debug, Copy, Clone)]
+pub enum TextStyle {
+    Plain,
+    /// Copy of the text, moved by the offset (in pixels)
+    Shadow { offset_x: i32, offset_y: i32, color: Color },
+    /// Copies of the text all around it, `width` pixels away
+    Outline { width: i32, color: Color },
+}
+
+impl TextStyle {
+    /// Offsets of the copies that are drawn behind the text, and their color
+    pub fn background_copies(&self) -> Option<(Vec<(i32, i32)>, Color)> {
+        match *self {
+            TextStyle::Plain => None,
+            TextStyle::Shadow { offset_x, offset_y, color } => Some((vec![(offset_x, offset_y)], color)),
+            TextStyle::Outline { width, color } => {
+                let mut offsets = Vec::with_capacity(8);
+                for &dx in [-width, 0, width].iter() {
+                    for &dy in [-width, 0, width].iter() {
+                        if dx != 0 || dy != 0 {
+                            offsets.push((dx, dy));
+                        }
+                    }
+                }
+                Some((offsets, color))
+            },
+        }
+    }
+}
+
+/// Part of a text with its own color
+#[derive(Debug, Copy, Clone)]
+pub struct TextSpan<'a> {
+    pub text: &'a str,
+    pub color: Color,
+}
+
+impl<'a> TextSpan<'a> {
+    pub fn new(text: &'a str, color: Color) -> Self {
+        Self {
+            text: text,
+            color: color,
+        }
+    }
+}
+
+/// Where and how a text is laid out
+#[derive(Debug, Copy, Clone)]
+pub struct TextLayout {
+    /// Left edge of the box, in pixels
+    pub x: f32,
+    /// Top edge of the box, in pixels (origin at the bottom left of the screen)
+    pub top: f32,
+    /// Width of the box, the lines are aligned within this width
+    pub width: f32,
+    pub alignment: TextAlignment,
+    /// Break lines between words if they get wider than the box
+    pub wrap: bool,
+    /// Distance between two lines, as a multiple of the font size
+    pub line_spacing: f32,
+    pub style: TextStyle,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        Self {
+            x: 0.0,
+            top: 0.0,
+            width: 0.0,
+            alignment: TextAlignment::Left,
+            wrap: false,
+            line_spacing: 1.2,
+            style: TextStyle::Plain,
+        }
+    }
+}
+
+/// A piece of text on one line with one color, ready to be drawn
+#[derive(Debug, Clone)]
+pub struct TextRun {
+    pub text: String,
+    pub color: Color,
+    /// Left edge of the text, in pixels
+    pub x: f32,
+    /// Position of the line, same as `Text::screen_y`
+    pub y: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct LaidOutText {
+    pub runs: Vec<TextRun>,
+    /// Size of the text in pixels (not the size of the box)
+    pub width: f32,
+    pub height: f32,
+    pub line_count: usize,
+}
+
+enum LayoutToken<'a> {
+    Piece(&'a str, Color),
+    Space,
+    Newline,
+}
+
+/// Pieces of text placed on one line, x is relative to the start of the line
+#[derive(Default)]
+struct LayoutLine {
+    runs: Vec<(String, Color, f32)>,
+    width: f32,
+}
+
+/// Splits the spans into lines and positions each line in the box.
+/// `measure` returns the width of a string in pixels (i.e. `RenderBackend::calculate_font_width`)
+pub fn layout_text<F>(font: &FontInstanceId, spans: &[TextSpan], layout: &TextLayout, measure: F)
+    -> LaidOutText where F: Fn(&str) -> f32
+{
+    // -- split the text into words, spaces and line breaks
+    let mut tokens = Vec::<LayoutToken>::new();
+
+    for span in spans {
+        for (line_idx, line) in span.text.split('\n').enumerate() {
+            if line_idx > 0 {
+                tokens.push(LayoutToken::Newline);
+            }
+            for (word_idx, word) in line.split(' ').enumerate() {
+                if word_idx > 0 {
+                    tokens.push(LayoutToken::Space);
+                }
+                if !word.is_empty() {
+                    tokens.push(LayoutToken::Piece(word, span.color));
+                }
+            }
+        }
+    }
+
+    // -- put the words on lines. A word can consist of several pieces with different colors
+    let space_width = measure(" ");
+    let mut lines = vec![LayoutLine::default()];
+    let mut word = Vec::<(&str, Color)>::new();
+    let mut spaces_before_word = 0;
+
+    let place_word = |word: &mut Vec<(&str, Color)>, spaces: usize, lines: &mut Vec<LayoutLine>| {
+        if word.is_empty() {
+            return;
+        }
+
+        let word_width: f32 = word.iter().map(|&(piece, _)| measure(piece)).sum();
+        let mut spaces = spaces;
+
+        let needs_break = {
+            let line = lines.last().unwrap();
+            layout.wrap && !line.runs.is_empty() &&
+            line.width + spaces as f32 * space_width + word_width > layout.width
+        };
+
+        // spaces at the start of a wrapped line are dropped
+        if needs_break {
+            lines.push(LayoutLine::default());
+            spaces = 0;
+        }
+
+        let line = lines.last_mut().unwrap();
+        line.width += spaces as f32 * space_width;
+
+        for (piece_idx, &(piece, color)) in word.iter().enumerate() {
+            let leading_spaces = if piece_idx == 0 { spaces } else { 0 };
+
+            // continue the previous run if it has the same color, so that it is drawn at once
+            let same_color = line.runs.last().map(|&(_, c, _)| c == color).unwrap_or(false);
+            if same_color {
+                let run = line.runs.last_mut().unwrap();
+                for _ in 0..leading_spaces {
+                    run.0.push(' ');
+                }
+                run.0.push_str(piece);
+            } else {
+                line.runs.push((piece.to_string(), color, line.width));
+            }
+
+            line.width += measure(piece);
+        }
+
+        word.clear();
+    };
+
+    for token in tokens.iter() {
+        match *token {
+            LayoutToken::Piece(piece, color) => {
+                word.push((piece, color));
+            },
+            LayoutToken::Space => {
+                if !word.is_empty() {
+                    place_word(&mut word, spaces_before_word, &mut lines);
+                    spaces_before_word = 0;
+                }
+                spaces_before_word += 1;
+            },
+            LayoutToken::Newline => {
+                // spaces at the end of a line are dropped
+                place_word(&mut word, spaces_before_word, &mut lines);
+                spaces_before_word = 0;
+                lines.push(LayoutLine::default());
+            },
+        }
+    }
+
+    place_word(&mut word, spaces_before_word, &mut lines);
+
+    // -- align the lines in the box, the first line starts at the top
+    let font_size = font.font_size as f32;
+    let line_height = font_size * layout.line_spacing;
+    let mut runs = Vec::new();
+    let mut width = 0.0_f32;
+
+    for (line_idx, line) in lines.iter().enumerate() {
+        let offset_x = match layout.alignment {
+            TextAlignment::Left => 0.0,
+            TextAlignment::Center => (layout.width - line.width) / 2.0,
+            TextAlignment::Right => layout.width - line.width,
+        };
+
+        let y = layout.top - font_size - line_idx as f32 * line_height;
+        width = width.max(line.width);
+
+        for &(ref text, color, x) in line.runs.iter() {
+            runs.push(TextRun {
+                text: text.clone(),
+                color: color,
+                x: layout.x + offset_x + x,
+                y: y,
+            });
+        }
+    }
+
+    LaidOutText {
+        runs: runs,
+        width: width,
+        height: font_size + (lines.len() - 1) as f32 * line_height,
+        line_count: lines.len(),
+    }
+}
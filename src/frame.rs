@@ -3,12 +3,17 @@ use audio::AudioContext;
 use {TextureInstanceIdMap, FontInstanceIdMap};
 use color::Color;
 use font::FontInstanceId;
-use texture::{TextureId, TextureInstanceId, TextureDrawOptions};
+use texture::{TextureId, TextureInstanceId, TextureDrawOptions, SpriteDrawParameters};
 use context::OpenGlContext;
 use font::Text;
 use std::rc::Rc;
 use glium::backend::Context;
 use ShaderHashMap;
+use post_processing::PostProcessingSettings;
+use render_backend::RenderBackend;
+use shapes::ShapeBatch;
+use lighting::LightingFrame;
+use image::{self, RgbaImage};
 
 /// This does NOT represent just the screen, it
 /// represents everything that can be done in 1/60th of a sencond.
@@ -22,8 +27,53 @@ pub struct GameFrame<'a> {
 
 impl<'a> GameFrame<'a> {
 
+    /// Applies the enabled screen effects to everything drawn so far
+    pub fn apply_post_processing(&mut self, settings: &PostProcessingSettings) {
+        ::post_processing::apply_post_processing(&mut self.frame, &self.context.display,
+            &self.context.render_targets, &self.context.shader_programs, settings);
+    }
+
+    /// Reads back everything drawn so far, top row first
+    pub fn read_pixels(&self) -> RgbaImage {
+        use glium::{Surface, BlitTarget};
+        use glium::texture::RawImage2d;
+        use glium::uniforms::MagnifySamplerFilter;
+        use capture::CAPTURE_TARGET_ID;
+
+        let (w, h) = self.frame.get_dimensions();
+        self.context.render_targets.ensure_target(&self.context.display, CAPTURE_TARGET_ID, w, h);
+
+        let targets = self.context.render_targets.get();
+        let capture_target = &targets[CAPTURE_TARGET_ID];
+
+        self.frame.blit_whole_color_to(&capture_target.as_surface(), &BlitTarget {
+            left: 0,
+            bottom: 0,
+            width: w as i32,
+            height: h as i32,
+        }, MagnifySamplerFilter::Nearest);
+
+        let raw: RawImage2d<u8> = capture_target.read();
+        let image = RgbaImage::from_raw(raw.width, raw.height, raw.data.into_owned()).unwrap();
+
+        // OpenGL stores the bottom row first
+        image::imageops::flip_vertical(&image)
+    }
+
+    pub fn drop(self) {
+        self.frame.finish().unwrap();
+    }
+}
+
+impl<'a> RenderBackend for GameFrame<'a> {
+
+    fn dimensions(&self) -> (u32, u32) {
+        use glium::Surface;
+        self.frame.get_dimensions()
+    }
+
     // clear the screen
-    pub fn clear_screen(&mut self, color: Color) {
+    fn clear_screen(&mut self, color: Color) {
         use glium::Surface;
         self.frame.clear_color_and_depth(
             (color.r as f32 / 255.0,
@@ -34,31 +84,172 @@ impl<'a> GameFrame<'a> {
     }
 
     // notice: panics if the font isn't valid!!!
-    pub fn get_font(&self, id: &'static str) -> FontInstanceId {
+    fn get_font(&self, id: &'static str) -> FontInstanceId {
         let font_id = self.font_ids.get(id);
         *font_id.unwrap()
     }
 
-    pub fn get_texture(&self, id: &'static str) -> TextureId {
+    fn get_texture(&self, id: &'static str) -> TextureId {
         let texture_id = self.texture_ids.get(id);
         *texture_id.unwrap()
     }
 
-    pub fn calculate_font_width(&self, id: &FontInstanceId, text: &str) -> f32 {
+    fn calculate_font_width(&self, id: &FontInstanceId, text: &str) -> f32 {
         self.context.font_system.calculate_font_width(id, text)
     }
 
-    pub fn draw_font(&mut self, text: &Text, color: Color) {
-        self.context.font_system.draw_font(&mut self.frame, text, color);
+    fn draw_font(&mut self, text: &Text, color: Color) {
+        self.context.font_system.draw_font(&mut self.frame, &self.context.display, &self.context.shader_programs, text, color);
     }
 
-    pub fn draw_texture(&mut self, display: &Rc<Context>, texture_id: &TextureInstanceId,
-                        transparency: f32, shaders: &ShaderHashMap, options: TextureDrawOptions)
-    {
-        self.context.texture_system.draw_texture(&mut self.frame, display, texture_id, transparency, shaders, options);
+    fn draw_texture(&mut self, texture_id: &TextureInstanceId, sprite_parameters: &SpriteDrawParameters, options: TextureDrawOptions) {
+        use glium::backend::Facade;
+        self.context.texture_system.draw_texture(&mut self.frame, self.context.display.get_context(), texture_id,
+                                                 sprite_parameters, &self.context.shader_programs, options);
     }
 
-    pub fn drop(self) {
-        self.frame.finish().unwrap();
+    fn draw_texture_batch(&mut self, sprites: &[(TextureInstanceId, SpriteDrawParameters)], options: TextureDrawOptions) {
+        use glium::backend::Facade;
+        self.context.texture_system.draw_texture_batch(&mut self.frame, self.context.display.get_context(), sprites,
+                                                       &self.context.shader_programs, options);
     }
+
+    fn draw_shapes(&mut self, shapes: &ShapeBatch) {
+        use glium::{Surface, DrawParameters, VertexBuffer, Blend};
+
+        if shapes.is_empty() {
+            return;
+        }
+
+        let (w, h) = self.frame.get_dimensions();
+
+        // no depth test, the shapes are drawn over everything drawn before and
+        // overlapping shapes in the batch are drawn in the order they were added
+        let uniforms = uniform!{
+            window_width: w as f32,
+            window_height: h as f32,
+            z: 0.0_f32,
+        };
+
+        let draw_parameters = DrawParameters {
+            blend: Blend::alpha_blending(),
+            .. Default::default()
+        };
+
+        let vbuf = VertexBuffer::new(&self.context.display, &shapes.vertices).unwrap();
+        let program = self.context.shader_programs.get(::context::SHAPE_SHADER_ID).unwrap();
+        self.frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE_LIST, program, &uniforms, &draw_parameters).unwrap();
+    }
+
+    fn draw_lighting(&mut self, lighting: &LightingFrame) {
+        use glium::{Surface, DrawParameters, VertexBuffer, Blend};
+        use glium::draw_parameters::{BlendingFunction, LinearBlendingFactor};
+        use glium::uniforms::MagnifySamplerFilter;
+        use lighting::{LIGHT_ACCUMULATION_TARGET_ID, LIGHT_SHADOW_MASK_TARGET_ID, shadow_quads};
+        use texture::PixelScreenVert;
+
+        let (w, h) = self.frame.get_dimensions();
+        let display = &self.context.display;
+        let shaders = &self.context.shader_programs;
+
+        self.context.render_targets.ensure_target(display, LIGHT_ACCUMULATION_TARGET_ID, w, h);
+        self.context.render_targets.ensure_target(display, LIGHT_SHADOW_MASK_TARGET_ID, w, h);
+
+        let targets = self.context.render_targets.get();
+        let accumulation = &targets[LIGHT_ACCUMULATION_TARGET_ID];
+        let shadow_mask = &targets[LIGHT_SHADOW_MASK_TARGET_ID];
+
+        let ambient = lighting.ambient.to_f32_array();
+        accumulation.as_surface().clear_color(ambient[0], ambient[1], ambient[2], 1.0);
+
+        let additive_blending = Blend {
+            color: BlendingFunction::Addition { source: LinearBlendingFactor::One, destination: LinearBlendingFactor::One },
+            alpha: BlendingFunction::Addition { source: LinearBlendingFactor::One, destination: LinearBlendingFactor::One },
+            constant_value: (0.0, 0.0, 0.0, 0.0),
+        };
+
+        // shadows reach until the end of the screen
+        let shadow_length = (w as f32).hypot(h as f32) * 2.0;
+
+        for light in lighting.lights.iter() {
+
+            // -- shadow mask: white = lit, black = in the shadow of a crate
+            {
+                let mut mask_surface = shadow_mask.as_surface();
+                mask_surface.clear_color(1.0, 1.0, 1.0, 1.0);
+
+                let mut shadows = ShapeBatch::new();
+                for occluder in lighting.occluders.iter() {
+                    for quad in shadow_quads(light, occluder, shadow_length) {
+                        shadows.quad(quad[0], quad[1], quad[2], quad[3], Color::black());
+                    }
+                }
+
+                if !shadows.is_empty() {
+                    let uniforms = uniform!{
+                        window_width: w as f32,
+                        window_height: h as f32,
+                        z: 0.0_f32,
+                    };
+                    let vbuf = VertexBuffer::new(display, &shadows.vertices).unwrap();
+                    let program = shaders.get(::context::SHAPE_SHADER_ID).unwrap();
+                    mask_surface.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE_LIST, program, &uniforms, &DrawParameters::default()).unwrap();
+                }
+            }
+
+            // -- the light itself, a quad around the light position. The shader fades it out
+            let (left, right) = (light.x - light.radius, light.x + light.radius);
+            let (bottom, top) = (light.y - light.radius, light.y + light.radius);
+
+            let vertex_buf = [
+                PixelScreenVert { position: [left,  bottom, 0.0], tex_coords: [-1.0, -1.0] },
+                PixelScreenVert { position: [left,  top,    0.0], tex_coords: [-1.0,  1.0] },
+                PixelScreenVert { position: [right, bottom, 0.0], tex_coords: [ 1.0, -1.0] },
+                PixelScreenVert { position: [right, top,    0.0], tex_coords: [ 1.0,  1.0] },
+            ];
+
+            let uniforms = uniform!{
+                window_width: w as f32,
+                window_height: h as f32,
+                light_color: light.color.to_f32_array(),
+                light_intensity: light.intensity,
+                shadow_mask: shadow_mask.sampled().magnify_filter(MagnifySamplerFilter::Nearest),
+            };
+
+            let draw_parameters = DrawParameters {
+                blend: additive_blending,
+                .. Default::default()
+            };
+
+            let vbuf = VertexBuffer::new(display, &vertex_buf).unwrap();
+            let program = shaders.get(::context::LIGHT_SHADER_ID).unwrap();
+            accumulation.as_surface().draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE, program, &uniforms, &draw_parameters).unwrap();
+        }
+
+        // -- multiply the scene with the accumulated light
+        let multiply_blending = Blend {
+            color: BlendingFunction::Addition { source: LinearBlendingFactor::DestinationColor, destination: LinearBlendingFactor::Zero },
+            alpha: BlendingFunction::Addition { source: LinearBlendingFactor::Zero, destination: LinearBlendingFactor::One },
+            constant_value: (0.0, 0.0, 0.0, 0.0),
+        };
+
+        let uniforms = uniform!(
+            window_width: w as f32,
+            window_height: h as f32,
+            transparency: 1.0_f32,
+            tint: Color::white().to_f32_array(),
+            flash: [0.0_f32; 4],
+            tex: accumulation.sampled().magnify_filter(MagnifySamplerFilter::Nearest),
+        );
+
+        let draw_parameters = DrawParameters {
+            blend: multiply_blending,
+            .. Default::default()
+        };
+
+        let vbuf = ::post_processing::fullscreen_quad(display, w, h);
+        let program = shaders.get(::context::PIXEL_TO_SCREEN_SHADER_ID).unwrap();
+        self.frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE, program, &uniforms, &draw_parameters).unwrap();
+    }
+
 }
@@ -0,0 +1,256 @@
+//! Frame pacing
+//!
+//! Waits at the end of each frame so that the game runs at the configured frame rate
+//! and keeps track of how long the last frames took.
+
+use std::time::{Duration, Instant};
+use std::collections::VecDeque;
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, Write};
+
+/// How many frame times are kept for the statistics (about two seconds at 60 FPS)
+pub const FRAME_TIME_HISTORY: usize = 120;
+
+/// The last part of the wait is spent yielding instead of sleeping,
+/// because `thread::sleep` may oversleep by a millisecond or more
+const SPIN_WAIT_NANOS: u64 = 1_500_000;
+
+/// How fast the game is allowed to run
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameRateCap {
+    /// Render as fast as possible
+    Uncapped,
+    /// Wait for the vertical blank of the monitor when swapping buffers
+    VSync,
+    /// Limit to a fixed number of frames per second, without vsync
+    Fixed(u32),
+}
+
+impl Default for FrameRateCap {
+    fn default() -> Self {
+        FrameRateCap::VSync
+    }
+}
+
+impl FrameRateCap {
+    /// If the window has to be created with vsync enabled
+    pub fn uses_vsync(&self) -> bool {
+        *self == FrameRateCap::VSync
+    }
+
+    /// The cap after this one, for switching through the caps with a hotkey
+    pub fn next(&self) -> Self {
+        match *self {
+            FrameRateCap::VSync => FrameRateCap::Fixed(60),
+            FrameRateCap::Fixed(60) => FrameRateCap::Fixed(30),
+            FrameRateCap::Fixed(_) => FrameRateCap::Uncapped,
+            FrameRateCap::Uncapped => FrameRateCap::VSync,
+        }
+    }
+
+    /// Parses `vsync`, `uncapped` or a number of frames per second
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "vsync" => Some(FrameRateCap::VSync),
+            "uncapped" => Some(FrameRateCap::Uncapped),
+            fps => match fps.parse::<u32>() {
+                Ok(fps) if fps > 0 => Some(FrameRateCap::Fixed(fps)),
+                _ => None,
+            },
+        }
+    }
+
+    /// Loads the cap from a `key = value` file, keeps the default if the key is missing or invalid
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut cap = Self::default();
+        for (key, value) in ::settings::read_config_file(path)? {
+            match key.as_str() {
+                "frame_rate_cap" => if let Some(c) = Self::parse(&value) { cap = c; },
+                _ => println!("unknown frame rate setting: {:?}", key),
+            }
+        }
+
+        Ok(cap)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        match *self {
+            FrameRateCap::VSync => writeln!(file, "frame_rate_cap = vsync")?,
+            FrameRateCap::Uncapped => writeln!(file, "frame_rate_cap = uncapped")?,
+            FrameRateCap::Fixed(fps) => writeln!(file, "frame_rate_cap = {}", fps)?,
+        }
+        Ok(())
+    }
+
+    /// Minimum time between two frames, if the limiter has to wait at all
+    pub fn target_frame_time(&self) -> Option<Duration> {
+        match *self {
+            FrameRateCap::Fixed(fps) if fps > 0 => Some(Duration::new(0, 1_000_000_000 / fps)),
+            _ => None,
+        }
+    }
+}
+
+/// Statistics over the last frames, all times in milliseconds
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct FrameStats {
+    /// Number of frames the statistics are calculated from
+    pub frame_count: usize,
+    pub average_ms: f32,
+    pub min_ms: f32,
+    pub max_ms: f32,
+    /// 99% of the frames were faster than this, shows stutter better than the average
+    pub percentile_99_ms: f32,
+    /// Frames per second, calculated from the average frame time
+    pub fps: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct FrameLimiter {
+    pub cap: FrameRateCap,
+    /// When the current frame started (set when the previous frame was finished)
+    frame_start: Instant,
+    /// Time between the starts of the last frames, oldest first
+    frame_times: VecDeque<Duration>,
+}
+
+impl FrameLimiter {
+
+    pub fn new(cap: FrameRateCap) -> Self {
+        Self {
+            cap: cap,
+            frame_start: Instant::now(),
+            frame_times: VecDeque::with_capacity(FRAME_TIME_HISTORY),
+        }
+    }
+
+    /// Call once at the end of every frame (after the buffers were swapped).
+    /// Waits until the frame has taken as long as the cap allows and starts the next frame
+    pub fn end_frame(&mut self) {
+        if let Some(target) = self.cap.target_frame_time() {
+            let deadline = self.frame_start + target;
+            let spin_time = Duration::new(0, SPIN_WAIT_NANOS as u32);
+
+            let now = Instant::now();
+            if deadline > now + spin_time {
+                ::std::thread::sleep(deadline - now - spin_time);
+            }
+            while Instant::now() < deadline {
+                ::std::thread::yield_now();
+            }
+        }
+
+        let now = Instant::now();
+        if self.frame_times.len() == FRAME_TIME_HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(now - self.frame_start);
+        self.frame_start = now;
+    }
+
+    /// Statistics of the last `FRAME_TIME_HISTORY` frames
+    pub fn stats(&self) -> FrameStats {
+        if self.frame_times.is_empty() {
+            return FrameStats::default();
+        }
+
+        let mut times_ms = self.frame_times.iter().map(|t| duration_to_ms(*t)).collect::<Vec<f32>>();
+        times_ms.sort_by(|a, b| a.partial_cmp(b).unwrap_or(::std::cmp::Ordering::Equal));
+
+        let frame_count = times_ms.len();
+        let average_ms = times_ms.iter().sum::<f32>() / frame_count as f32;
+        let percentile_idx = ((frame_count as f32 * 0.99).ceil() as usize).max(1) - 1;
+
+        FrameStats {
+            frame_count: frame_count,
+            average_ms: average_ms,
+            min_ms: times_ms[0],
+            max_ms: times_ms[frame_count - 1],
+            percentile_99_ms: times_ms[percentile_idx],
+            fps: if average_ms > 0.0 { 1000.0 / average_ms } else { 0.0 },
+        }
+    }
+
+    /// Forgets the recorded frame times, i.e. after loading or when the cap changes
+    pub fn reset_stats(&mut self) {
+        self.frame_times.clear();
+        self.frame_start = Instant::now();
+    }
+}
+
+pub fn duration_to_ms(duration: Duration) -> f32 {
+    duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter_with_frame_times(times_ms: &[u64]) -> FrameLimiter {
+        let mut limiter = FrameLimiter::new(FrameRateCap::Uncapped);
+        limiter.frame_times = times_ms.iter().map(|ms| Duration::from_millis(*ms)).collect();
+        limiter
+    }
+
+    #[test]
+    fn stats_of_no_frames_are_zero() {
+        assert_eq!(limiter_with_frame_times(&[]).stats(), FrameStats::default());
+    }
+
+    #[test]
+    fn stats_of_a_single_frame() {
+        let stats = limiter_with_frame_times(&[20]).stats();
+        assert_eq!(stats.frame_count, 1);
+        assert_eq!(stats.min_ms, 20.0);
+        assert_eq!(stats.max_ms, 20.0);
+        assert_eq!(stats.percentile_99_ms, 20.0);
+        assert_eq!(stats.fps, 50.0);
+    }
+
+    #[test]
+    fn percentile_is_taken_from_the_sorted_frame_times() {
+        // 1 - 100 ms, in reverse order
+        let times = (1..101).rev().collect::<Vec<u64>>();
+        let stats = limiter_with_frame_times(&times).stats();
+        assert_eq!(stats.frame_count, 100);
+        assert_eq!(stats.min_ms, 1.0);
+        assert_eq!(stats.max_ms, 100.0);
+        assert_eq!(stats.average_ms, 50.5);
+        // 99 of the 100 frames were faster than or as fast as 99 ms
+        assert_eq!(stats.percentile_99_ms, 99.0);
+    }
+
+    #[test]
+    fn one_stutter_shows_in_the_percentile_but_not_the_average() {
+        let mut times = vec![10; 119];
+        times.push(100);
+        let stats = limiter_with_frame_times(&times).stats();
+        // 99% of 120 frames are 118.8, rounded up to the 119th frame
+        assert_eq!(stats.percentile_99_ms, 10.0);
+        assert_eq!(stats.max_ms, 100.0);
+        assert!((stats.average_ms - 10.75).abs() < 0.001);
+    }
+
+    #[test]
+    fn history_is_limited() {
+        let mut limiter = FrameLimiter::new(FrameRateCap::Uncapped);
+        for _ in 0..(FRAME_TIME_HISTORY + 10) {
+            limiter.end_frame();
+        }
+        assert_eq!(limiter.stats().frame_count, FRAME_TIME_HISTORY);
+
+        limiter.reset_stats();
+        assert_eq!(limiter.stats().frame_count, 0);
+    }
+
+    #[test]
+    fn cap_is_parsed_from_the_config_value() {
+        assert_eq!(FrameRateCap::parse("vsync"), Some(FrameRateCap::VSync));
+        assert_eq!(FrameRateCap::parse("uncapped"), Some(FrameRateCap::Uncapped));
+        assert_eq!(FrameRateCap::parse("144"), Some(FrameRateCap::Fixed(144)));
+        assert_eq!(FrameRateCap::parse("0"), None);
+        assert_eq!(FrameRateCap::parse("fast"), None);
+    }
+}
@@ -3,33 +3,67 @@
 use player_state::PlayerState;
 use camera::Camera;
 use renderer::Renderer;
-use audio::AudioContext;
+use audio::{AudioContext, AudioCommand, SongId};
 use color::Color;
-use physics::{PhysicsWorld, PhysicsFinalizedData};
+use physics::{PhysicsWorld, PhysicsFinalizedData, PlayerResult};
 use {ShaderHashMap, FontInstanceIdMap, TextureInstanceIdMap, FastHashMap};
 use font::FontInstanceId;
-use texture::TextureInstanceId;
+use texture::{TextureInstanceId, SpriteDrawParameters};
 use frame::GameFrame;
-use font::Text;
+use font::{TextSpan, TextLayout, TextAlignment, TextStyle};
 use std::rc::Rc;
 use glium::backend::Context;
 use ui::{Ui, UiRect, UiRendererData, UiActions};
+use settings::{Settings, AudioSettings};
+use post_processing::PostProcessingSettings;
+use render_backend::RenderBackend;
+use capture::FrameRecorder;
+use shader_reload::ShaderReloader;
+use sfx::SpatialAudio;
+use frame_limiter::{FrameLimiter, FrameRateCap, FrameStats};
 
 pub const FONT_BIG_ID: &str = "font_fredoka_big";
 pub const FONT_MEDIUM_ID: &str = "font_fredoka_medium";
 pub const FONT_SMALL_ID: &str = "font_fredoka_small";
 
+/// Height of the highest stack of crates (in pixels) at which the music
+/// switches to the next level song, 5 and 10 crates
+pub const MUSIC_LEVEL_2_HEIGHT: f32 = 5.0 * ::physics::CRATE_SIZE;
+pub const MUSIC_LEVEL_3_HEIGHT: f32 = 10.0 * ::physics::CRATE_SIZE;
+/// Smallest change of the tower wobble that changes the music speed
+pub const MUSIC_TENSION_STEP: f32 = 0.05;
+
+/// Window height at which the fonts of the menus are drawn at their default size
+pub const UI_REFERENCE_HEIGHT: f32 = 600.0;
+
 pub const TEXTURE_START_GAME_ID: &str = "texture_start_game";
 pub const TEXTURE_HERO_CHARACTER_ID: &str = "texture_hero_character";
 pub const TEXTURE_CRATE_ID: &str = "texture_crate";
 pub const TEXTURE_BACKGROUND_ID: &str = "texture_background";
+pub const TEXTURE_WHITE_ID: &str = "texture_white";
 
 pub struct Game {
     pub renderer: Renderer,
     pub audio_context: AudioContext,
+    /// The song that was last sent to the audio thread
+    pub current_song: Option<SongId>,
+    /// The music tension that was last sent to the audio thread
+    pub music_tension: f32,
+    /// If the audio is paused because the window is in the background
+    pub audio_paused: bool,
+    /// Positions the sound effects relative to the camera
+    pub spatial_audio: SpatialAudio,
     pub available_font_ids: FontInstanceIdMap,
     pub available_texture_ids: TextureInstanceIdMap,
     pub game_state: GameState,
+    /// User-configurable settings (screen effects, etc.)
+    pub settings: Settings,
+    /// Rolling buffer of the last few seconds, for exporting GIFs
+    pub frame_recorder: FrameRecorder,
+    /// Recompiles changed shaders (debug builds only)
+    pub shader_reloader: ShaderReloader,
+    /// Waits between frames according to `settings.frame_rate_cap`
+    pub frame_limiter: FrameLimiter,
 }
 
 /// what the state of the game currently is (what should be drawn on the screen)
@@ -72,13 +106,23 @@ impl GameState {
         }
     }
 
-    pub fn get_song(&self) -> &'static str {
+    /// Which song should be playing. In-game, the music gets more intense the higher the tower is.
+    /// The song doesn't switch back if the tower gets lower again
+    pub fn get_song(&self) -> SongId {
         match *self {
             GameState::StartMenu => {
-                ::assets::AUDIO_MSG_PLAY_TITLE_SCREEN_SONG
+                SongId::TitleScreen
             },
-            GameState::Game(_) => {
-                ::assets::AUDIO_MSG_PLAY_GAME_SONG
+            GameState::Game(ref player_state) => {
+                if player_state.game_over {
+                    SongId::Ending
+                } else if player_state.best_stack_height >= MUSIC_LEVEL_3_HEIGHT {
+                    SongId::Level3
+                } else if player_state.best_stack_height >= MUSIC_LEVEL_2_HEIGHT {
+                    SongId::Level2
+                } else {
+                    SongId::Level1
+                }
             }
         }
     }
@@ -89,47 +133,69 @@ impl Game {
     /// Initializes the audio and renderer, adds all the fonts, and textures and shaders
     pub fn new(width: u32, height: u32) -> Self {
 
-        use std::io::Cursor;
-
         // -- initialize audio
         let audio_context = AudioContext::new();
 
-        // -- initialize shaders
-        let mut renderer = Renderer::new(width, height).unwrap();
-
-        // -- initialize fonts
-        let mut available_font_ids = FontInstanceIdMap::default();
-
-        let font_instance_big_id = renderer.context.add_font(::assets::FONT_ID, ::assets::FONT_BIG_SIZE, Cursor::new(::assets::FONT));
-        available_font_ids.insert(FONT_BIG_ID, font_instance_big_id);
-
-        let font_instance_medium_id = renderer.context.add_font(::assets::FONT_ID, ::assets::FONT_MEDIUM_SIZE, Cursor::new(::assets::FONT));
-        available_font_ids.insert(FONT_MEDIUM_ID, font_instance_medium_id);
-
-        let font_instance_small_id = renderer.context.add_font(::assets::FONT_ID, ::assets::FONT_SMALL_SIZE, Cursor::new(::assets::FONT));
-        available_font_ids.insert(FONT_SMALL_ID, font_instance_small_id);
-
-        // -- initialize textures
-        let mut available_texture_ids = TextureInstanceIdMap::default();
-
-        let texture_start_game = renderer.context.add_texture_png(::assets::START_SCREEN_BUTTON_00_ID, Cursor::new(::assets::START_SCREEN_BUTTON_00));
-        available_texture_ids.insert(TEXTURE_START_GAME_ID, texture_start_game);
-
-        let texture_hero_char = renderer.context.add_texture_png(::assets::HERO_TEXTURE_ID, Cursor::new(::assets::HERO_TEXTURE));
-        available_texture_ids.insert(TEXTURE_HERO_CHARACTER_ID, texture_hero_char);
+        let mut settings = Settings::default();
+        match AudioSettings::load(::settings::AUDIO_CONFIG_FILE) {
+            Ok(audio_settings) => settings.audio = audio_settings,
+            Err(e) => println!("using default audio settings: {}", e),
+        }
+        match PostProcessingSettings::load(::settings::GRAPHICS_CONFIG_FILE) {
+            Ok(post_processing) => settings.post_processing = post_processing,
+            Err(e) => println!("using default graphics settings: {}", e),
+        }
+        match FrameRateCap::load(::settings::FRAME_RATE_CONFIG_FILE) {
+            Ok(frame_rate_cap) => settings.frame_rate_cap = frame_rate_cap,
+            Err(e) => println!("using the default frame rate cap: {}", e),
+        }
 
-        let texture_crate = renderer.context.add_texture_png(::assets::CRATE_TEXTURE_ID, Cursor::new(::assets::CRATE_TEXTURE_DATA));
-        available_texture_ids.insert(TEXTURE_CRATE_ID, texture_crate);
+        // -- initialize shaders
+        let mut renderer = Renderer::new(width, height, settings.frame_rate_cap.uses_vsync()).unwrap();
 
-        let texture_background = renderer.context.add_texture_png(::assets::BACKGROUND_3_TEXTURE_ID, Cursor::new(::assets::BACKGROUND_3_TEXTURE_DATA));
-        available_texture_ids.insert(TEXTURE_BACKGROUND_ID, texture_background);
+        // -- initialize fonts and textures
+        let (available_font_ids, available_texture_ids) = ::render_backend::load_game_assets(&mut renderer.context);
 
         Self {
             renderer: renderer,
             audio_context: audio_context,
+            current_song: None,
+            music_tension: 0.0,
+            audio_paused: false,
+            spatial_audio: SpatialAudio::new(),
             available_font_ids: available_font_ids,
             available_texture_ids: available_texture_ids,
             game_state: GameState::StartMenu,
+            settings: settings,
+            frame_recorder: FrameRecorder::new(),
+            shader_reloader: ShaderReloader::new(),
+            frame_limiter: FrameLimiter::new(settings.frame_rate_cap),
+        }
+    }
+
+    /// Frame time statistics of the last few seconds
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_limiter.stats()
+    }
+
+    /// Switches to the next frame rate cap and saves it. Prints how the old cap performed,
+    /// so that the caps can be compared
+    fn change_frame_rate_cap(&mut self) {
+        let stats = self.frame_stats();
+        println!("{:?}: {:.1} FPS, {:.2} ms average, {:.2} ms 99th percentile over {} frames",
+                 self.settings.frame_rate_cap, stats.fps, stats.average_ms, stats.percentile_99_ms, stats.frame_count);
+
+        let cap = self.settings.frame_rate_cap.next();
+        // vsync can only be set when the window is created
+        if cap.uses_vsync() != self.settings.frame_rate_cap.uses_vsync() {
+            println!("switching vsync on or off takes effect after a restart");
+        }
+
+        self.settings.frame_rate_cap = cap;
+        self.frame_limiter.cap = cap;
+        self.frame_limiter.reset_stats();
+        if let Err(e) = cap.save(::settings::FRAME_RATE_CONFIG_FILE) {
+            println!("could not save the frame rate cap: {}", e);
         }
     }
 
@@ -139,14 +205,15 @@ impl Game {
         use glium::Surface;
         use glium::backend::Facade;
         use glium::glutin::MouseCursor;
-        use input::GameInputEvent;
+        use input::{GameInputEvent, Hotkey};
 
         let mut previous_frame_ui = Ui::default();
         let mut previous_mouse_cursor_type = MouseCursor::Default;
 
+        send_audio_volume(&self.audio_context, &self.settings.audio);
+
         'outer: loop {
 
-            let begin_time = ::std::time::Instant::now();
             let mut input_events = Vec::<GameInputEvent>::new();
 
             // updates the game state, by using the UI of the previous frame as a reference
@@ -163,10 +230,18 @@ impl Game {
                 previous_mouse_cursor_type = current_mouse_cursor_type;
             }
 
-            // update the audio, change song if needed. the audio module only changes the song if it has changed
-            self.audio_context.send_msg(self.game_state.get_song())
-            .map_err(|e| { println!("could not send new song: {:}", e); })
-            .unwrap_or(());
+            // pause the audio while the window is in the background
+            let should_pause = self.settings.audio.pause_when_unfocused && !self.renderer.window_state.focused;
+            if should_pause != self.audio_paused {
+                let command = if should_pause { AudioCommand::Pause } else { AudioCommand::Resume };
+                self.audio_context.send_msg(command)
+                .map_err(|e| { println!("could not pause / resume audio: {:}", e); })
+                .unwrap_or(());
+                self.audio_paused = should_pause;
+            }
+
+            // recompile shaders that were edited since the last frame
+            self.shader_reloader.reload_changed(&self.renderer.context.display, &mut self.renderer.context.shader_programs);
 
             // the GameState generates the UI
             let mut current_frame_ui = self.game_state.get_ui();
@@ -178,53 +253,188 @@ impl Game {
                 texture_ids: &self.available_texture_ids
             };
 
+            let mut tension = 0.0;
+
             match self.game_state {
                 GameState::StartMenu => {
-                    show_start_menu(&mut game_frame, self.renderer.context.display.get_context(),
-                                    &self.renderer.context.shader_programs, &mut current_frame_ui);
+                    show_start_menu(&mut game_frame, &mut current_frame_ui);
                 },
                 GameState::Game(ref mut player_state) => {
                     player_state.camera.screen_width = self.renderer.window_state.width as f32;
                     player_state.camera.screen_height = self.renderer.window_state.height as f32;
 
                     let world_finalized = player_state.finalize(input_events);
-                    show_game(&mut game_frame, self.renderer.context.display.get_context(),
-                              &self.renderer.context.shader_programs,
-                              &world_finalized, &player_state.camera);
+                    show_game(&mut game_frame, &world_finalized, &player_state.camera);
+
+                    self.spatial_audio.update(&self.audio_context, &player_state.camera, &world_finalized.player_position,
+                                              &world_finalized.crates, &world_finalized.events);
+                    // the ending song plays at its normal speed
+                    tension = if player_state.game_over { 0.0 } else { world_finalized.tower_wobble };
                 }
             }
 
+            // update the audio, change song if needed. On game over, the music
+            // fades out completely before the ending song starts
+            let song = self.game_state.get_song();
+
+            if self.current_song != Some(song) {
+                let crossfade = self.settings.music_crossfade;
+                // switching between the level songs is on the beat, so it sounds like one song
+                let switch_on_beat = song.is_level_song() && self.current_song.map(|s| s.is_level_song()).unwrap_or(false);
+                let transition = if song == SongId::Ending {
+                    AudioCommand::FadeOutAndPlay(song, ::std::time::Duration::from_millis(::audio::GAME_OVER_FADE_OUT_MS))
+                } else if switch_on_beat {
+                    AudioCommand::SwitchAtBar(song, crossfade)
+                } else {
+                    AudioCommand::FadeTo(song, crossfade)
+                };
+
+                self.audio_context.send_msg(transition)
+                .map_err(|e| { println!("could not send new song: {:}", e); })
+                .unwrap_or(());
+                self.current_song = Some(song);
+            }
+
+            // only send noticeable changes, the tension changes a little bit every frame
+            if (tension - self.music_tension).abs() >= MUSIC_TENSION_STEP || (tension <= 0.0 && self.music_tension > 0.0) {
+                self.audio_context.send_msg(AudioCommand::SetMusicTension(tension))
+                .map_err(|e| { println!("could not send music tension: {:}", e); })
+                .unwrap_or(());
+                self.music_tension = tension;
+            }
+
+            game_frame.apply_post_processing(&self.settings.post_processing);
+
+            // screenshots and GIF recording
+            let hotkeys = self.renderer.window_state.take_hotkeys();
+            let take_screenshot = hotkeys.contains(&Hotkey::TakeScreenshot);
+            let now = ::std::time::Instant::now();
+
+            // reading the pixels back stalls the GPU, so only the gameplay is recorded
+            let record_frame = match self.game_state {
+                GameState::Game(_) => self.frame_recorder.wants_frame(now),
+                GameState::StartMenu => false,
+            };
+
+            if take_screenshot || record_frame {
+                let image = game_frame.read_pixels();
+                if take_screenshot {
+                    let file_name = ::capture::timestamped_file_name("screenshot", "png");
+                    match ::capture::save_screenshot(&image, &file_name) {
+                        Ok(()) => println!("saved screenshot to {}", file_name),
+                        Err(e) => println!("could not save screenshot: {}", e),
+                    }
+                }
+                if record_frame {
+                    self.frame_recorder.push_frame(&image, now);
+                }
+            }
+
+            // drawn after the capture, so that the errors don't end up in screenshots and GIFs
+            if let Some(message) = self.shader_reloader.error_message() {
+                draw_shader_errors(&mut game_frame, &message);
+            }
+
             game_frame.drop();
+
+            if hotkeys.contains(&Hotkey::ToggleMute) {
+                change_audio_settings(&self.audio_context, &mut self.settings.audio, |audio| audio.muted = !audio.muted);
+            }
+
+            if hotkeys.contains(&Hotkey::VolumeUp) {
+                change_audio_settings(&self.audio_context, &mut self.settings.audio, |audio| audio.change_master_volume(::settings::VOLUME_STEP));
+            }
+
+            if hotkeys.contains(&Hotkey::VolumeDown) {
+                change_audio_settings(&self.audio_context, &mut self.settings.audio, |audio| audio.change_master_volume(-::settings::VOLUME_STEP));
+            }
+
+            for hotkey in hotkeys.iter() {
+                if let Hotkey::ToggleEffect(effect) = *hotkey {
+                    self.settings.post_processing.toggle(effect);
+                    if let Err(e) = self.settings.post_processing.save(::settings::GRAPHICS_CONFIG_FILE) {
+                        println!("could not save graphics settings: {}", e);
+                    }
+                }
+            }
+
+            if hotkeys.contains(&Hotkey::NextFrameRateCap) {
+                self.change_frame_rate_cap();
+            }
+
+            if hotkeys.contains(&Hotkey::NextSkin) {
+                if let GameState::Game(ref mut player_state) = self.game_state {
+                    player_state.player_skin = (player_state.player_skin + 1) % ::assets::HERO_PALETTE.variant_count();
+                }
+            }
+
+            if hotkeys.contains(&Hotkey::NextLevel) {
+                if let GameState::Game(ref mut player_state) = self.game_state {
+                    let levels = ::assets::LEVELS;
+                    let current = levels.iter().position(|l| l.name == player_state.level.name).unwrap_or(0);
+                    player_state.level = levels[(current + 1) % levels.len()];
+                }
+            }
+
+            if hotkeys.contains(&Hotkey::ExportGif) {
+                self.frame_recorder.export_gif(::capture::timestamped_file_name("recording", "gif"));
+            }
             self.renderer.context.texture_system.highest_texture.set(0.99);
             previous_frame_ui = current_frame_ui;
-            ::std::thread::sleep(::std::time::Duration::from_millis(16));
+            self.frame_limiter.end_frame();
         }
     }
 }
 
+/// Sends the volume levels to the audio thread
+fn send_audio_volume(audio_context: &AudioContext, audio: &AudioSettings) {
+    audio_context.send_msg(AudioCommand::SetVolume {
+        master: audio.effective_master_volume(),
+        music: audio.music_volume,
+        sfx: audio.sfx_volume,
+    })
+    .map_err(|e| { println!("could not send volume: {:}", e); })
+    .unwrap_or(());
+}
+
+/// Changes the audio settings, applies them and saves them to the config file
+fn change_audio_settings<F>(audio_context: &AudioContext, audio: &mut AudioSettings, change: F) where F: FnOnce(&mut AudioSettings) {
+    change(audio);
+    send_audio_volume(audio_context, audio);
+    if let Err(e) = audio.save(::settings::AUDIO_CONFIG_FILE) {
+        println!("could not save audio settings: {}", e);
+    }
+}
+
 /// Draw the start menu
-fn show_start_menu(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHashMap, ui: &mut Ui)
+pub fn show_start_menu<B: RenderBackend>(frame: &mut B, ui: &mut Ui)
 {
-    use glium::Surface;
-    use texture::TargetPixelRegion;
+    use texture::{TargetPixelRegion, NineSliceInstance};
     use ui::{Ui, UiRect, UiRendererData, UiActions};
     use texture::TextureDrawOptions;
 
     frame.clear_screen(Color::light_blue());
 
-    let big_font = frame.get_font(FONT_BIG_ID);
-    let medium_font = frame.get_font(FONT_MEDIUM_ID);
-    let small_font = frame.get_font(FONT_SMALL_ID);
+    let (w, h) = frame.dimensions();
+
+    // fonts grow and shrink with the window
+    let ui_scale = (h as f32 / UI_REFERENCE_HEIGHT).max(0.5);
+    let big_font = frame.get_font(FONT_BIG_ID).scaled(ui_scale);
+    let medium_font = frame.get_font(FONT_MEDIUM_ID).scaled(ui_scale);
+    let small_font = frame.get_font(FONT_SMALL_ID).scaled(ui_scale);
 
-    let (w, h) = frame.frame.get_dimensions();
     let center_w = w as f32 / 2.0;
     let center_h = h as f32 / 2.0;
 
     draw_text_with_shadow(frame, ::assets::GAME_TITLE, &big_font, 0.3, center_w, 2);
     draw_text_with_shadow(frame, "Ludum Dare 40", &small_font, 0.85, center_w, 1);
 
-    let start_game_button_width = 190.0; // px
-    let start_game_button_height = 49.0; // px
+    // the button is a nine-slice image, so it can be as wide as the text needs
+    let start_game_text = "Start Game";
+    let font_width = frame.calculate_font_width(&medium_font, start_game_text);
+    let start_game_button_padding = 40.0 * ui_scale; // px, left and right of the text
+    let start_game_button_width = (font_width + 2.0 * start_game_button_padding).round();
+    let start_game_button_height = (49.0 * ui_scale).round(); // px, grows with the font
     let half_start_game_button_width  = start_game_button_width  / 2.0;
     let half_start_game_button_height = start_game_button_height / 2.0;
 
@@ -248,73 +458,108 @@ fn show_start_menu(frame: &mut GameFrame, display: &Rc<Context>, shaders: &Shade
 
         start_btn.x = button_arr_x;
         start_btn.y = button_arr_y;
-        start_btn.data.image = Some(TextureInstanceId {
-            source_texture_region: ::assets::START_SCREEN_BUTTON_00_TX_STR,
+        start_btn.data.nine_slice_image = Some(NineSliceInstance {
+            nine_slice: ::assets::START_SCREEN_BUTTON_00_NINE_SLICE,
             target_texture_region: start_button_target_pixel_region,
         });
     }
 
     for rect in ui.rectangles.iter_mut() {
         if let Some(ref texture_instance_id) = rect.data.image {
-            frame.draw_texture(display, &texture_instance_id, 1.0, shaders, TextureDrawOptions::default());
+            frame.draw_texture(&texture_instance_id, &SpriteDrawParameters::default(), TextureDrawOptions::default());
+        }
+        if let Some(ref nine_slice_instance) = rect.data.nine_slice_image {
+            frame.draw_nine_slice(&nine_slice_instance, &SpriteDrawParameters::default(), TextureDrawOptions::default());
         }
     }
 
-    let start_game_text = "Start Game";
-    let font_width = frame.calculate_font_width(&medium_font, start_game_text);
-    let text = Text {
-        font: &medium_font,
-        text: start_game_text,
-        screen_x: (center_w as u32) - ((font_width / 2.0) as u32),
-        screen_y: (center_h as u32) - (medium_font.font_size / 2)
+    // vertically centered on the button
+    let text_layout = TextLayout {
+        x: left,
+        top: center_h + (medium_font.font_size / 2) as f32,
+        width: start_game_button_width,
+        alignment: TextAlignment::Center,
+        .. Default::default()
     };
-    frame.draw_font(&text, Color::black());
+    frame.draw_text_layout(&medium_font, &[TextSpan::new(start_game_text, Color::black())], &text_layout);
 }
 
-fn draw_text_with_shadow(frame: &mut GameFrame, text: &str, font: &FontInstanceId,
-                                  offset_y: f32, offset_x: f32, shadow_offset: u32)
+fn draw_text_with_shadow<B: RenderBackend>(frame: &mut B, text: &str, font: &FontInstanceId,
+                                           offset_y: f32, offset_x: f32, shadow_offset: i32)
 {
-    use glium::Surface;
+    // text is centered around offset_x, offset_y is the fraction of the screen height from the top
+    let (w, h) = frame.dimensions();
+    let screen_y = h as f32 - (offset_y * h as f32).floor();
+
+    let layout = TextLayout {
+        x: offset_x - w as f32 / 2.0,
+        top: screen_y + font.font_size as f32,
+        width: w as f32,
+        alignment: TextAlignment::Center,
+        style: TextStyle::Shadow { offset_x: shadow_offset, offset_y: -shadow_offset * 2, color: Color::black() },
+        .. Default::default()
+    };
 
-    // calculate centered position of the text and draw text
-    let (w, h) = frame.frame.get_dimensions();
-    let font_width = frame.calculate_font_width(&font, text);
-    let screen_x = (offset_x - (font_width / 2.0)) as u32;
-    let screen_y = h - (offset_y * h as f32) as u32;
+    frame.draw_text_layout(font, &[TextSpan::new(text, Color::white())], &layout);
+}
 
-    let text = Text { font: &font, text: text, screen_x: screen_x, screen_y: screen_y };
+/// Shows shader compile errors on top of everything (after the post-processing)
+fn draw_shader_errors<B: RenderBackend>(frame: &mut B, message: &str)
+{
+    let (w, h) = frame.dimensions();
+    let small_font = frame.get_font(FONT_SMALL_ID);
 
-    let mut shadow_text = text.clone();
-    shadow_text.screen_x += shadow_offset;
-    shadow_text.screen_y -= shadow_offset * 2;
+    let layout = TextLayout {
+        x: 10.0,
+        top: h as f32 - 10.0,
+        width: w as f32 - 20.0,
+        wrap: true,
+        style: TextStyle::Outline { width: 1, color: Color::black() },
+        .. Default::default()
+    };
 
-    frame.draw_font(&shadow_text, Color::black());
-    frame.draw_font(&text, Color::white());
+    frame.draw_text_layout(&small_font, &[TextSpan::new(message, Color { r: 255, g: 80, b: 80, a: 255 })], &layout);
 }
 
 // --- draw game
 
 // Draw the actual game
-fn show_game(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHashMap,
-             game_finalized_data: &PhysicsFinalizedData, camera: &Camera)
+pub fn show_game<B: RenderBackend>(frame: &mut B, game_finalized_data: &PhysicsFinalizedData, camera: &Camera)
 {
-    use glium::Surface;
-
     frame.clear_screen(Color::light_blue());
-    draw_background(frame, display, shaders, game_finalized_data);
-    draw_highscore(frame, display, shaders, game_finalized_data);
-    draw_crates(frame, display, shaders, game_finalized_data);
-    draw_character(frame, display, shaders, game_finalized_data);
+    draw_background(frame, game_finalized_data);
+
+    // in lit levels, the highscore is drawn after the lighting, so that it isn't darkened
+    if game_finalized_data.lighting.is_none() {
+        draw_highscore(frame, game_finalized_data);
+    }
+
+    draw_crates(frame, game_finalized_data);
+    draw_character(frame, game_finalized_data);
+    draw_particles(frame, game_finalized_data);
+
+    if let Some(ref lighting) = game_finalized_data.lighting {
+        frame.draw_lighting(lighting);
+        draw_highscore(frame, game_finalized_data);
+    }
 
+    if let PlayerResult::PlayerHasLost = game_finalized_data.result {
+        draw_game_over(frame);
+    }
+}
+
+fn draw_game_over<B: RenderBackend>(frame: &mut B)
+{
+    let (w, _) = frame.dimensions();
+    let big_font = frame.get_font(FONT_BIG_ID);
+    draw_text_with_shadow(frame, "Game Over", &big_font, 0.3, w as f32 / 2.0, 2);
 }
 
-fn draw_background(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHashMap,
-                   game_finalized_data: &PhysicsFinalizedData)
+fn draw_background<B: RenderBackend>(frame: &mut B, game_finalized_data: &PhysicsFinalizedData)
 {
     use texture::{TargetPixelRegion, TextureDrawOptions};
-    use glium::Surface;
 
-    let (w, h) = frame.frame.get_dimensions();
+    let (w, h) = frame.dimensions();
 
     let max = w.max(h);
     let min = w.min(h);
@@ -333,14 +578,15 @@ fn draw_background(frame: &mut GameFrame, display: &Rc<Context>, shaders: &Shade
         target_texture_region: background_sprite_region,
     };
 
-    frame.draw_texture(display, &texture_instance_id, 1.0, shaders, TextureDrawOptions::PixelPerfect);
+    frame.draw_texture(&texture_instance_id, &SpriteDrawParameters::default(), TextureDrawOptions::PixelPerfect);
 }
 
-fn draw_crates(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHashMap,
-               game_finalized_data: &PhysicsFinalizedData)
+fn draw_crates<B: RenderBackend>(frame: &mut B, game_finalized_data: &PhysicsFinalizedData)
 {
     use texture::{TargetPixelRegion, TextureDrawOptions};
 
+    let mut sprites = Vec::with_capacity(game_finalized_data.crates.len());
+
     for crate_box in &game_finalized_data.crates {
 
         let crate_sprite_region = TargetPixelRegion {
@@ -356,12 +602,20 @@ fn draw_crates(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHas
             target_texture_region: crate_sprite_region,
         };
 
-        frame.draw_texture(display, &texture_instance_id, 1.0, shaders, TextureDrawOptions::PixelPerfect);
+        let sprite_parameters = SpriteDrawParameters {
+            rotation: crate_box.rotation,
+            palette_swap: ::assets::CRATE_PALETTE.variant(crate_box.palette_variant),
+            .. Default::default()
+        };
+
+        sprites.push((texture_instance_id, sprite_parameters));
     }
+
+    // all crates are on the same atlas page, so this is one draw call
+    frame.draw_texture_batch(&sprites, TextureDrawOptions::PixelPerfect);
 }
 
-fn draw_character(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHashMap,
-                  game_finalized_data: &PhysicsFinalizedData)
+fn draw_character<B: RenderBackend>(frame: &mut B, game_finalized_data: &PhysicsFinalizedData)
 {
     use texture::{TargetPixelRegion, TextureDrawOptions};
 
@@ -373,16 +627,89 @@ fn draw_character(frame: &mut GameFrame, display: &Rc<Context>, shaders: &Shader
     };
 
     let texture_instance_id = TextureInstanceId {
-        // TODO: set character state (side, flying, etc.) here
-        source_texture_region: ::assets::HERO_TX_NORMAL_STR,
+        source_texture_region: game_finalized_data.player_sprite,
         target_texture_region: player_sprite_region,
     };
 
-    frame.draw_texture(display, &texture_instance_id, 1.0, shaders, TextureDrawOptions::PixelPerfect);
+    let sprite_parameters = SpriteDrawParameters {
+        flip_x: game_finalized_data.player_sprite_flipped,
+        palette_swap: ::assets::HERO_PALETTE.variant(game_finalized_data.player_skin),
+        .. Default::default()
+    };
+
+    frame.draw_texture(&texture_instance_id, &sprite_parameters, TextureDrawOptions::PixelPerfect);
+}
+
+fn draw_particles<B: RenderBackend>(frame: &mut B, game_finalized_data: &PhysicsFinalizedData)
+{
+    use texture::{TargetPixelRegion, TextureDrawOptions};
+
+    let (w, h) = frame.dimensions();
+    let mut sprites = Vec::with_capacity(game_finalized_data.particles.len());
+
+    for particle in &game_finalized_data.particles {
+        let size = particle.current_size();
+        let color = particle.current_color();
+        let left = (particle.x - size / 2.0).floor() as i32;
+        let bottom = (particle.y - size / 2.0).floor() as i32;
+        let size = size as i32;
+
+        // the target region can't start at a negative position, so the part of a particle
+        // that crosses the left or bottom edge is cut off, from the target and the source region
+        let cut_left = (-left).max(0);
+        let cut_bottom = (-bottom).max(0);
+
+        // skip particles that have left the screen
+        if cut_left >= size || cut_bottom >= size || left >= w as i32 || bottom >= h as i32 {
+            continue;
+        }
+
+        let particle_sprite_region = TargetPixelRegion {
+            screen_bottom_x: (left + cut_left) as u32,
+            screen_bottom_y: (bottom + cut_bottom) as u32,
+            screen_width: (size - cut_left) as u32,
+            screen_height: (size - cut_bottom) as u32,
+        };
+
+        let mut source_texture_region = particle.texture_region;
+        {
+            let source = &mut source_texture_region.region;
+            let source_cut_x = ((cut_left * source.width as i32) as f32 / size as f32).round() as u32;
+            let source_cut_y = ((cut_bottom * source.height as i32) as f32 / size as f32).round() as u32;
+            let source_cut_x = source_cut_x.min(source.width.saturating_sub(1));
+            let source_cut_y = source_cut_y.min(source.height.saturating_sub(1));
+            source.bottom_x += source_cut_x;
+            source.bottom_y += source_cut_y;
+            source.width -= source_cut_x;
+            source.height -= source_cut_y;
+        }
+
+        let texture_instance_id = TextureInstanceId {
+            source_texture_region: source_texture_region,
+            target_texture_region: particle_sprite_region,
+        };
+
+        // cut off particles still rotate around the center of the whole particle
+        let pivot = (
+            (size as f32 / 2.0 - cut_left as f32) / particle_sprite_region.screen_width as f32,
+            (size as f32 / 2.0 - cut_bottom as f32) / particle_sprite_region.screen_height as f32,
+        );
+
+        let sprite_parameters = SpriteDrawParameters {
+            tint: Color { a: 255, .. color },
+            transparency: color.a as f32 / 255.0,
+            rotation: particle.rotation,
+            pivot: pivot,
+            .. Default::default()
+        };
+
+        sprites.push((texture_instance_id, sprite_parameters));
+    }
+
+    frame.draw_texture_batch(&sprites, TextureDrawOptions::PixelPerfect);
 }
 
-fn draw_highscore(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHashMap,
-                  game_finalized_data: &PhysicsFinalizedData)
+fn draw_highscore<B: RenderBackend>(frame: &mut B, game_finalized_data: &PhysicsFinalizedData)
 {
     let score = format!("{:.2}", game_finalized_data.highscore);
     let initial_floor_height = 25.0;
@@ -391,84 +718,58 @@ fn draw_highscore(frame: &mut GameFrame, display: &Rc<Context>, shaders: &Shader
     let height_in_screen_pixels = ((game_finalized_data.highscore) + initial_floor_height) as u32;
     let font_offset = 25;
 
-    frame.draw_font(&Text { font: &big_font, text: &score, screen_x: 25 + 2, screen_y: height_in_screen_pixels  + font_offset - 4 }, Color::black());
-    frame.draw_font(&Text { font: &big_font, text: &score, screen_x: 25, screen_y: height_in_screen_pixels + font_offset }, Color::white());
+    let layout = TextLayout {
+        x: 25.0,
+        top: (height_in_screen_pixels + font_offset + big_font.font_size) as f32,
+        style: TextStyle::Shadow { offset_x: 2, offset_y: -4, color: Color::black() },
+        .. Default::default()
+    };
+    frame.draw_text_layout(&big_font, &[TextSpan::new(&score, Color::white())], &layout);
 
-    draw_highscore_line(frame, display, height_in_screen_pixels, shaders);
+    draw_highscore_line(frame, height_in_screen_pixels);
 }
 
-fn draw_ground(frame: &mut GameFrame, display: &Rc<Context>, shaders: &ShaderHashMap)
+fn draw_ground<B: RenderBackend>(frame: &mut B)
 {
 
 }
 
-fn draw_highscore_line(frame: &mut GameFrame, display: &Rc<Context>, line_height: u32, shaders: &ShaderHashMap)
+fn draw_highscore_line<B: RenderBackend>(frame: &mut B, line_height: u32)
 {
-    use glium::Surface;
-    use glium::DrawParameters;
-    use texture::PixelScreenVert;
-    use glium::VertexBuffer;
+    use shapes::{ShapeBatch, LineCap};
 
     // 100 pixel = 10 points in height of the highscore line
-    // line is drawn using GL_LINES
-    let (w, h) = frame.frame.get_dimensions();
-
-    let mut verts_a = Vec::with_capacity(20);
-    let mut verts_b = Vec::with_capacity(20);
-
-    let mut x_val = 10;
-    while x_val < w {
-        verts_a.push(PixelScreenVert {
-            position:   [(x_val + 2) as f32,
-                         line_height as f32,
-                         0.2],
-            tex_coords: [0.0, 0.0]
-        });
-        verts_b.push(PixelScreenVert {
-            position:   [x_val as f32,
-                         (line_height + 2) as f32,
-                         0.2],
-            tex_coords: [0.0, 0.0]
-        });
-        x_val += 40;
+    let (w, _) = frame.dimensions();
+    let line_height = line_height as f32;
+    let w = w as f32;
 
-        verts_a.push(PixelScreenVert {
-            position:   [(x_val + 2) as f32,
-                         line_height as f32,
-                         0.2],
-            tex_coords: [0.0, 0.0]
-        });
-        verts_b.push(PixelScreenVert {
-            position:   [x_val as f32,
-                         (line_height + 2) as f32,
-                         0.2],
-            tex_coords: [0.0, 0.0]
-        });
-        x_val += 20;
-    }
-
-    let vbuf_a = VertexBuffer::new(display, &verts_a).unwrap();
-    let vbuf_b = VertexBuffer::new(display, &verts_b).unwrap();
+    // dark shadow first, then the light dashes slightly above it
+    let mut shapes = ShapeBatch::new();
+    shapes.dashed_line(12.0, line_height, w, line_height, 40.0, 20.0, 9.0, LineCap::Butt, Color::black());
+    shapes.dashed_line(10.0, line_height + 2.0, w, line_height + 2.0, 40.0, 20.0, 9.0, LineCap::Butt,
+                       Color { r: 230, g: 230, b: 230, a: 255 });
 
-    let draw_parameters = DrawParameters {
-        line_width: Some(9.0),
-        .. Default::default()
-    };
-
-    let uniforms = uniform!{
-        window_width: w as f32,
-        window_height: h as f32,
-        in_color: [0.0_f32, 0.0, 0.0, 1.0],
-    };
-
-    let program = shaders.get(::context::PIXEL_TO_SCREEN_SHADER_LINE_ONLY_ID).unwrap();
-    frame.frame.draw(&vbuf_a, ::context::NO_INDICES_BUFFER_LINE, program, &uniforms, &draw_parameters).unwrap();
+    frame.draw_shapes(&shapes);
+}
 
-    let uniforms = uniform!{
-        window_width: w as f32,
-        window_height: h as f32,
-        in_color: [0.9_f32, 0.9, 0.9, 1.0],
-    };
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn game_over_lasts_and_plays_the_ending_song() {
+        let mut player_state = PlayerState::default();
+        player_state.best_stack_height = MUSIC_LEVEL_3_HEIGHT;
+        player_state.game_over = true;
+
+        // the flag isn't reset by playing on
+        for _ in 0..2 {
+            match player_state.finalize(Vec::new()).result {
+                PlayerResult::PlayerHasLost => { },
+                other => panic!("expected the game to be over, got {:?}", other),
+            }
+        }
 
-    frame.frame.draw(&vbuf_b, ::context::NO_INDICES_BUFFER_LINE, program, &uniforms, &draw_parameters).unwrap();
+        assert_eq!(GameState::Game(Box::new(player_state)).get_song(), SongId::Ending);
+    }
 }
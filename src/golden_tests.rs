@@ -0,0 +1,120 @@
+//! Renders the game screens with the `SoftwareRenderer` and compares them with
+//! reference images in `tests/golden`.
+//!
+//! After an intended change to the rendering, run the tests with
+//! `UPDATE_GOLDEN_IMAGES=1 cargo test` and check the new images in.
+
+use image::{self, RgbaImage};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use camera::Camera;
+use game::{self, GameState};
+use physics::{CratePosition, PhysicsFinalizedData, PlayerResult};
+use player_state::PlayerSpritePosition;
+use software_renderer::SoftwareRenderer;
+
+const GOLDEN_WIDTH: u32 = 400;
+const GOLDEN_HEIGHT: u32 = 300;
+
+/// How much a channel may differ, i.e. because of float rounding on another platform
+const CHANNEL_TOLERANCE: u8 = 2;
+/// How many pixels may differ by more than `CHANNEL_TOLERANCE`
+const MAX_DIFFERING_PIXELS: usize = 0;
+
+fn golden_path(name: &str) -> PathBuf {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("golden");
+    path.push(format!("{}.png", name));
+    path
+}
+
+/// Compares the buffer with `tests/golden/<name>.png`. Writes the buffer to
+/// `<name>.actual.png` next to it if they differ, so that the difference can be looked at
+fn assert_matches_golden(renderer: &SoftwareRenderer, name: &str) {
+    let path = golden_path(name);
+
+    if env::var("UPDATE_GOLDEN_IMAGES").is_ok() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        renderer.save_png(&path).unwrap();
+        return;
+    }
+
+    let golden: RgbaImage = match image::open(&path) {
+        Ok(golden) => golden.to_rgba(),
+        Err(e) => panic!("could not open golden image {:?} ({}), run with UPDATE_GOLDEN_IMAGES=1 to create it", path, e),
+    };
+
+    let differing = renderer.count_differing_pixels(&golden, CHANNEL_TOLERANCE);
+    if differing.map(|d| d > MAX_DIFFERING_PIXELS).unwrap_or(true) {
+        let actual_path = path.with_extension("actual.png");
+        renderer.save_png(&actual_path).unwrap();
+        panic!("{} does not match its golden image: {:?} differing pixels, see {:?}", name, differing, actual_path);
+    }
+}
+
+fn crate_at(x: f32, y: f32, palette_variant: usize, id: u32) -> CratePosition {
+    CratePosition {
+        x: x,
+        y: y,
+        width: ::physics::CRATE_SIZE,
+        height: ::physics::CRATE_SIZE,
+        rotation: 0.0,
+        palette_variant: palette_variant,
+        id: id,
+    }
+}
+
+/// A small tower next to the player, no particles or lighting (those depend on the time)
+fn game_scene(result: PlayerResult) -> PhysicsFinalizedData {
+    PhysicsFinalizedData {
+        player_position: PlayerSpritePosition { x: 60.0, y: 0.0, width: 100.0, height: 100.0 },
+        player_sprite: ::assets::HERO_TX_NORMAL_STR,
+        player_sprite_flipped: false,
+        player_skin: 0,
+        crates: vec![
+            crate_at(200.0, 0.0, 0, 0),
+            crate_at(232.0, 0.0, 1, 1),
+            crate_at(216.0, 32.0, 2, 2),
+        ],
+        result: result,
+        highscore: 64.0,
+        tower_wobble: 0.0,
+        particles: Vec::new(),
+        events: Vec::new(),
+        lighting: None,
+    }
+}
+
+fn camera() -> Camera {
+    Camera {
+        x: 0.0,
+        y: 0.0,
+        screen_width: GOLDEN_WIDTH as f32,
+        screen_height: GOLDEN_HEIGHT as f32,
+    }
+}
+
+#[test]
+fn start_menu_matches_golden_image() {
+    let mut renderer = SoftwareRenderer::with_game_assets(GOLDEN_WIDTH, GOLDEN_HEIGHT);
+    let mut ui = GameState::StartMenu.get_ui();
+    game::show_start_menu(&mut renderer, &mut ui);
+    assert_matches_golden(&renderer, "start_menu");
+}
+
+#[test]
+fn game_matches_golden_image() {
+    let mut renderer = SoftwareRenderer::with_game_assets(GOLDEN_WIDTH, GOLDEN_HEIGHT);
+    game::show_game(&mut renderer, &game_scene(PlayerResult::PlayerOk), &camera());
+    assert_matches_golden(&renderer, "game");
+}
+
+#[test]
+fn game_over_matches_golden_image() {
+    let mut renderer = SoftwareRenderer::with_game_assets(GOLDEN_WIDTH, GOLDEN_HEIGHT);
+    game::show_game(&mut renderer, &game_scene(PlayerResult::PlayerHasLost), &camera());
+    assert_matches_golden(&renderer, "game_over");
+}
@@ -6,6 +6,7 @@ use glium::glutin::{Event, MouseCursor, ElementState,VirtualKeyCode, MouseButton
 use std::time::{Duration, Instant};
 use ui::{Ui, UiRect, UiRendererData};
 use game::GameState;
+use post_processing::PostProcessingEffect;
 
 pub enum GameInputEvent {
     PlayerJump,
@@ -15,6 +16,27 @@ pub enum GameInputEvent {
     PlayerTakeBox,
 }
 
+/// Global hotkeys, handled by the game loop regardless of the game state
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Hotkey {
+    /// Save the current frame as a PNG file
+    TakeScreenshot,
+    /// Save the last few seconds as an animated GIF file
+    ExportGif,
+    /// Switch to the next level variant (i.e. day / night)
+    NextLevel,
+    /// Switch to the next color variant of the hero
+    NextSkin,
+    /// Mute / unmute all audio
+    ToggleMute,
+    VolumeUp,
+    VolumeDown,
+    /// Turn a screen effect on or off
+    ToggleEffect(PostProcessingEffect),
+    /// Switch to the next frame rate cap
+    NextFrameRateCap,
+}
+
 /// Determines which keys are pressed currently (modifiers, etc.)
 #[derive(Debug, Clone)]
 pub struct KeyboardState
@@ -25,6 +47,11 @@ pub struct KeyboardState
     pub hidden_keys: Vec<char>,
     /// Actual keys pressed during this cycle (i.e. regular text input)
     pub keys: Vec<char>,
+    /// Global hotkeys pressed since the last frame, drained by the game loop
+    pub hotkeys: Vec<Hotkey>,
+    /// Keys of hotkeys that are held down. Unlike `modifiers`, this is only
+    /// cleared when the key is released, so auto-repeated presses can be ignored
+    pub held_hotkeys: Vec<VirtualKeyCode>,
 }
 
 impl KeyboardState
@@ -35,6 +62,8 @@ impl KeyboardState
             modifiers: Vec::new(),
             hidden_keys: Vec::new(),
             keys: Vec::new(),
+            hotkeys: Vec::new(),
+            held_hotkeys: Vec::new(),
         }
     }
 }
@@ -94,6 +123,8 @@ pub struct WindowState
     pub time_of_last_update: Instant,
     /// Minimum frame time
     pub min_frame_time: Duration,
+    /// If the window is in the foreground
+    pub focused: bool,
 }
 
 impl WindowState
@@ -111,6 +142,7 @@ impl WindowState
             height,
             time_of_last_update: Instant::now(),
             min_frame_time: Duration::from_millis(16),
+            focused: true,
         }
     }
 
@@ -154,6 +186,7 @@ impl WindowState
         focused: bool,
     )
     {
+        self.focused = focused;
         if !focused {
             self.mouse_state.mouse_cursor = None;
         }
@@ -268,11 +301,34 @@ impl WindowState
         let vk_code = vk_code.unwrap();
         if state == ElementState::Pressed {
                 self.keyboard_state.modifiers.push(vk_code);
+                let hotkey = match vk_code {
+                    VirtualKeyCode::F12 => Some(Hotkey::TakeScreenshot),
+                    VirtualKeyCode::F11 => Some(Hotkey::ExportGif),
+                    VirtualKeyCode::F9 => Some(Hotkey::NextLevel),
+                    VirtualKeyCode::F8 => Some(Hotkey::NextSkin),
+                    VirtualKeyCode::F7 => Some(Hotkey::ToggleEffect(PostProcessingEffect::Crt)),
+                    VirtualKeyCode::F6 => Some(Hotkey::ToggleEffect(PostProcessingEffect::Vignette)),
+                    VirtualKeyCode::F5 => Some(Hotkey::ToggleEffect(PostProcessingEffect::ColorGrading)),
+                    VirtualKeyCode::F4 => Some(Hotkey::ToggleEffect(PostProcessingEffect::Bloom)),
+                    VirtualKeyCode::F3 => Some(Hotkey::NextFrameRateCap),
+                    VirtualKeyCode::M => Some(Hotkey::ToggleMute),
+                    VirtualKeyCode::Add | VirtualKeyCode::Equals => Some(Hotkey::VolumeUp),
+                    VirtualKeyCode::Subtract | VirtualKeyCode::Minus => Some(Hotkey::VolumeDown),
+                    _ => None,
+                };
+                // holding a key down sends repeated presses, hotkeys only trigger on the first one
+                if let Some(hotkey) = hotkey {
+                    if !self.keyboard_state.held_hotkeys.contains(&vk_code) {
+                        self.keyboard_state.held_hotkeys.push(vk_code);
+                        self.keyboard_state.hotkeys.push(hotkey);
+                    }
+                }
         } else {
             let indices_found = self.keyboard_state.modifiers.iter().position(|e| *e == vk_code);
             if let Some(index) = indices_found {
                 self.keyboard_state.modifiers.remove(index);
             }
+            self.keyboard_state.held_hotkeys.retain(|e| *e != vk_code);
         }
     }
 
@@ -369,6 +425,11 @@ impl WindowState
         false
     }
 
+    /// Returns the hotkeys pressed since the last call
+    pub fn take_hotkeys(&mut self) -> Vec<Hotkey> {
+        ::std::mem::replace(&mut self.keyboard_state.hotkeys, Vec::new())
+    }
+
     /// Update the WASD keys
     pub fn update_game_state_from_kbinput(&mut self, game_state: &mut GameState)
         -> Vec<GameInputEvent>
@@ -412,3 +473,30 @@ pub fn check_point_in_rect(
     let (right, bottom) = (rect.x[3], rect.y[3]); // bottom right
     (x > left) && (x < right) && (y > bottom) && (y < top)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_hotkey_triggers_once_until_released() {
+        let mut window_state = WindowState::new(800, 600);
+        let mut game_state = GameState::StartMenu;
+
+        // one frame per press, like `handle_event`, which resets the modifiers every time
+        let mut press = |window_state: &mut WindowState, state: ElementState| {
+            window_state.handle_vk_code(&mut game_state, state, Some(VirtualKeyCode::M));
+            window_state.update_game_state_from_kbinput(&mut game_state);
+        };
+
+        press(&mut window_state, ElementState::Pressed);
+        // auto-repeat while the key is held down
+        press(&mut window_state, ElementState::Pressed);
+        press(&mut window_state, ElementState::Pressed);
+        assert_eq!(window_state.take_hotkeys(), vec![Hotkey::ToggleMute]);
+
+        press(&mut window_state, ElementState::Released);
+        press(&mut window_state, ElementState::Pressed);
+        assert_eq!(window_state.take_hotkeys(), vec![Hotkey::ToggleMute]);
+    }
+}
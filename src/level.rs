@@ -0,0 +1,10 @@
+//! Level variants, the playing field is the same, but the look differs
+
+use lighting::LevelLighting;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Level {
+    pub name: &'static str,
+    /// None = daylight, the lighting pass is skipped
+    pub lighting: Option<LevelLighting>,
+}
@@ -0,0 +1,179 @@
+//! 2D lighting for dark levels
+//!
+//! The scene is darkened to the ambient color, point lights brighten it again.
+//! Crates cast hard shadows: everything behind a crate (seen from the light) is not lit by that light.
+
+use color::Color;
+use physics::CratePosition;
+use player_state::PlayerSpritePosition;
+
+/// Render target for adding up the light of all lights
+pub const LIGHT_ACCUMULATION_TARGET_ID: &str = "light_accumulation_target";
+/// Render target for the shadows of the current light (white = lit, black = in the shadow)
+pub const LIGHT_SHADOW_MASK_TARGET_ID: &str = "light_shadow_mask_target";
+
+/// What a light is attached to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LightAnchor {
+    /// Fixed position in the world, in pixels
+    World { x: f32, y: f32 },
+    /// Moves with the player, the offset is from the center of the player sprite
+    Player { offset_x: f32, offset_y: f32 },
+}
+
+/// A light as it is defined in a level
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LevelLight {
+    pub anchor: LightAnchor,
+    /// Distance in pixels at which the light has faded out completely
+    pub radius: f32,
+    pub color: Color,
+    /// Brightness in the center of the light, 1.0 = the full texture color
+    pub intensity: f32,
+}
+
+/// Lighting of a level
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LevelLighting {
+    /// Color of the unlit parts of the scene
+    pub ambient: Color,
+    pub lights: &'static [LevelLight],
+}
+
+/// A light at its current position in the world
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PointLight {
+    pub x: f32,
+    pub y: f32,
+    pub radius: f32,
+    pub color: Color,
+    pub intensity: f32,
+}
+
+/// An axis-aligned box that blocks light
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Occluder {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Occluder {
+    pub fn from_crate(crate_box: &CratePosition) -> Self {
+        Self {
+            x: crate_box.x,
+            y: crate_box.y,
+            width: crate_box.width,
+            height: crate_box.height,
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x > self.x && x < self.x + self.width && y > self.y && y < self.y + self.height
+    }
+
+    /// Returns if the line segment from (x0, y0) to (x1, y1) passes through the box (slab test)
+    pub fn intersects_segment(&self, x0: f32, y0: f32, x1: f32, y1: f32) -> bool {
+        let mut t_min = 0.0_f32;
+        let mut t_max = 1.0_f32;
+
+        let axes = [(x0, x1 - x0, self.x, self.x + self.width), (y0, y1 - y0, self.y, self.y + self.height)];
+
+        for &(start, delta, box_min, box_max) in axes.iter() {
+            if delta.abs() < ::std::f32::EPSILON {
+                if start < box_min || start > box_max {
+                    return false;
+                }
+            } else {
+                let t0 = (box_min - start) / delta;
+                let t1 = (box_max - start) / delta;
+                t_min = t_min.max(t0.min(t1));
+                t_max = t_max.min(t0.max(t1));
+                if t_min > t_max {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+}
+
+/// Everything that is needed to draw the lighting of one frame
+#[derive(Debug, Clone)]
+pub struct LightingFrame {
+    pub ambient: Color,
+    pub lights: Vec<PointLight>,
+    pub occluders: Vec<Occluder>,
+}
+
+impl LevelLighting {
+    /// Moves the lights to the entities they are attached to
+    pub fn resolve(&self, player_position: &PlayerSpritePosition, crates: &[CratePosition]) -> LightingFrame {
+        let lights = self.lights.iter().map(|light| {
+            let (x, y) = match light.anchor {
+                LightAnchor::World { x, y } => (x, y),
+                LightAnchor::Player { offset_x, offset_y } => (
+                    player_position.x + player_position.width / 2.0 + offset_x,
+                    player_position.y + player_position.height / 2.0 + offset_y,
+                ),
+            };
+            PointLight {
+                x: x,
+                y: y,
+                radius: light.radius,
+                color: light.color,
+                intensity: light.intensity,
+            }
+        }).collect();
+
+        LightingFrame {
+            ambient: self.ambient,
+            lights: lights,
+            occluders: crates.iter().map(Occluder::from_crate).collect(),
+        }
+    }
+}
+
+/// Brightness of a light at a distance from its center, 1.0 in the center, 0.0 at the radius.
+/// Same as in `light.frag.glsl`
+pub fn light_falloff(distance: f32, radius: f32) -> f32 {
+    let falloff = (1.0 - distance / radius).max(0.0).min(1.0);
+    falloff * falloff
+}
+
+/// Quads (four corners, counter-clockwise) covering the shadow of the box.
+/// The edges facing away from the light are extruded to `length` pixels from the light
+pub fn shadow_quads(light: &PointLight, occluder: &Occluder, length: f32) -> Vec<[(f32, f32);4]> {
+    // lights inside a box don't cast a shadow
+    if occluder.contains(light.x, light.y) {
+        return Vec::new();
+    }
+
+    let (left, right) = (occluder.x, occluder.x + occluder.width);
+    let (bottom, top) = (occluder.y, occluder.y + occluder.height);
+
+    // edges with their outward normal
+    let edges = [
+        ((left, bottom), (right, bottom), (0.0, -1.0)),
+        ((right, bottom), (right, top), (1.0, 0.0)),
+        ((right, top), (left, top), (0.0, 1.0)),
+        ((left, top), (left, bottom), (-1.0, 0.0)),
+    ];
+
+    let extrude = |(x, y): (f32, f32)| {
+        let (dx, dy) = (x - light.x, y - light.y);
+        let distance = (dx * dx + dy * dy).sqrt().max(0.001);
+        (light.x + dx / distance * length, light.y + dy / distance * length)
+    };
+
+    let mut quads = Vec::new();
+    for &(a, b, (normal_x, normal_y)) in edges.iter() {
+        let facing_light = (light.x - a.0) * normal_x + (light.y - a.1) * normal_y > 0.0;
+        if !facing_light {
+            quads.push([a, b, extrude(b), extrude(a)]);
+        }
+    }
+    quads
+}
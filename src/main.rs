@@ -29,10 +29,11 @@
 
 #[macro_use] extern crate glium;
 extern crate image;
-extern crate glium_text;
 extern crate twox_hash;
 extern crate cpal;
 extern crate lewton;
+extern crate rusttype;
+extern crate gif;
 
 pub mod input;
 pub mod renderer;
@@ -51,6 +52,25 @@ pub mod frame;
 pub mod ui;
 pub mod player_state;
 pub mod actions;
+pub mod post_processing;
+pub mod settings;
+pub mod render_backend;
+pub mod software_renderer;
+pub mod capture;
+pub mod animation;
+pub mod particles;
+pub mod atlas;
+pub mod shapes;
+pub mod lighting;
+pub mod level;
+pub mod shader_reload;
+pub mod frame_limiter;
+pub mod mixer;
+pub mod audio_backend;
+pub mod sfx;
+
+#[cfg(test)]
+mod golden_tests;
 
 pub type FastHashMap<T, U> = ::std::collections::HashMap<T, U, ::std::hash::BuildHasherDefault<::twox_hash::XxHash>>;
 pub type FontInstanceIdMap = FastHashMap<&'static str, font::FontInstanceId>;
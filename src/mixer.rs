@@ -0,0 +1,750 @@
+//! Software mixer for the audio thread
+//!
+//! Sums the music and all playing sound effects into one buffer.
+//! Voices pull their samples from a `SampleSource`, so songs can be decoded while they play.
+//! Each voice is resampled and up- or down-mixed to the format of the output device.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How many sound effects can play at the same time. If more are started, the oldest one is stopped
+pub const MAX_SFX_VOICES: usize = 16;
+
+/// How many frames are read from a source at once
+const SOURCE_CHUNK_FRAMES: usize = 1024;
+
+/// Linear volume ramp, i.e. for fading music in and out
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Fade {
+    pub from: f32,
+    pub to: f32,
+    /// Length of the fade in frames (one sample per channel)
+    pub length: usize,
+    /// How many frames of the fade have been played
+    pub position: usize,
+}
+
+impl Fade {
+
+    pub fn new(from: f32, to: f32, length: usize) -> Self {
+        Self { from: from, to: to, length: length, position: 0 }
+    }
+
+    /// Current volume factor of the fade
+    pub fn gain(&self) -> f32 {
+        if self.position >= self.length {
+            self.to
+        } else {
+            self.from + (self.to - self.from) * (self.position as f32 / self.length as f32)
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position >= self.length
+    }
+}
+
+/// How fast sounds outside of the screen get quieter, per half screen size of distance
+pub const DISTANCE_ROLLOFF: f32 = 1.0;
+
+/// Where the sounds are heard from (the center of the screen), in world coordinates
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Listener {
+    pub x: f32,
+    pub y: f32,
+    /// Half of the visible area. A sound at the left edge of the screen is panned fully to the left
+    pub half_width: f32,
+    pub half_height: f32,
+}
+
+impl Listener {
+    /// Pan (-1.0 - 1.0) and volume factor of a sound at the world position.
+    /// Sounds on the screen have their full volume, sounds outside fade with the distance
+    pub fn spatialize(&self, x: f32, y: f32) -> (f32, f32) {
+        let dx = (x - self.x) / self.half_width.max(1.0);
+        let dy = (y - self.y) / self.half_height.max(1.0);
+        let pan = dx.max(-1.0).min(1.0);
+        let distance = dx.abs().max(dy.abs());
+        let attenuation = 1.0 / (1.0 + DISTANCE_ROLLOFF * (distance - 1.0).max(0.0));
+        (pan, attenuation)
+    }
+}
+
+/// Tempo of a song, for switching songs on bar boundaries
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MusicTempo {
+    pub beats_per_minute: f32,
+    pub beats_per_bar: u32,
+}
+
+impl MusicTempo {
+    /// Length of one bar in frames
+    pub fn bar_length_frames(&self, sample_rate: u32) -> u64 {
+        let seconds_per_bar = 60.0 / self.beats_per_minute * self.beats_per_bar as f32;
+        ((seconds_per_bar * sample_rate as f32) as u64).max(1)
+    }
+}
+
+/// A song that starts when the current song reaches the next bar
+struct PendingSwitch {
+    voice: Voice,
+    /// Tempo of the current song
+    tempo: MusicTempo,
+    /// Bar of the current song in which the switch was requested
+    requested_in_bar: u64,
+    crossfade: Duration,
+}
+
+/// Where the samples of a voice come from
+pub trait SampleSource: Send {
+    /// Writes the next interleaved samples into `buffer`, returns how many were written.
+    /// Less than `buffer.len()` means that the end of the sound was reached
+    fn read(&mut self, buffer: &mut [i16]) -> usize;
+    /// Starts again from the beginning
+    fn rewind(&mut self);
+    /// Number of interleaved channels
+    fn channels(&self) -> usize;
+    /// Frames per second
+    fn sample_rate(&self) -> u32;
+}
+
+/// A sound that is fully decoded in memory, i.e. a sound effect
+#[derive(Debug, Clone)]
+pub struct PcmSource {
+    /// Interleaved samples
+    pub samples: Arc<Vec<i16>>,
+    pub channels: usize,
+    pub sample_rate: u32,
+    /// Index of the next sample to play
+    pub position: usize,
+}
+
+impl PcmSource {
+    pub fn new(samples: Arc<Vec<i16>>, channels: usize, sample_rate: u32) -> Self {
+        Self {
+            samples: samples,
+            channels: channels.max(1),
+            sample_rate: sample_rate,
+            position: 0,
+        }
+    }
+}
+
+impl SampleSource for PcmSource {
+    fn read(&mut self, buffer: &mut [i16]) -> usize {
+        let remaining = &self.samples[self.position.min(self.samples.len())..];
+        let count = remaining.len().min(buffer.len());
+        buffer[..count].copy_from_slice(&remaining[..count]);
+        self.position += count;
+        count
+    }
+
+    fn rewind(&mut self) {
+        self.position = 0;
+    }
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+}
+
+/// A sound that is currently playing
+pub struct Voice {
+    pub source: Box<SampleSource>,
+    /// Start again from the beginning when the end is reached
+    pub looping: bool,
+    /// Set when the source has no more samples
+    pub finished: bool,
+    /// 0.0 = silent, 1.0 = original volume
+    pub volume: f32,
+    /// -1.0 = left, 0.0 = center, 1.0 = right
+    pub pan: f32,
+    /// Volume ramp on top of `volume`, if the voice is fading in or out
+    pub fade: Option<Fade>,
+    /// Playback speed, 1.0 = original speed (higher is also higher pitched)
+    pub speed: f32,
+    /// How many frames have been read from the source, counting from the start of the voice.
+    /// One frame ahead of the played frame, which is interpolated towards the next one
+    pub frames_played: u64,
+    /// Position in the world, pan and distance are calculated from the `Listener`
+    pub position: Option<(f32, f32)>,
+    /// Id for moving the sound after it was started
+    pub handle: Option<u32>,
+    /// Samples that were read from the source, but not played yet
+    source_buffer: Vec<i16>,
+    source_position: usize,
+    /// The two source frames the output is interpolated between
+    previous_frame: Vec<f32>,
+    current_frame: Vec<f32>,
+    /// Position between the previous and the current frame, 0.0 - 1.0
+    fraction: f64,
+    started: bool,
+    /// The source has no more frames, the current frame is the last one
+    source_ended: bool,
+}
+
+impl Voice {
+
+    pub fn new(source: Box<SampleSource>, looping: bool) -> Self {
+        Self {
+            source: source,
+            looping: looping,
+            finished: false,
+            volume: 1.0,
+            pan: 0.0,
+            fade: None,
+            speed: 1.0,
+            frames_played: 0,
+            position: None,
+            handle: None,
+            source_buffer: Vec::new(),
+            source_position: 0,
+            previous_frame: Vec::new(),
+            current_frame: Vec::new(),
+            fraction: 0.0,
+            started: false,
+            source_ended: false,
+        }
+    }
+
+    pub fn with_volume(self, volume: f32) -> Self {
+        Self { volume: volume, .. self }
+    }
+
+    pub fn with_pan(self, pan: f32) -> Self {
+        Self { pan: pan.max(-1.0).min(1.0), .. self }
+    }
+
+    /// Places the sound in the world, `handle` is for moving it later
+    pub fn at_position(self, x: f32, y: f32, handle: Option<u32>) -> Self {
+        Self { position: Some((x, y)), handle: handle, .. self }
+    }
+
+    pub fn with_fade(self, fade: Fade) -> Self {
+        Self { fade: Some(fade), .. self }
+    }
+
+    /// Fades from the current fade volume to silence, the voice is finished afterwards
+    pub fn fade_out(&mut self, length: usize) {
+        let current = self.fade.map(|f| f.gain()).unwrap_or(1.0);
+        self.fade = Some(Fade::new(current, 0.0, length));
+    }
+
+    pub fn is_finished(&self) -> bool {
+        let faded_out = self.fade.map(|f| f.is_done() && f.to <= 0.0).unwrap_or(false);
+        faded_out || self.finished
+    }
+
+    /// Reads the next chunk from the source, starts again from the beginning if the voice is looping.
+    /// Returns false if the source has no more samples
+    fn refill_source_buffer(&mut self) -> bool {
+        let channels = self.source.channels().max(1);
+        let chunk_len = channels * SOURCE_CHUNK_FRAMES;
+        self.source_buffer.resize(chunk_len, 0);
+
+        let mut written = self.source.read(&mut self.source_buffer);
+        if written < chunk_len && self.looping {
+            self.source.rewind();
+            written += self.source.read(&mut self.source_buffer[written..]);
+        }
+
+        // only keep full frames
+        self.source_buffer.truncate(written - written % channels);
+        self.source_position = 0;
+        !self.source_buffer.is_empty()
+    }
+
+    /// Moves to the next frame of the source, returns false at the end of the source
+    fn next_source_frame(&mut self) -> bool {
+        let channels = self.source.channels().max(1);
+        if self.source_position + channels > self.source_buffer.len() && !self.refill_source_buffer() {
+            return false;
+        }
+
+        ::std::mem::swap(&mut self.previous_frame, &mut self.current_frame);
+        self.current_frame.clear();
+        for sample in self.source_buffer[self.source_position..self.source_position + channels].iter() {
+            self.current_frame.push(f32::from(*sample) / 32768.0);
+        }
+        self.source_position += channels;
+        self.frames_played += 1;
+        true
+    }
+
+    /// Fills `output` with the next frames in the output format (linear interpolation
+    /// between the source frames). Returns the number of samples written
+    fn read_converted(&mut self, output: &mut [f32], channels: usize, sample_rate: u32) -> usize {
+        if !self.started {
+            self.started = true;
+            if !self.next_source_frame() {
+                self.finished = true;
+                return 0;
+            }
+            // interpolate between the first and the second frame, so that the
+            // first output frame is the first source frame
+            if !self.next_source_frame() {
+                self.previous_frame = self.current_frame.clone();
+                self.source_ended = true;
+            }
+            self.fraction = 0.0;
+        }
+
+        let step = f64::from(self.source.sample_rate()) * f64::from(self.speed.max(0.01)) / f64::from(sample_rate.max(1));
+        let mut interpolated = Vec::with_capacity(self.current_frame.len());
+        let mut written = 0;
+
+        for frame in output.chunks_mut(channels) {
+            while self.fraction >= 1.0 {
+                if self.source_ended {
+                    self.finished = true;
+                    return written;
+                }
+                if !self.next_source_frame() {
+                    // the last frame still has to be played
+                    self.source_ended = true;
+                    self.previous_frame = self.current_frame.clone();
+                }
+                self.fraction -= 1.0;
+            }
+
+            let t = self.fraction as f32;
+            interpolated.clear();
+            for (previous, current) in self.previous_frame.iter().zip(self.current_frame.iter()) {
+                interpolated.push(previous + (current - previous) * t);
+            }
+
+            map_channels(&interpolated, frame);
+            written += frame.len();
+            self.fraction += step;
+        }
+
+        written
+    }
+}
+
+/// Up- or down-mixes one frame to the channel count of `output`.
+/// Mono is copied to all channels, extra channels are averaged into the available ones
+pub fn map_channels(input: &[f32], output: &mut [f32]) {
+    if input.is_empty() {
+        for sample in output.iter_mut() { *sample = 0.0; }
+    } else if input.len() == output.len() {
+        output.copy_from_slice(input);
+    } else if input.len() < output.len() {
+        for (channel, sample) in output.iter_mut().enumerate() {
+            *sample = input[channel % input.len()];
+        }
+    } else {
+        let output_channels = output.len();
+        for (channel, sample) in output.iter_mut().enumerate() {
+            let sources = input.iter().enumerate().filter(|&(idx, _)| idx % output_channels == channel);
+            let (sum, count) = sources.fold((0.0, 0), |(sum, count), (_, s)| (sum + s, count + 1));
+            *sample = sum / count as f32;
+        }
+    }
+}
+
+/// Gain of a voice on an output channel. On stereo outputs, the channel on the
+/// other side of the pan is turned down, so that centered voices keep their full volume
+pub fn pan_gain(pan: f32, channel: usize, channel_count: usize) -> f32 {
+    if channel_count != 2 {
+        return 1.0;
+    }
+    let pan = pan.max(-1.0).min(1.0);
+    if channel == 0 { (1.0 - pan).min(1.0) } else { (1.0 + pan).min(1.0) }
+}
+
+pub struct Mixer {
+    /// Number of interleaved output channels
+    pub channels: usize,
+    /// Frames per second of the output
+    pub sample_rate: u32,
+    /// The background song, if any
+    pub music: Option<Voice>,
+    /// Songs that are fading out after the music was changed
+    pub fading_music: Vec<Voice>,
+    /// Song that starts when the fading songs are silent
+    pub queued_music: Option<Voice>,
+    /// Song that replaces the music at the next bar
+    pending_switch: Option<PendingSwitch>,
+    /// Playback speed of the music, i.e. faster when the game gets tense
+    pub music_speed: f32,
+    /// None = positioned sounds are played in the center, at full volume
+    pub listener: Option<Listener>,
+    /// Sound effects, removed when they have finished playing
+    pub sfx: Vec<Voice>,
+    /// Volume of the whole output, 0.0 - 1.0
+    pub volume: f32,
+    /// Volume of the songs, on top of `volume`
+    pub music_volume: f32,
+    /// Volume of the sound effects, on top of `volume`
+    pub sfx_volume: f32,
+    /// Outputs silence and doesn't advance the voices
+    pub paused: bool,
+    /// Scratch buffer for summing the voices, to avoid clipping between two voices
+    mix_buffer: Vec<f32>,
+    /// Scratch buffer for reading the samples of one voice
+    voice_buffer: Vec<f32>,
+}
+
+impl Mixer {
+
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        Self {
+            channels: channels.max(1),
+            sample_rate: sample_rate,
+            music: None,
+            fading_music: Vec::new(),
+            queued_music: None,
+            pending_switch: None,
+            music_speed: 1.0,
+            listener: None,
+            sfx: Vec::new(),
+            volume: 1.0,
+            music_volume: 1.0,
+            sfx_volume: 1.0,
+            paused: false,
+            mix_buffer: Vec::new(),
+            voice_buffer: Vec::new(),
+        }
+    }
+
+    /// Replaces the current song immediately, `None` stops the music
+    pub fn set_music(&mut self, music: Option<Voice>) {
+        self.music = music;
+        self.fading_music.clear();
+        self.queued_music = None;
+        self.pending_switch = None;
+    }
+
+    /// Number of output frames that play in the given time
+    pub fn frames_for(&self, duration: Duration) -> usize {
+        let seconds = duration.as_secs() as f32 + duration.subsec_nanos() as f32 / 1_000_000_000.0;
+        (seconds * self.sample_rate as f32) as usize
+    }
+
+    /// Fades the current song out while the new song fades in.
+    /// `None` fades out to silence
+    pub fn crossfade_music(&mut self, music: Option<Voice>, duration: Duration) {
+        let length = self.frames_for(duration);
+        self.fade_out_current_music(length);
+        self.queued_music = None;
+        self.pending_switch = None;
+        self.music = music.map(|voice| voice.with_fade(Fade::new(0.0, 1.0, length)));
+    }
+
+    /// Crossfades to the new song when the current song (with the given tempo) reaches its
+    /// next bar, so that the switch is on the beat. Starts immediately if there is no music
+    pub fn switch_music_at_bar(&mut self, music: Voice, tempo: MusicTempo, crossfade: Duration) {
+        let current_bar = self.music.as_ref().map(|current| {
+            current.frames_played / tempo.bar_length_frames(current.source.sample_rate())
+        });
+
+        match current_bar {
+            Some(requested_in_bar) => {
+                self.pending_switch = Some(PendingSwitch {
+                    voice: music,
+                    tempo: tempo,
+                    requested_in_bar: requested_in_bar,
+                    crossfade: crossfade,
+                });
+            },
+            None => self.crossfade_music(Some(music), crossfade),
+        }
+    }
+
+    /// Starts the pending song if the current song has reached the next bar
+    fn update_pending_switch(&mut self) {
+        let bar_reached = match (self.pending_switch.as_ref(), self.music.as_ref()) {
+            (Some(pending), Some(current)) => {
+                let bar = current.frames_played / pending.tempo.bar_length_frames(current.source.sample_rate());
+                bar > pending.requested_in_bar
+            },
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+
+        if bar_reached {
+            if let Some(pending) = self.pending_switch.take() {
+                self.crossfade_music(Some(pending.voice), pending.crossfade);
+            }
+        }
+    }
+
+    /// Fades the current song out completely, then starts the new song
+    pub fn fade_out_then_play(&mut self, music: Voice, fade_out: Duration) {
+        let length = self.frames_for(fade_out);
+        self.fade_out_current_music(length);
+        self.pending_switch = None;
+        self.queued_music = Some(music);
+    }
+
+    fn fade_out_current_music(&mut self, length: usize) {
+        if let Some(mut current) = self.music.take() {
+            current.fade_out(length);
+            self.fading_music.push(current);
+        }
+    }
+
+    pub fn play_sfx(&mut self, voice: Voice) {
+        if self.sfx.len() >= MAX_SFX_VOICES {
+            self.sfx.remove(0);
+        }
+        self.sfx.push(voice);
+    }
+
+    /// Moves a sound effect that was started with a handle
+    pub fn move_sfx(&mut self, handle: u32, x: f32, y: f32) {
+        for voice in self.sfx.iter_mut().filter(|voice| voice.handle == Some(handle)) {
+            voice.position = Some((x, y));
+        }
+    }
+
+    /// Number of voices that are currently audible
+    pub fn active_voices(&self) -> usize {
+        self.sfx.len() + self.fading_music.len() + if self.music.is_some() { 1 } else { 0 }
+    }
+
+    /// Mixes the next `output.len()` interleaved samples into `output`
+    pub fn mix_i16(&mut self, output: &mut [i16]) {
+        self.mix(output.len());
+        for (out, mixed) in output.iter_mut().zip(self.mix_buffer.iter()) {
+            *out = (mixed * 32767.0) as i16;
+        }
+    }
+
+    pub fn mix_u16(&mut self, output: &mut [u16]) {
+        self.mix(output.len());
+        for (out, mixed) in output.iter_mut().zip(self.mix_buffer.iter()) {
+            *out = (mixed * 32767.0 + 32768.0) as u16;
+        }
+    }
+
+    pub fn mix_f32(&mut self, output: &mut [f32]) {
+        self.mix(output.len());
+        output.copy_from_slice(&self.mix_buffer[..output.len()]);
+    }
+
+    /// Sums all voices into `mix_buffer`, `sample_count` samples (not frames).
+    /// The result is in the range of -1.0 - 1.0
+    fn mix(&mut self, sample_count: usize) {
+        let channels = self.channels;
+        let sample_rate = self.sample_rate;
+
+        self.mix_buffer.clear();
+        self.mix_buffer.resize(sample_count, 0.0);
+        self.voice_buffer.resize(sample_count, 0.0);
+
+        if self.paused {
+            return;
+        }
+
+        let music_speed = self.music_speed;
+        let (music_volume, sfx_volume) = (self.music_volume, self.sfx_volume);
+
+        if let Some(ref mut music) = self.music {
+            music.speed = music_speed;
+            mix_voice(music, music_volume, &mut self.mix_buffer, &mut self.voice_buffer, channels, sample_rate);
+        }
+
+        for voice in self.fading_music.iter_mut() {
+            voice.speed = music_speed;
+            mix_voice(voice, music_volume, &mut self.mix_buffer, &mut self.voice_buffer, channels, sample_rate);
+        }
+
+        self.fading_music.retain(|voice| !voice.is_finished());
+        if self.fading_music.is_empty() && self.music.is_none() {
+            self.music = self.queued_music.take();
+        }
+
+        self.update_pending_switch();
+
+        for voice in self.sfx.iter_mut() {
+            let mut volume = sfx_volume;
+            if let (Some((x, y)), Some(listener)) = (voice.position, self.listener) {
+                let (pan, attenuation) = listener.spatialize(x, y);
+                voice.pan = pan;
+                volume *= attenuation;
+            }
+            mix_voice(voice, volume, &mut self.mix_buffer, &mut self.voice_buffer, channels, sample_rate);
+        }
+
+        self.sfx.retain(|voice| !voice.is_finished());
+
+        let volume = self.volume;
+        for sample in self.mix_buffer.iter_mut() {
+            *sample = (*sample * volume).max(-1.0).min(1.0);
+        }
+    }
+}
+
+/// Adds the next samples of the voice to the buffer, `group_volume` is the music or sfx volume
+fn mix_voice(voice: &mut Voice, group_volume: f32, buffer: &mut [f32], voice_buffer: &mut [f32], channels: usize, sample_rate: u32) {
+    if voice.is_finished() {
+        return;
+    }
+
+    let gains = (0..channels).map(|c| group_volume * voice.volume * pan_gain(voice.pan, c, channels)).collect::<Vec<f32>>();
+    let read = voice.read_converted(&mut voice_buffer[..buffer.len()], channels, sample_rate);
+
+    for (frame, samples) in buffer[..read].chunks_mut(channels).zip(voice_buffer[..read].chunks(channels)) {
+        let fade_gain = match voice.fade {
+            Some(ref mut fade) => { let gain = fade.gain(); fade.position += 1; gain },
+            None => 1.0,
+        };
+        for ((mixed, sample), gain) in frame.iter_mut().zip(samples.iter()).zip(gains.iter()) {
+            *mixed += sample * gain * fade_gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn pcm_voice(samples: Vec<i16>, channels: usize, sample_rate: u32) -> Voice {
+        Voice::new(Box::new(PcmSource::new(Arc::new(samples), channels, sample_rate)), false)
+    }
+
+    /// The mixer scales by 32767 instead of 32768, so samples may be off by one
+    fn assert_samples_close(actual: &[i16], expected: &[i16]) {
+        assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+        for (a, e) in actual.iter().zip(expected.iter()) {
+            assert!((i32::from(*a) - i32::from(*e)).abs() <= 1, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn pcm_source_is_mixed_unchanged() {
+        let mut mixer = Mixer::new(1, 100);
+        mixer.play_sfx(pcm_voice(vec![0, 8000, -8000, 16000], 1, 100));
+
+        let mut output = [0_i16; 6];
+        mixer.mix_i16(&mut output);
+
+        assert_samples_close(&output, &[0, 8000, -8000, 16000, 0, 0]);
+        assert_eq!(mixer.active_voices(), 0);
+    }
+
+    #[test]
+    fn mono_source_is_played_on_both_channels() {
+        let mut mixer = Mixer::new(2, 100);
+        mixer.play_sfx(pcm_voice(vec![8000, -8000], 1, 100));
+
+        let mut output = [0_i16; 4];
+        mixer.mix_i16(&mut output);
+
+        assert_samples_close(&output, &[8000, 8000, -8000, -8000]);
+    }
+
+    #[test]
+    fn upsampled_source_is_interpolated() {
+        let mut mixer = Mixer::new(1, 100);
+        mixer.play_sfx(pcm_voice(vec![0, 8000, 16000], 1, 50));
+
+        let mut output = [0_i16; 8];
+        mixer.mix_i16(&mut output);
+
+        assert_samples_close(&output, &[0, 4000, 8000, 12000, 16000, 16000, 0, 0]);
+    }
+
+    #[test]
+    fn fade_ramps_linearly_and_holds_the_target() {
+        let mut fade = Fade::new(0.0, 1.0, 4);
+        let mut gains = Vec::new();
+        for _ in 0..6 {
+            gains.push(fade.gain());
+            fade.position += 1;
+        }
+
+        assert_eq!(gains, vec![0.0, 0.25, 0.5, 0.75, 1.0, 1.0]);
+        assert!(fade.is_done());
+    }
+
+    #[test]
+    fn music_fades_in() {
+        let mut mixer = Mixer::new(1, 100);
+        mixer.set_music(Some(pcm_voice(vec![16000; 6], 1, 100).with_fade(Fade::new(0.0, 1.0, 4))));
+
+        let mut output = [0_i16; 6];
+        mixer.mix_i16(&mut output);
+
+        assert_samples_close(&output, &[0, 4000, 8000, 12000, 16000, 16000]);
+    }
+
+    #[test]
+    fn faded_out_voice_is_removed() {
+        let mut voice = pcm_voice(vec![16000; 8], 1, 100);
+        voice.fade_out(4);
+        let mut mixer = Mixer::new(1, 100);
+        mixer.play_sfx(voice);
+
+        let mut output = [0_i16; 6];
+        mixer.mix_i16(&mut output);
+
+        assert_samples_close(&output, &[16000, 12000, 8000, 4000, 0, 0]);
+        assert_eq!(mixer.active_voices(), 0);
+    }
+
+    #[test]
+    fn map_channels_copies_mono_and_averages_extra_channels() {
+        let mut stereo = [0.0; 2];
+        map_channels(&[0.5], &mut stereo);
+        assert_eq!(stereo, [0.5, 0.5]);
+
+        let mut mono = [0.0; 1];
+        map_channels(&[0.5, -0.25], &mut mono);
+        assert_eq!(mono, [0.125]);
+
+        // channel 0 and 2 are averaged into the left, 1 and 3 into the right channel
+        map_channels(&[0.5, 0.25, -0.5, 0.75], &mut stereo);
+        assert_eq!(stereo, [0.0, 0.5]);
+
+        let mut silent = [1.0; 2];
+        map_channels(&[], &mut silent);
+        assert_eq!(silent, [0.0, 0.0]);
+    }
+
+    fn listener() -> Listener {
+        Listener { x: 400.0, y: 300.0, half_width: 400.0, half_height: 300.0 }
+    }
+
+    #[test]
+    fn sounds_are_panned_by_their_position_on_the_screen() {
+        let listener = listener();
+        assert_eq!(listener.spatialize(400.0, 300.0), (0.0, 1.0));
+        assert_eq!(listener.spatialize(200.0, 300.0), (-0.5, 1.0));
+        assert_eq!(listener.spatialize(800.0, 0.0), (1.0, 1.0));
+        // outside of the screen, the pan stays at the side
+        assert_eq!(listener.spatialize(-400.0, 300.0).0, -1.0);
+    }
+
+    #[test]
+    fn sounds_outside_of_the_screen_get_quieter_with_the_distance() {
+        let listener = listener();
+        // half a screen width away from the edge
+        assert_eq!(listener.spatialize(1200.0, 300.0), (1.0, 0.5));
+        let far = listener.spatialize(4000.0, 300.0).1;
+        assert!(far < 0.5 && far > 0.0);
+        // above the screen, not panned but attenuated
+        assert_eq!(listener.spatialize(400.0, 900.0), (0.0, 0.5));
+    }
+
+    #[test]
+    fn positioned_sound_is_mixed_with_pan_and_attenuation() {
+        let mut mixer = Mixer::new(2, 100);
+        mixer.listener = Some(listener());
+        // right of the screen, half a screen width away from the edge
+        mixer.play_sfx(pcm_voice(vec![16000, 16000], 1, 100).at_position(1200.0, 300.0, None));
+
+        let mut output = [0_i16; 4];
+        mixer.mix_i16(&mut output);
+
+        // left channel silent, right channel at half the volume
+        assert_samples_close(&output, &[0, 8000, 0, 8000]);
+    }
+}
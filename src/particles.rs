@@ -0,0 +1,329 @@
+//! CPU particle system for dust, splinters and confetti
+//!
+//! Particles are simulated in world coordinates and drawn as sprites.
+//! Emitters either spawn all particles at once (burst) or a certain
+//! amount per second (continuous).
+
+use color::Color;
+use physics::WorldEvent;
+use texture::SourceTextureRegion;
+
+/// Maximum number of particles alive at the same time, new particles are dropped above this
+pub const MAX_PARTICLES: usize = 2000;
+
+#[derive(Debug, Copy, Clone)]
+pub struct Particle {
+    pub x: f32,
+    pub y: f32,
+    /// Velocity in pixels per second
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    /// Downwards acceleration in pixels per second squared
+    pub gravity: f32,
+    pub rotation: f32,
+    /// Rotation speed in radians per second
+    pub angular_velocity: f32,
+    /// Seconds since the particle was spawned
+    pub age: f32,
+    /// Seconds until the particle is removed
+    pub lifetime: f32,
+    pub start_color: Color,
+    pub end_color: Color,
+    /// Size in pixels
+    pub start_size: f32,
+    pub end_size: f32,
+    pub texture_region: SourceTextureRegion,
+}
+
+impl Particle {
+    /// 0.0 when spawned, 1.0 when the particle dies
+    pub fn life_progress(&self) -> f32 {
+        (self.age / self.lifetime).min(1.0)
+    }
+
+    pub fn current_color(&self) -> Color {
+        let t = self.life_progress();
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * t) as u8;
+        Color {
+            r: lerp(self.start_color.r, self.end_color.r),
+            g: lerp(self.start_color.g, self.end_color.g),
+            b: lerp(self.start_color.b, self.end_color.b),
+            a: lerp(self.start_color.a, self.end_color.a),
+        }
+    }
+
+    pub fn current_size(&self) -> f32 {
+        self.start_size + (self.end_size - self.start_size) * self.life_progress()
+    }
+}
+
+/// How particles of an emitter look and move. Ranges are (min, max)
+#[derive(Debug, Copy, Clone)]
+pub struct EmitterSettings {
+    /// Direction of the initial velocity, in radians (0 = right, PI / 2 = up)
+    pub angle: (f32, f32),
+    /// Initial speed in pixels per second
+    pub speed: (f32, f32),
+    pub gravity: f32,
+    pub angular_velocity: (f32, f32),
+    pub lifetime: (f32, f32),
+    /// Random offset from the emitter position, in pixels
+    pub spawn_radius: f32,
+    /// The start colors are picked randomly from this list
+    pub start_colors: &'static [Color],
+    /// Alpha of the particle at the end of its life, the color stays the same
+    pub end_alpha: u8,
+    pub start_size: (f32, f32),
+    pub end_size: f32,
+    pub texture_region: SourceTextureRegion,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum EmitterKind {
+    /// Spawns this many particles at once, then stops
+    Burst(u32),
+    /// Spawns particles per second, until the duration (in seconds) is over. None = forever
+    Continuous { rate: f32, duration: Option<f32> },
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Emitter {
+    pub x: f32,
+    pub y: f32,
+    pub kind: EmitterKind,
+    pub settings: EmitterSettings,
+    /// Seconds since the emitter was created
+    pub age: f32,
+    /// Fractional particles that haven't been spawned yet (for continuous emitters)
+    pub spawn_accumulator: f32,
+}
+
+impl Emitter {
+    pub fn new(x: f32, y: f32, kind: EmitterKind, settings: EmitterSettings) -> Self {
+        Self {
+            x: x,
+            y: y,
+            kind: kind,
+            settings: settings,
+            age: 0.0,
+            spawn_accumulator: 0.0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self.kind {
+            EmitterKind::Burst(_) => true,
+            EmitterKind::Continuous { duration: Some(duration), .. } => self.age >= duration,
+            EmitterKind::Continuous { duration: None, .. } => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParticleSystem {
+    pub particles: Vec<Particle>,
+    /// Continuous emitters that are still active
+    pub emitters: Vec<Emitter>,
+    /// State of the random number generator (xorshift)
+    rng_state: u32,
+}
+
+impl Default for ParticleSystem {
+    fn default() -> Self {
+        Self {
+            particles: Vec::new(),
+            emitters: Vec::new(),
+            rng_state: 0x9E37_79B9,
+        }
+    }
+}
+
+impl ParticleSystem {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an emitter. Bursts spawn their particles immediately
+    pub fn add_emitter(&mut self, emitter: Emitter) {
+        if let EmitterKind::Burst(count) = emitter.kind {
+            for _ in 0..count {
+                self.spawn_particle(&emitter);
+            }
+        } else {
+            self.emitters.push(emitter);
+        }
+    }
+
+    /// Spawns the particle effect that belongs to an event in the game world
+    pub fn emit_for_event(&mut self, event: &WorldEvent) {
+        match *event {
+            WorldEvent::CrateLanded { x, y, impact_velocity, .. } => {
+                let count = (6.0 + impact_velocity * 0.05).min(30.0) as u32;
+                self.add_emitter(Emitter::new(x, y, EmitterKind::Burst(count), dust_settings()));
+            },
+            WorldEvent::CrateBroken { x, y } => {
+                self.add_emitter(Emitter::new(x, y, EmitterKind::Burst(16), splinter_settings()));
+            },
+            WorldEvent::NewHighscore { x, y, .. } => {
+                self.add_emitter(Emitter::new(x, y, EmitterKind::Burst(60), confetti_settings()));
+            },
+            WorldEvent::PlayerJumped { .. } |
+            WorldEvent::CratePickedUp { .. } |
+            WorldEvent::CrateDropped { .. } => { },
+        }
+    }
+
+    /// Advances the simulation by `time_diff_secs` seconds
+    pub fn update(&mut self, time_diff_secs: f32) {
+
+        // -- spawn particles from continuous emitters
+        let mut emitters = ::std::mem::replace(&mut self.emitters, Vec::new());
+
+        for emitter in emitters.iter_mut() {
+            emitter.age += time_diff_secs;
+            if let EmitterKind::Continuous { rate, .. } = emitter.kind {
+                emitter.spawn_accumulator += rate * time_diff_secs;
+                while emitter.spawn_accumulator >= 1.0 {
+                    self.spawn_particle(emitter);
+                    emitter.spawn_accumulator -= 1.0;
+                }
+            }
+        }
+
+        emitters.retain(|e| !e.is_finished());
+        self.emitters = emitters;
+
+        // -- move particles
+        for particle in self.particles.iter_mut() {
+            particle.velocity_y -= particle.gravity * time_diff_secs;
+            particle.x += particle.velocity_x * time_diff_secs;
+            particle.y += particle.velocity_y * time_diff_secs;
+            particle.rotation += particle.angular_velocity * time_diff_secs;
+            particle.age += time_diff_secs;
+        }
+
+        self.particles.retain(|p| p.age < p.lifetime);
+    }
+
+    fn spawn_particle(&mut self, emitter: &Emitter) {
+        if self.particles.len() >= MAX_PARTICLES {
+            return;
+        }
+
+        let settings = &emitter.settings;
+        let angle = self.random_range(settings.angle);
+        let speed = self.random_range(settings.speed);
+        let offset_angle = self.random_range((0.0, ::std::f32::consts::PI * 2.0));
+        let offset = self.random_range((0.0, settings.spawn_radius));
+        let color_idx = (self.next_random() * settings.start_colors.len() as f32) as usize;
+        let start_color = settings.start_colors[color_idx.min(settings.start_colors.len() - 1)];
+        let mut end_color = start_color;
+        end_color.a = settings.end_alpha;
+        let rotation = self.random_range((0.0, ::std::f32::consts::PI * 2.0));
+        let angular_velocity = self.random_range(settings.angular_velocity);
+        let lifetime = self.random_range(settings.lifetime);
+        let start_size = self.random_range(settings.start_size);
+
+        self.particles.push(Particle {
+            x: emitter.x + offset_angle.cos() * offset,
+            y: emitter.y + offset_angle.sin() * offset,
+            velocity_x: angle.cos() * speed,
+            velocity_y: angle.sin() * speed,
+            gravity: settings.gravity,
+            rotation: rotation,
+            angular_velocity: angular_velocity,
+            age: 0.0,
+            lifetime: lifetime,
+            start_color: start_color,
+            end_color: end_color,
+            start_size: start_size,
+            end_size: settings.end_size,
+            texture_region: settings.texture_region,
+        });
+    }
+
+    /// Random number between 0.0 and 1.0 (xorshift, good enough for particles)
+    fn next_random(&mut self) -> f32 {
+        let mut x = self.rng_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng_state = x;
+        (x % 1_000_000) as f32 / 1_000_000.0
+    }
+
+    fn random_range(&mut self, range: (f32, f32)) -> f32 {
+        range.0 + (range.1 - range.0) * self.next_random()
+    }
+}
+
+// -- presets
+
+const DUST_COLORS: &[Color] = &[
+    Color { r: 210, g: 200, b: 180, a: 200 },
+    Color { r: 180, g: 170, b: 150, a: 200 },
+];
+
+const SPLINTER_COLORS: &[Color] = &[
+    Color { r: 255, g: 255, b: 255, a: 255 },
+];
+
+const CONFETTI_COLORS: &[Color] = &[
+    Color { r: 255, g: 80,  b: 80,  a: 255 },
+    Color { r: 80,  g: 200, b: 80,  a: 255 },
+    Color { r: 80,  g: 140, b: 255, a: 255 },
+    Color { r: 255, g: 220, b: 40,  a: 255 },
+    Color { r: 230, g: 90,  b: 230, a: 255 },
+];
+
+/// Small clouds that spread sideways along the ground
+pub fn dust_settings() -> EmitterSettings {
+    EmitterSettings {
+        angle: (0.1, ::std::f32::consts::PI - 0.1),
+        speed: (20.0, 70.0),
+        gravity: -10.0,
+        angular_velocity: (-1.0, 1.0),
+        lifetime: (0.4, 0.9),
+        spawn_radius: 10.0,
+        start_colors: DUST_COLORS,
+        end_alpha: 0,
+        start_size: (4.0, 8.0),
+        end_size: 14.0,
+        texture_region: ::assets::WHITE_TEXTURE_TX_STR,
+    }
+}
+
+/// Pieces of the crate texture flying in all directions
+pub fn splinter_settings() -> EmitterSettings {
+    EmitterSettings {
+        angle: (0.0, ::std::f32::consts::PI * 2.0),
+        speed: (80.0, 220.0),
+        gravity: 500.0,
+        angular_velocity: (-10.0, 10.0),
+        lifetime: (0.6, 1.2),
+        spawn_radius: 12.0,
+        start_colors: SPLINTER_COLORS,
+        end_alpha: 0,
+        start_size: (4.0, 9.0),
+        end_size: 4.0,
+        texture_region: ::assets::CRATE_SPLINTER_TX_STR,
+    }
+}
+
+/// Colorful pieces of paper shooting upwards, then slowly raining down
+pub fn confetti_settings() -> EmitterSettings {
+    EmitterSettings {
+        angle: (::std::f32::consts::PI * 0.3, ::std::f32::consts::PI * 0.7),
+        speed: (150.0, 350.0),
+        gravity: 250.0,
+        angular_velocity: (-8.0, 8.0),
+        lifetime: (1.5, 2.5),
+        spawn_radius: 5.0,
+        start_colors: CONFETTI_COLORS,
+        end_alpha: 0,
+        start_size: (4.0, 7.0),
+        end_size: 4.0,
+        texture_region: ::assets::WHITE_TEXTURE_TX_STR,
+    }
+}
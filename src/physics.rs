@@ -6,6 +6,9 @@
 use player_state::{PlayerSpritePosition, PlayerState};
 use input::GameInputEvent;
 use std::time::Instant;
+use texture::SourceTextureRegion;
+use particles::Particle;
+use lighting::LightingFrame;
 
 /// If the player presses a key, he should arrive at his goal (with linear interpolation)
 /// in `SPEED_FACTOR` seconds
@@ -14,6 +17,9 @@ pub const SPEED_FACTOR: f32 = 0.25;
 /// Maximum speed the player can go, in any direction (plus an additional SPEED_FACTOR)
 pub const MAX_SPEED: f32 = 0.5;
 
+/// Width and height of a newly spawned crate
+pub const CRATE_SIZE: f32 = 32.0;
+
 #[derive(Debug, Clone)]
 pub struct PhysicsWorld {
     /// Player velocity, in X and Y
@@ -22,6 +28,8 @@ pub struct PhysicsWorld {
     pub player_position: PlayerSpritePosition,
     pub last_crate_spawned: Instant,
     pub gravity: f32,
+    /// Id of the next spawned crate
+    pub next_crate_id: u32,
 }
 
 impl Default for PhysicsWorld {
@@ -32,6 +40,7 @@ impl Default for PhysicsWorld {
             player_position: PlayerSpritePosition::default(),
             player_velocity: PlayerVelocity::default(),
             gravity: 9.8,
+            next_crate_id: 0,
         }
     }
 }
@@ -42,12 +51,21 @@ impl PhysicsWorld {
         self.crates.push(CratePosition {
             x: x_pos,
             y: y_pos,
-            width: 32.0,
-            height: 32.0,
+            width: CRATE_SIZE,
+            height: CRATE_SIZE,
+            rotation: 0.0,
+            // every crate looks a bit different
+            palette_variant: self.crates.len() % ::assets::CRATE_PALETTE.variant_count(),
+            id: self.next_crate_id,
         });
+        self.next_crate_id = self.next_crate_id.wrapping_add(1);
     }
 }
 
+fn overlaps_horizontally(a: &CratePosition, b: &CratePosition) -> bool {
+    a.x < b.x + b.width && b.x < a.x + a.width
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct PlayerVelocity {
     pub x: f32,
@@ -73,6 +91,59 @@ pub struct CratePosition {
     pub width: f32,
     /// height of the crate
     pub height: f32,
+    /// rotation of the crate around its center, in radians
+    pub rotation: f32,
+    /// Color variant of the crate, see `assets::CRATE_PALETTE`
+    pub palette_variant: usize,
+    /// Identifies the crate while it moves, i.e. for sounds that follow it
+    pub id: u32,
+}
+
+/// How far (in pixels) the bottom of a crate may be from the top of another crate
+/// for the crate to count as resting on it
+const RESTING_TOLERANCE: f32 = 2.0;
+
+/// If the bottom of `crate_box` touches the top of `support`
+fn rests_on(crate_box: &CratePosition, support: &CratePosition) -> bool {
+    (support.y + support.height - crate_box.y).abs() < RESTING_TOLERANCE &&
+    overlaps_horizontally(support, crate_box)
+}
+
+/// Height of the highest stack of crates that rest on each other, in pixels.
+/// A single crate is a stack of one crate
+pub fn stack_height(crates: &[CratePosition]) -> f32 {
+    // the crates below are measured first, so every crate can add itself to its highest support
+    let mut sorted = crates.to_vec();
+    sorted.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap_or(::std::cmp::Ordering::Equal));
+
+    let mut heights = Vec::<f32>::with_capacity(sorted.len());
+    for (idx, c) in sorted.iter().enumerate() {
+        let below = (0..idx)
+            .filter(|&s| rests_on(c, &sorted[s]))
+            .map(|s| heights[s])
+            .fold(0.0, f32::max);
+        heights.push(below + c.height);
+    }
+
+    heights.into_iter().fold(0.0, f32::max)
+}
+
+/// How unstable the stacked crates are, 0.0 = stable, 1.0 = about to fall.
+/// A crate that hangs over the crate below it makes the stack unstable,
+/// 1.0 = the center of the crate is exactly above the edge of the crate below
+pub fn tower_wobble(crates: &[CratePosition]) -> f32 {
+    crates.iter().fold(0.0_f32, |max, c| {
+        let center = c.x + c.width / 2.0;
+
+        // the crate below, the one that is closest to the center if the crate rests on two crates
+        let support_offset = crates.iter()
+            .filter(|s| rests_on(c, s))
+            .map(|s| (center - (s.x + s.width / 2.0)).abs() / (s.width / 2.0).max(1.0))
+            .fold(None, |min: Option<f32>, offset| Some(min.map(|m| m.min(offset)).unwrap_or(offset)));
+
+        // crates that don't rest on anything can't hang over
+        max.max(support_offset.unwrap_or(0.0))
+    }).min(1.0)
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -82,14 +153,82 @@ pub enum PlayerResult {
     PlayerHasLost,
 }
 
+/// Something that happened in the game world during one frame
+/// (for particles and sound effects). Positions are in world coordinates
+#[derive(Debug, Copy, Clone)]
+pub enum WorldEvent {
+    /// A crate hit the ground or another crate, velocity in pixels per second
+    CrateLanded { x: f32, y: f32, impact_velocity: f32, crate_id: u32 },
+    /// A crate was destroyed
+    CrateBroken { x: f32, y: f32 },
+    /// The tower has reached a new height, x / y is the top of the highest crate
+    NewHighscore { x: f32, y: f32, highscore: f32 },
+    /// The player has jumped, x / y are the feet of the player
+    PlayerJumped { x: f32, y: f32 },
+    /// The player has picked up a crate
+    CratePickedUp { x: f32, y: f32, crate_id: u32 },
+    /// The player has let go of the crate he was carrying
+    CrateDropped { x: f32, y: f32, crate_id: u32 },
+}
+
 /// The physics world, for submission to the renderer
 ///
 /// i.e. where, for this one frame,
 #[derive(Debug, Clone)]
 pub struct PhysicsFinalizedData {
     pub player_position: PlayerSpritePosition,
+    /// Current animation frame of the player
+    pub player_sprite: SourceTextureRegion,
+    /// If the player is facing left, the sprite has to be mirrored
+    pub player_sprite_flipped: bool,
+    /// Skin of the player, see `assets::HERO_PALETTE`
+    pub player_skin: usize,
     pub crates: Vec<CratePosition>,
     /// Has the player quit or lost the game?
     pub result: PlayerResult,
     pub highscore: f32,
+    /// How unstable the tower is, see `tower_wobble`
+    pub tower_wobble: f32,
+    /// Particles to draw on this frame
+    pub particles: Vec<Particle>,
+    /// What happened during this frame
+    pub events: Vec<WorldEvent>,
+    /// Lights and shadow casters, None if the level has no lighting
+    pub lighting: Option<LightingFrame>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn crate_at(x: f32, y: f32, id: u32) -> CratePosition {
+        CratePosition { x: x, y: y, width: CRATE_SIZE, height: CRATE_SIZE, rotation: 0.0, palette_variant: 0, id: id }
+    }
+
+    #[test]
+    fn stack_height_counts_only_crates_that_rest_on_each_other() {
+        let crates = [
+            crate_at(0.0, 0.0, 0),
+            crate_at(8.0, 32.0, 1),
+            crate_at(8.0, 64.0, 2),
+            // flying crate, doesn't touch the stack
+            crate_at(100.0, 200.0, 3),
+        ];
+        assert_eq!(stack_height(&crates), 3.0 * CRATE_SIZE);
+        assert_eq!(stack_height(&crates[3..]), CRATE_SIZE);
+        assert_eq!(stack_height(&[]), 0.0);
+    }
+
+    #[test]
+    fn wobble_is_the_overhang_over_the_crate_below() {
+        let straight = [crate_at(0.0, 0.0, 0), crate_at(0.0, 32.0, 1)];
+        assert_eq!(tower_wobble(&straight), 0.0);
+
+        let half = [crate_at(0.0, 0.0, 0), crate_at(8.0, 32.0, 1)];
+        assert!((tower_wobble(&half) - 0.5).abs() < 0.001);
+
+        // crates that are only next to each other don't wobble
+        let apart = [crate_at(0.0, 0.0, 0), crate_at(40.0, 32.0, 1)];
+        assert_eq!(tower_wobble(&apart), 0.0);
+    }
 }
@@ -1,7 +1,16 @@
 use camera::Camera;
-use physics::{PhysicsWorld, PhysicsFinalizedData, PlayerResult, MAX_SPEED, CratePosition};
+use physics::{PhysicsWorld, PhysicsFinalizedData, PlayerResult, MAX_SPEED, CratePosition, WorldEvent};
 use input::GameInputEvent;
 use std::time::{Duration, Instant};
+use animation::PlayerAnimator;
+use particles::ParticleSystem;
+use level::Level;
+
+/// Gravity for falling objects, in pixels per second squared
+pub const GRAVITY_PIXELS_PER_SEC2: f32 = 980.0;
+
+/// Every time the highscore passes a multiple of this (in pixels), there is a celebration
+pub const HIGHSCORE_MILESTONE: f32 = 50.0;
 
 /// The state of the player in the game world
 #[derive(Debug, Clone)]
@@ -14,6 +23,22 @@ pub struct PlayerState {
     pub player_wants_box: bool,
     pub player_carrying_crate: Option<CratePosition>,
     pub last_tick_update: Instant,
+    /// Selects the animation of the hero sprite
+    pub player_animator: PlayerAnimator,
+    /// Dust, splinters and confetti
+    pub particle_system: ParticleSystem,
+    /// Highest highscore reached in this game
+    pub best_highscore: f32,
+    /// Height of the highest stack of crates that rest on each other
+    pub stack_height: f32,
+    /// Highest stack reached in this game, selects the level song
+    pub best_stack_height: f32,
+    /// Which variant of the level is played (lighting, etc.)
+    pub level: Level,
+    /// Color variant of the hero, 0 = original colors
+    pub player_skin: usize,
+    /// Set once the player has lost, the game stays over from then on
+    pub game_over: bool,
 }
 
 impl PlayerState {
@@ -31,11 +56,13 @@ impl PlayerState {
 
         let mut x_diff = 0.0;
         let mut y_diff = 0.0;
+        let mut player_jumped = false;
 
         for event in events {
             match event {
                 GameInputEvent::PlayerJump => {
                     y_diff += 20.0;
+                    player_jumped = true;
                 },
                 GameInputEvent::PlayerGoDown => {
                     y_diff -= 20.0;
@@ -68,11 +95,24 @@ impl PlayerState {
             self.physics_world.last_crate_spawned = now;
         }
 
+        let mut world_events = Vec::<WorldEvent>::new();
+
+        if player_jumped {
+            world_events.push(WorldEvent::PlayerJumped {
+                x: self.physics_world.player_position.x + self.physics_world.player_position.width / 2.0,
+                y: self.physics_world.player_position.y,
+            });
+        }
+
         let mut new_crates: Vec<CratePosition> = self.physics_world.crates.iter().cloned().filter_map(|mut crate_box| {
             if crate_box.x + crate_box.width > (self.camera.x + self.camera.screen_width) ||
                 crate_box.y + crate_box.height > (self.camera.y + self.camera.screen_height)
             {
                 // remove crates that have flown outside the screen
+                world_events.push(WorldEvent::CrateBroken {
+                    x: crate_box.x + crate_box.width / 2.0,
+                    y: crate_box.y + crate_box.height / 2.0,
+                });
                 None
             } else {
                 crate_box.x += 1.0;
@@ -84,8 +124,9 @@ impl PlayerState {
         self.physics_world.player_position.x += x_diff;
         self.physics_world.player_position.y += y_diff;
 
-        // TODO: check if a crate has fallen down, if so, end the game
-        let game_result = PlayerResult::PlayerOk;
+        let on_ground = self.physics_world.player_position.y <= self.camera.y + self.floor_height;
+        let tick_secs = tick_nanos as f32 / 1_000_000_000.0;
+        self.player_animator.update(x_diff, y_diff, on_ground, self.player_carrying_crate.is_some(), tick_secs);
 
         if self.player_wants_box {
             if let Some(idx) = player_intersect_crate(self.physics_world.player_position,
@@ -96,10 +137,30 @@ impl PlayerState {
                     // if the event was to pick up the crate, let the crate fall
                     self.physics_world.crates.push(box_crate.clone());
                     self.player_carrying_crate = None;
+
+                    world_events.push(WorldEvent::CrateDropped {
+                        x: box_crate.x + box_crate.width / 2.0,
+                        y: box_crate.y + box_crate.height / 2.0,
+                        crate_id: box_crate.id,
+                    });
+
+                    let drop_height = (self.physics_world.player_position.y + self.physics_world.player_position.height - box_crate.y).max(0.0);
+                    world_events.push(WorldEvent::CrateLanded {
+                        x: box_crate.x + box_crate.width / 2.0,
+                        y: box_crate.y,
+                        impact_velocity: (2.0 * GRAVITY_PIXELS_PER_SEC2 * drop_height).sqrt(),
+                        crate_id: box_crate.id,
+                    });
                 } else {
                     // remove the crates from the default crate stack
                     let carrying_crate = self.physics_world.crates.remove(idx);
                     self.player_carrying_crate = Some(carrying_crate);
+
+                    world_events.push(WorldEvent::CratePickedUp {
+                        x: carrying_crate.x + carrying_crate.width / 2.0,
+                        y: carrying_crate.y + carrying_crate.height / 2.0,
+                        crate_id: carrying_crate.id,
+                    });
                 }
             }
             self.player_wants_box = false;
@@ -114,21 +175,67 @@ impl PlayerState {
 
         self.highscore = new_highscore.map(|x| x as f32).unwrap_or(0.0);
 
+        // only celebrate every few pixels, otherwise there would be confetti on every frame
+        if (self.highscore / HIGHSCORE_MILESTONE).floor() > (self.best_highscore / HIGHSCORE_MILESTONE).floor() {
+            let highest_crate = self.physics_world.crates.iter().fold(None, |highest: Option<&CratePosition>, c| {
+                match highest {
+                    Some(h) if h.y + h.height >= c.y + c.height => Some(h),
+                    _ => Some(c),
+                }
+            });
+
+            if let Some(highest_crate) = highest_crate {
+                world_events.push(WorldEvent::NewHighscore {
+                    x: highest_crate.x + highest_crate.width / 2.0,
+                    y: highest_crate.y + highest_crate.height,
+                    highscore: self.highscore,
+                });
+            }
+        }
+
+        self.best_highscore = self.best_highscore.max(self.highscore);
+
+        // crates that rest on each other, for the music
+        self.stack_height = ::physics::stack_height(&self.physics_world.crates);
+        self.best_stack_height = self.best_stack_height.max(self.stack_height);
+
+        // -- particles
+        for event in &world_events {
+            self.particle_system.emit_for_event(event);
+        }
+        self.particle_system.update(tick_secs);
+
         // push the crate on the head of the player if he is carrying a crate
-        if self.player_carrying_crate.is_some() {
+        if let Some(carrying_crate) = self.player_carrying_crate {
             new_crates.push(CratePosition {
                 x: self.physics_world.player_position.x + ((self.physics_world.player_position.width / 2.0) - 25.0),
                 y: self.physics_world.player_position.y + self.physics_world.player_position.height,
                 width: 50.0,
                 height: 50.0,
+                rotation: 0.0,
+                .. carrying_crate
             });
         }
 
+        // TODO: check if a crate has fallen down, if so, end the game
+        let game_result = if self.game_over { PlayerResult::PlayerHasLost } else { PlayerResult::PlayerOk };
+
+        let lighting = self.level.lighting.map(|lighting| {
+            lighting.resolve(&self.physics_world.player_position, &new_crates)
+        });
+
         PhysicsFinalizedData {
             crates: new_crates,
             result: game_result,
             player_position: self.physics_world.player_position,
+            player_sprite: self.player_animator.current_frame(),
+            player_sprite_flipped: self.player_animator.is_flipped(),
+            player_skin: self.player_skin,
+            particles: self.particle_system.particles.clone(),
+            events: world_events,
+            lighting: lighting,
             highscore: self.highscore,
+            tower_wobble: ::physics::tower_wobble(&self.physics_world.crates),
         }
     }
 }
@@ -144,6 +251,14 @@ impl Default for PlayerState {
             camera: Camera { x: 0.0, y: 0.0, screen_width: 800.0, screen_height: 600.0 },
             physics_world: PhysicsWorld::default(),
             highscore: { 0.0 },
+            player_animator: PlayerAnimator::default(),
+            particle_system: ParticleSystem::new(),
+            best_highscore: 0.0,
+            stack_height: 0.0,
+            best_stack_height: 0.0,
+            level: ::assets::LEVEL_DAY,
+            player_skin: 0,
+            game_over: false,
         }
     }
 }
@@ -0,0 +1,260 @@
+//! Offscreen render targets and the post-processing chain
+//!
+//! The scene is drawn to the window as usual, then copied into an offscreen
+//! texture. Every enabled effect reads from the previous texture and writes
+//! into the next one, the last effect writes back into the window.
+
+use glium::{DrawParameters, Frame, Surface, VertexBuffer};
+use glium::backend::Facade;
+use glium::texture::Texture2d;
+use glium::uniforms::MagnifySamplerFilter;
+use std::cell::{Ref, RefCell};
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, Write};
+
+use texture::PixelScreenVert;
+use {FastHashMap, ShaderHashMap};
+
+/// The window contents get copied into this target before the first effect runs
+pub const SCENE_TARGET_ID: &str = "render_target_scene";
+/// Ping-pong targets for chaining effects
+pub const POST_PROCESSING_TARGET_A_ID: &str = "render_target_post_a";
+pub const POST_PROCESSING_TARGET_B_ID: &str = "render_target_post_b";
+
+/// Offscreen textures that can be drawn to, hashed by ID.
+/// The textures are (re-)created lazily if the window size changes
+#[derive(Default)]
+pub struct RenderTargets {
+    pub targets: RefCell<FastHashMap<&'static str, Texture2d>>,
+}
+
+impl RenderTargets {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes sure that the render target exists and has the given size
+    pub fn ensure_target<F>(&self, display: &F, id: &'static str, width: u32, height: u32)
+        where F: Facade
+    {
+        let mut targets = self.targets.borrow_mut();
+
+        let needs_resize = match targets.get(id) {
+            Some(texture) => texture.dimensions() != (width, height),
+            None => true,
+        };
+
+        if needs_resize {
+            let texture = Texture2d::empty(display, width, height).unwrap();
+            targets.insert(id, texture);
+        }
+    }
+
+    /// Returns all render targets. Don't hold on to the returned value while calling `ensure_target`
+    pub fn get(&self) -> Ref<FastHashMap<&'static str, Texture2d>> {
+        self.targets.borrow()
+    }
+}
+
+/// Which screen effect a post-processing pass should apply
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PostProcessingEffect {
+    Bloom,
+    Vignette,
+    Crt,
+    ColorGrading,
+}
+
+impl PostProcessingEffect {
+    /// Returns the ID of the shader in the `ShaderHashMap`
+    pub fn shader_id(&self) -> &'static str {
+        use context::*;
+        match *self {
+            PostProcessingEffect::Bloom => POST_BLOOM_SHADER_ID,
+            PostProcessingEffect::Vignette => POST_VIGNETTE_SHADER_ID,
+            PostProcessingEffect::Crt => POST_CRT_SHADER_ID,
+            PostProcessingEffect::ColorGrading => POST_COLOR_GRADING_SHADER_ID,
+        }
+    }
+}
+
+/// Which effects are enabled, and their parameters
+#[derive(Debug, Copy, Clone)]
+pub struct PostProcessingSettings {
+    pub bloom: bool,
+    /// Brightness above which pixels start to glow (0.0 - 1.0)
+    pub bloom_threshold: f32,
+    pub bloom_intensity: f32,
+
+    pub vignette: bool,
+    /// Distance from the center of the screen where the darkening starts (0.0 - 1.0)
+    pub vignette_radius: f32,
+    pub vignette_intensity: f32,
+
+    pub crt: bool,
+    pub crt_curvature: f32,
+    pub crt_scanline_intensity: f32,
+
+    pub color_grading: bool,
+    pub grading_brightness: f32,
+    pub grading_contrast: f32,
+    pub grading_saturation: f32,
+    pub grading_tint: [f32;3],
+}
+
+impl Default for PostProcessingSettings {
+    fn default() -> Self {
+        Self {
+            bloom: false,
+            bloom_threshold: 0.7,
+            bloom_intensity: 1.5,
+
+            vignette: false,
+            vignette_radius: 0.75,
+            vignette_intensity: 0.5,
+
+            crt: false,
+            crt_curvature: 0.04,
+            crt_scanline_intensity: 0.15,
+
+            color_grading: false,
+            grading_brightness: 0.0,
+            grading_contrast: 1.1,
+            grading_saturation: 1.1,
+            grading_tint: [1.0, 1.0, 1.0],
+        }
+    }
+}
+
+impl PostProcessingSettings {
+
+    /// Turns the effect on or off
+    pub fn toggle(&mut self, effect: PostProcessingEffect) {
+        match effect {
+            PostProcessingEffect::Bloom => self.bloom = !self.bloom,
+            PostProcessingEffect::Vignette => self.vignette = !self.vignette,
+            PostProcessingEffect::Crt => self.crt = !self.crt,
+            PostProcessingEffect::ColorGrading => self.color_grading = !self.color_grading,
+        }
+    }
+
+    /// Loads which effects are enabled from a `key = value` file, the parameters keep their default
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut settings = Self::default();
+        for (key, value) in ::settings::read_config_file(path)? {
+            let flag = match value.parse::<bool>() {
+                Ok(flag) => flag,
+                Err(_) => continue,
+            };
+
+            match key.as_str() {
+                "bloom" => settings.bloom = flag,
+                "vignette" => settings.vignette = flag,
+                "crt" => settings.crt = flag,
+                "color_grading" => settings.color_grading = flag,
+                _ => println!("unknown graphics setting: {:?}", key),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "bloom = {}", self.bloom)?;
+        writeln!(file, "vignette = {}", self.vignette)?;
+        writeln!(file, "crt = {}", self.crt)?;
+        writeln!(file, "color_grading = {}", self.color_grading)?;
+        Ok(())
+    }
+
+    /// Returns the enabled effects, in the order they are applied
+    pub fn enabled_effects(&self) -> Vec<PostProcessingEffect> {
+        let mut effects = Vec::new();
+        if self.bloom { effects.push(PostProcessingEffect::Bloom); }
+        if self.color_grading { effects.push(PostProcessingEffect::ColorGrading); }
+        if self.vignette { effects.push(PostProcessingEffect::Vignette); }
+        if self.crt { effects.push(PostProcessingEffect::Crt); }
+        effects
+    }
+}
+
+/// Runs all enabled effects on the finished frame. Does nothing if no effect is enabled
+pub fn apply_post_processing<F>(frame: &mut Frame, display: &F, render_targets: &RenderTargets,
+                                shaders: &ShaderHashMap, settings: &PostProcessingSettings)
+    where F: Facade
+{
+    use glium::BlitTarget;
+
+    let effects = settings.enabled_effects();
+    if effects.is_empty() {
+        return;
+    }
+
+    let (w, h) = frame.get_dimensions();
+
+    render_targets.ensure_target(display, SCENE_TARGET_ID, w, h);
+    render_targets.ensure_target(display, POST_PROCESSING_TARGET_A_ID, w, h);
+    render_targets.ensure_target(display, POST_PROCESSING_TARGET_B_ID, w, h);
+
+    let targets = render_targets.get();
+    let scene = &targets[SCENE_TARGET_ID];
+    let ping_pong = [&targets[POST_PROCESSING_TARGET_A_ID], &targets[POST_PROCESSING_TARGET_B_ID]];
+
+    // copy the window contents (also resolves multisampling)
+    frame.blit_whole_color_to(&scene.as_surface(), &BlitTarget {
+        left: 0,
+        bottom: 0,
+        width: w as i32,
+        height: h as i32,
+    }, MagnifySamplerFilter::Nearest);
+
+    let vbuf = fullscreen_quad(display, w, h);
+
+    for (idx, effect) in effects.iter().enumerate() {
+        let source = if idx == 0 { scene } else { ping_pong[(idx - 1) % 2] };
+        let shader = shaders.get(effect.shader_id()).unwrap();
+
+        let uniforms = uniform!(
+            window_width: w as f32,
+            window_height: h as f32,
+            tex: source.sampled().magnify_filter(MagnifySamplerFilter::Linear),
+            bloom_threshold: settings.bloom_threshold,
+            bloom_intensity: settings.bloom_intensity,
+            vignette_radius: settings.vignette_radius,
+            vignette_intensity: settings.vignette_intensity,
+            crt_curvature: settings.crt_curvature,
+            crt_scanline_intensity: settings.crt_scanline_intensity,
+            grading_brightness: settings.grading_brightness,
+            grading_contrast: settings.grading_contrast,
+            grading_saturation: settings.grading_saturation,
+            grading_tint: settings.grading_tint,
+        );
+
+        let draw_parameters = DrawParameters::default();
+
+        if idx == effects.len() - 1 {
+            frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE, shader, &uniforms, &draw_parameters).unwrap();
+        } else {
+            ping_pong[idx % 2].as_surface().draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE, shader, &uniforms, &draw_parameters).unwrap();
+        }
+    }
+}
+
+/// Quad covering the whole window, in pixel coordinates (for the `pixel_to_screen_space` vertex shader)
+pub fn fullscreen_quad<F>(display: &F, width: u32, height: u32) -> VertexBuffer<PixelScreenVert>
+    where F: Facade
+{
+    let (w, h) = (width as f32, height as f32);
+
+    let vertex_buf = [
+        PixelScreenVert { position: [0.0, 0.0, 0.0], tex_coords: [0.0, 0.0] },
+        PixelScreenVert { position: [0.0, h,   0.0], tex_coords: [0.0, 1.0] },
+        PixelScreenVert { position: [w,   0.0, 0.0], tex_coords: [1.0, 0.0] },
+        PixelScreenVert { position: [w,   h,   0.0], tex_coords: [1.0, 1.0] },
+    ];
+
+    VertexBuffer::new(display, &vertex_buf).unwrap()
+}
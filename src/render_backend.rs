@@ -0,0 +1,120 @@
+//! Abstraction over the renderer, so that the game screens can be drawn
+//! either with OpenGL (`GameFrame`) or on the CPU (`SoftwareRenderer`)
+
+use color::Color;
+use font::{FontInstanceId, Text, TextSpan, TextLayout, LaidOutText};
+use texture::{TextureId, TextureInstanceId, TextureDrawOptions, SpriteDrawParameters, NineSliceInstance};
+use shapes::ShapeBatch;
+use lighting::LightingFrame;
+use {FontInstanceIdMap, TextureInstanceIdMap};
+
+/// Everything that is needed to draw one frame of the game
+pub trait RenderBackend {
+    /// Width and height of the surface that is drawn to, in pixels
+    fn dimensions(&self) -> (u32, u32);
+    /// Clears the whole surface with the given color
+    fn clear_screen(&mut self, color: Color);
+    /// notice: panics if the font isn't valid!!!
+    fn get_font(&self, id: &'static str) -> FontInstanceId;
+    fn get_texture(&self, id: &'static str) -> TextureId;
+    /// Width of the text in pixels, if it would be rendered with the given font
+    fn calculate_font_width(&self, id: &FontInstanceId, text: &str) -> f32;
+    fn draw_font(&mut self, text: &Text, color: Color);
+    /// Lays out the text in a box (alignment, wrapping, line breaks) and draws it with its
+    /// shadow or outline. Returns the layout, i.e. to get the height of the text
+    fn draw_text_layout(&mut self, font: &FontInstanceId, spans: &[TextSpan], layout: &TextLayout) -> LaidOutText {
+        let laid_out = ::font::layout_text(font, spans, layout, |text| self.calculate_font_width(font, text));
+
+        if let Some((offsets, color)) = layout.style.background_copies() {
+            for run in laid_out.runs.iter() {
+                for &(dx, dy) in offsets.iter() {
+                    let x = (run.x + dx as f32).max(0.0) as u32;
+                    let y = (run.y + dy as f32).max(0.0) as u32;
+                    self.draw_font(&Text::new(font, &run.text, x, y), color);
+                }
+            }
+        }
+
+        for run in laid_out.runs.iter() {
+            let x = run.x.max(0.0) as u32;
+            let y = run.y.max(0.0) as u32;
+            self.draw_font(&Text::new(font, &run.text, x, y), run.color);
+        }
+
+        laid_out
+    }
+    fn draw_texture(&mut self, texture_id: &TextureInstanceId, sprite_parameters: &SpriteDrawParameters, options: TextureDrawOptions);
+    /// Draws many sprites in the given order, i.e. all crates or particles.
+    /// Backends can draw sprites from the same atlas page in one draw call
+    fn draw_texture_batch(&mut self, sprites: &[(TextureInstanceId, SpriteDrawParameters)], options: TextureDrawOptions) {
+        for &(ref texture_id, ref sprite_parameters) in sprites {
+            self.draw_texture(texture_id, sprite_parameters, options);
+        }
+    }
+    /// Draws a scalable image (buttons, panels) as nine separate textures.
+    /// Rotation and scale are applied around the pivot of the whole image, so the slices stay together
+    fn draw_nine_slice(&mut self, nine_slice: &NineSliceInstance, sprite_parameters: &SpriteDrawParameters, options: TextureDrawOptions) {
+        let (pivot_x, pivot_y) = sprite_parameters.pivot_in_pixels(&nine_slice.target_texture_region);
+
+        for slice in nine_slice.slices() {
+            // the same pivot in screen pixels, relative to the slice
+            let region = slice.target_texture_region;
+            let slice_parameters = SpriteDrawParameters {
+                pivot: ((pivot_x - region.screen_bottom_x as f32) / region.screen_width as f32,
+                        (pivot_y - region.screen_bottom_y as f32) / region.screen_height as f32),
+                .. *sprite_parameters
+            };
+            self.draw_texture(&slice, &slice_parameters, options);
+        }
+    }
+    /// Draws all shapes (rectangles, circles, lines) of the batch at once, over everything drawn
+    /// before. Overlapping shapes are drawn in the order they were added to the batch
+    fn draw_shapes(&mut self, shapes: &ShapeBatch);
+    /// Darkens everything drawn so far to the ambient color, except where the lights shine
+    fn draw_lighting(&mut self, lighting: &LightingFrame);
+}
+
+/// Loads fonts and textures into a renderer at startup
+pub trait AssetLoader {
+    /// Loads a TrueType font, which can then be drawn in any size
+    fn load_font(&mut self, id: &'static str, data: &'static [u8]);
+    fn load_texture_png(&mut self, id: &'static str, data: &'static [u8]) -> TextureId;
+    /// Loads raw RGBA pixels (top row first)
+    fn load_texture_rgba(&mut self, id: &'static str, width: u32, height: u32, pixels: Vec<u8>) -> TextureId;
+    /// Called after all assets are loaded (i.e. for packing the textures into an atlas)
+    fn finish_loading(&mut self) { }
+}
+
+/// Loads all the fonts and textures of the game, returns the IDs under which they are available
+pub fn load_game_assets<L>(loader: &mut L) -> (FontInstanceIdMap, TextureInstanceIdMap)
+    where L: AssetLoader
+{
+    use game::*;
+
+    // -- fonts
+    let mut available_font_ids = FontInstanceIdMap::default();
+
+    loader.load_font(::assets::FONT_ID, ::assets::FONT);
+
+    // glyphs are rendered on demand, these are just the default sizes
+    let font = |size| FontInstanceId { font_name: ::assets::FONT_ID, font_size: size };
+    available_font_ids.insert(FONT_BIG_ID, font(::assets::FONT_BIG_SIZE));
+    available_font_ids.insert(FONT_MEDIUM_ID, font(::assets::FONT_MEDIUM_SIZE));
+    available_font_ids.insert(FONT_SMALL_ID, font(::assets::FONT_SMALL_SIZE));
+
+    // -- textures
+    let mut available_texture_ids = TextureInstanceIdMap::default();
+
+    available_texture_ids.insert(TEXTURE_START_GAME_ID, loader.load_texture_png(::assets::START_SCREEN_BUTTON_00_ID, ::assets::START_SCREEN_BUTTON_00));
+    available_texture_ids.insert(TEXTURE_HERO_CHARACTER_ID, loader.load_texture_png(::assets::HERO_TEXTURE_ID, ::assets::HERO_TEXTURE));
+    available_texture_ids.insert(TEXTURE_CRATE_ID, loader.load_texture_png(::assets::CRATE_TEXTURE_ID, ::assets::CRATE_TEXTURE_DATA));
+    available_texture_ids.insert(TEXTURE_BACKGROUND_ID, loader.load_texture_png(::assets::BACKGROUND_3_TEXTURE_ID, ::assets::BACKGROUND_3_TEXTURE_DATA));
+
+    let white_pixels = vec![255_u8; (::assets::WHITE_TEXTURE_SIZE * ::assets::WHITE_TEXTURE_SIZE * 4) as usize];
+    available_texture_ids.insert(TEXTURE_WHITE_ID, loader.load_texture_rgba(::assets::WHITE_TEXTURE_ID,
+        ::assets::WHITE_TEXTURE_SIZE, ::assets::WHITE_TEXTURE_SIZE, white_pixels));
+
+    loader.finish_loading();
+
+    (available_font_ids, available_texture_ids)
+}
@@ -9,6 +9,7 @@ use texture::TextureInstanceId;
 pub struct FrameRenderData<'a> {
     pub fonts: Vec<(FontInstanceId, Text<'a>)>,
     pub textures: Vec<TextureInstanceId>,
-    /// Which screen to draw to (currently unused, for compositing screen effects)
+    /// Which screen to draw to, an ID of a render target in `OpenGlContext::render_targets`
+    /// (currently unused, for compositing screen effects)
     pub target_screen_texture: &'static str,
 }
@@ -12,9 +12,9 @@ pub struct Renderer
 impl Renderer {
 
     /// Creates a new renderer. Does not add any fonts or textures
-    pub fn new(width: u32, height: u32) -> Result<Self, AppError> {
+    pub fn new(width: u32, height: u32, vsync: bool) -> Result<Self, AppError> {
         Ok(Self {
-            context: OpenGlContext::new(width, height)?,
+            context: OpenGlContext::new(width, height, vsync)?,
             window_state: WindowState::new(width, height),
         })
     }
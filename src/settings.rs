@@ -0,0 +1,129 @@
+//! User-configurable game settings
+
+use post_processing::PostProcessingSettings;
+use frame_limiter::FrameRateCap;
+
+use std::time::Duration;
+use std::path::Path;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/// Default length of the crossfade between two songs
+pub const DEFAULT_MUSIC_CROSSFADE_MS: u64 = 1500;
+
+/// File the audio settings are saved to, in the working directory
+pub const AUDIO_CONFIG_FILE: &str = "stackboxes_audio.cfg";
+
+/// File the enabled screen effects are saved to, in the working directory
+pub const GRAPHICS_CONFIG_FILE: &str = "stackboxes_graphics.cfg";
+
+/// File the frame rate cap is saved to, in the working directory
+pub const FRAME_RATE_CONFIG_FILE: &str = "stackboxes_frame_rate.cfg";
+
+/// How much the volume changes per key press
+pub const VOLUME_STEP: f32 = 0.1;
+
+/// Volume levels, all in the range of 0.0 - 1.0
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AudioSettings {
+    pub master_volume: f32,
+    pub music_volume: f32,
+    pub sfx_volume: f32,
+    /// Silences everything, without forgetting the volume levels
+    pub muted: bool,
+    /// Pause the music and sounds while the window is in the background
+    pub pause_when_unfocused: bool,
+}
+
+impl Default for AudioSettings {
+    fn default() -> Self {
+        Self {
+            master_volume: 1.0,
+            music_volume: 0.8,
+            sfx_volume: 1.0,
+            muted: false,
+            pause_when_unfocused: true,
+        }
+    }
+}
+
+impl AudioSettings {
+
+    /// Master volume that should be used, 0.0 if muted
+    pub fn effective_master_volume(&self) -> f32 {
+        if self.muted { 0.0 } else { self.master_volume }
+    }
+
+    pub fn change_master_volume(&mut self, change: f32) {
+        self.master_volume = (self.master_volume + change).max(0.0).min(1.0);
+    }
+
+    /// Loads the settings from a `key = value` file. Missing or invalid keys keep their default
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut settings = Self::default();
+        for (key, value) in read_config_file(path)? {
+            let (key, value) = (key.as_str(), value.as_str());
+
+            let volume = value.parse::<f32>().ok().map(|v| v.max(0.0).min(1.0));
+            let flag = value.parse::<bool>().ok();
+
+            match key {
+                "master_volume" => if let Some(v) = volume { settings.master_volume = v; },
+                "music_volume" => if let Some(v) = volume { settings.music_volume = v; },
+                "sfx_volume" => if let Some(v) = volume { settings.sfx_volume = v; },
+                "muted" => if let Some(f) = flag { settings.muted = f; },
+                "pause_when_unfocused" => if let Some(f) = flag { settings.pause_when_unfocused = f; },
+                _ => println!("unknown audio setting: {:?}", key),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = File::create(path)?;
+        writeln!(file, "master_volume = {}", self.master_volume)?;
+        writeln!(file, "music_volume = {}", self.music_volume)?;
+        writeln!(file, "sfx_volume = {}", self.sfx_volume)?;
+        writeln!(file, "muted = {}", self.muted)?;
+        writeln!(file, "pause_when_unfocused = {}", self.pause_when_unfocused)?;
+        Ok(())
+    }
+}
+
+/// Reads a `key = value` file, lines without a `=` are skipped
+pub fn read_config_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<(String, String)>> {
+    let mut contents = String::new();
+    File::open(path)?.read_to_string(&mut contents)?;
+
+    Ok(contents.lines().filter_map(|line| {
+        let mut parts = line.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => Some((key.trim().to_string(), value.trim().to_string())),
+            _ => None,
+        }
+    }).collect())
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Settings {
+    /// Which screen effects are enabled, saved in `GRAPHICS_CONFIG_FILE`
+    pub post_processing: PostProcessingSettings,
+    /// Frame rate limit, saved in `FRAME_RATE_CONFIG_FILE`. Vsync is applied when the window is created
+    pub frame_rate_cap: FrameRateCap,
+    /// How long the old song fades out while the new song fades in
+    pub music_crossfade: Duration,
+    /// Volume levels, saved in `AUDIO_CONFIG_FILE`
+    pub audio: AudioSettings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            post_processing: PostProcessingSettings::default(),
+            frame_rate_cap: FrameRateCap::default(),
+            music_crossfade: Duration::from_millis(DEFAULT_MUSIC_CROSSFADE_MS),
+            audio: AudioSettings::default(),
+        }
+    }
+}
@@ -0,0 +1,509 @@
+//! Procedural sound effects, in the style of sfxr
+//!
+//! A sound is one oscillator with a volume envelope (attack, sustain, decay),
+//! a pitch slide and an optional pitch jump. The sounds are synthesized once
+//! when the audio thread starts, so there are no sound files to ship.
+
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use audio::{SfxId, AudioContext, AudioCommand, SoundHandle};
+use mixer::{PcmSource, Listener};
+use physics::WorldEvent;
+use camera::Camera;
+use player_state::PlayerSpritePosition;
+use physics::CratePosition;
+
+/// Sample rate of the synthesized sounds
+pub const SFX_SAMPLE_RATE: u32 = 44_100;
+
+/// Impact velocity (pixels per second) at which the landing sound has its base pitch
+pub const CRATE_LANDING_REFERENCE_VELOCITY: f32 = 600.0;
+
+/// Shape of the oscillator
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Waveform {
+    Square,
+    Sawtooth,
+    Sine,
+    /// White noise, a new random value every half period of the frequency
+    Noise,
+}
+
+/// Everything that is needed to synthesize a sound, times in seconds
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SfxParams {
+    pub waveform: Waveform,
+    /// Start frequency in Hz
+    pub base_frequency: f32,
+    /// The sound stops when the frequency slides below this (Hz)
+    pub min_frequency: f32,
+    /// Change of the frequency, in octaves per second
+    pub frequency_slide: f32,
+    /// Pitch jump after `arpeggio_time` seconds, i.e. 1.5 for a fifth up. 1.0 = no jump
+    pub arpeggio_factor: f32,
+    pub arpeggio_time: f32,
+    /// Vibrato depth (fraction of the frequency) and speed (Hz)
+    pub vibrato_depth: f32,
+    pub vibrato_speed: f32,
+    /// Fraction of a period that the square wave is high, 0.0 - 1.0
+    pub duty: f32,
+    pub attack: f32,
+    pub sustain: f32,
+    /// Extra volume at the start of the sustain, fading out over the sustain
+    pub sustain_punch: f32,
+    pub decay: f32,
+    /// 0.0 - 1.0
+    pub volume: f32,
+}
+
+impl SfxParams {
+    /// Length of the sound in seconds
+    pub fn duration(&self) -> f32 {
+        self.attack + self.sustain + self.decay
+    }
+
+    /// Volume of the envelope at time `t`
+    fn envelope(&self, t: f32) -> f32 {
+        if t < self.attack {
+            t / self.attack
+        } else if t < self.attack + self.sustain {
+            let sustain_progress = (t - self.attack) / self.sustain;
+            1.0 + self.sustain_punch * (1.0 - sustain_progress)
+        } else if t < self.duration() {
+            1.0 - (t - self.attack - self.sustain) / self.decay
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Short upwards chirp
+pub const JUMP: SfxParams = SfxParams {
+    waveform: Waveform::Square,
+    base_frequency: 300.0,
+    min_frequency: 20.0,
+    frequency_slide: 4.0,
+    arpeggio_factor: 1.0,
+    arpeggio_time: 0.0,
+    vibrato_depth: 0.0,
+    vibrato_speed: 0.0,
+    duty: 0.3,
+    attack: 0.0,
+    sustain: 0.05,
+    sustain_punch: 0.0,
+    decay: 0.15,
+    volume: 0.35,
+};
+
+/// Coin-like blip with a jump up
+pub const PICKUP: SfxParams = SfxParams {
+    waveform: Waveform::Square,
+    base_frequency: 880.0,
+    min_frequency: 20.0,
+    frequency_slide: 0.0,
+    arpeggio_factor: 1.5,
+    arpeggio_time: 0.06,
+    vibrato_depth: 0.0,
+    vibrato_speed: 0.0,
+    duty: 0.5,
+    attack: 0.0,
+    sustain: 0.06,
+    sustain_punch: 0.5,
+    decay: 0.2,
+    volume: 0.3,
+};
+
+/// Low downwards blip
+pub const DROP: SfxParams = SfxParams {
+    waveform: Waveform::Sawtooth,
+    base_frequency: 400.0,
+    min_frequency: 80.0,
+    frequency_slide: -6.0,
+    arpeggio_factor: 1.0,
+    arpeggio_time: 0.0,
+    vibrato_depth: 0.0,
+    vibrato_speed: 0.0,
+    duty: 0.5,
+    attack: 0.0,
+    sustain: 0.04,
+    sustain_punch: 0.0,
+    decay: 0.12,
+    volume: 0.3,
+};
+
+/// Dull thud, the pitch is changed on playback depending on the impact velocity
+pub const CRATE_LANDING: SfxParams = SfxParams {
+    waveform: Waveform::Noise,
+    base_frequency: 1200.0,
+    min_frequency: 20.0,
+    frequency_slide: -3.0,
+    arpeggio_factor: 1.0,
+    arpeggio_time: 0.0,
+    vibrato_depth: 0.0,
+    vibrato_speed: 0.0,
+    duty: 0.5,
+    attack: 0.0,
+    sustain: 0.03,
+    sustain_punch: 0.8,
+    decay: 0.2,
+    volume: 0.5,
+};
+
+/// Long rumbling crash
+pub const COLLAPSE: SfxParams = SfxParams {
+    waveform: Waveform::Noise,
+    base_frequency: 600.0,
+    min_frequency: 20.0,
+    frequency_slide: -1.5,
+    arpeggio_factor: 1.0,
+    arpeggio_time: 0.0,
+    vibrato_depth: 0.3,
+    vibrato_speed: 12.0,
+    duty: 0.5,
+    attack: 0.0,
+    sustain: 0.3,
+    sustain_punch: 0.4,
+    decay: 0.6,
+    volume: 0.5,
+};
+
+/// Rising fanfare
+pub const NEW_HIGHSCORE: SfxParams = SfxParams {
+    waveform: Waveform::Square,
+    base_frequency: 523.0,
+    min_frequency: 20.0,
+    frequency_slide: 0.5,
+    arpeggio_factor: 2.0,
+    arpeggio_time: 0.15,
+    vibrato_depth: 0.02,
+    vibrato_speed: 8.0,
+    duty: 0.4,
+    attack: 0.01,
+    sustain: 0.3,
+    sustain_punch: 0.3,
+    decay: 0.3,
+    volume: 0.35,
+};
+
+impl SfxId {
+    pub const ALL: [SfxId;6] = [
+        SfxId::Jump, SfxId::Pickup, SfxId::Drop, SfxId::CrateLanding, SfxId::Collapse, SfxId::NewHighscore
+    ];
+
+    pub fn params(&self) -> SfxParams {
+        match *self {
+            SfxId::Jump => JUMP,
+            SfxId::Pickup => PICKUP,
+            SfxId::Drop => DROP,
+            SfxId::CrateLanding => CRATE_LANDING,
+            SfxId::Collapse => COLLAPSE,
+            SfxId::NewHighscore => NEW_HIGHSCORE,
+        }
+    }
+}
+
+/// Renders the sound as mono PCM
+pub fn synthesize(params: &SfxParams, sample_rate: u32) -> Vec<i16> {
+    let sample_count = (params.duration() * sample_rate as f32) as usize;
+    let dt = 1.0 / sample_rate as f32;
+
+    let mut samples = Vec::with_capacity(sample_count);
+    let mut phase = 0.0_f32;
+    let mut noise_value = 0.0_f32;
+    let mut rng_state = 0x2545_F491_u32;
+
+    for idx in 0..sample_count {
+        let t = idx as f32 * dt;
+
+        let mut frequency = params.base_frequency * (params.frequency_slide * t).exp2();
+        if params.arpeggio_time > 0.0 && t >= params.arpeggio_time {
+            frequency *= params.arpeggio_factor;
+        }
+        frequency *= 1.0 + params.vibrato_depth * (2.0 * PI * params.vibrato_speed * t).sin();
+
+        if frequency < params.min_frequency {
+            break;
+        }
+
+        let previous_phase = phase;
+        phase = (phase + frequency * dt).fract();
+
+        let oscillator = match params.waveform {
+            Waveform::Square => if phase < params.duty { 1.0 } else { -1.0 },
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+            Waveform::Noise => {
+                // new random value every half period
+                if (phase * 2.0) as u32 != (previous_phase * 2.0) as u32 || idx == 0 {
+                    noise_value = next_random(&mut rng_state) * 2.0 - 1.0;
+                }
+                noise_value
+            },
+        };
+
+        let value = oscillator * params.envelope(t) * params.volume;
+        samples.push((value.max(-1.0).min(1.0) * 32767.0) as i16);
+    }
+
+    samples
+}
+
+/// xorshift, returns a value in the range of 0.0 - 1.0
+fn next_random(state: &mut u32) -> f32 {
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 17;
+    x ^= x << 5;
+    *state = x;
+    x as f32 / ::std::u32::MAX as f32
+}
+
+/// Synthesizes all sound effects of the game
+pub fn build_sound_bank() -> Vec<(SfxId, PcmSource)> {
+    SfxId::ALL.iter().map(|id| {
+        let samples = synthesize(&id.params(), SFX_SAMPLE_RATE);
+        (*id, PcmSource::new(Arc::new(samples), 1, SFX_SAMPLE_RATE))
+    }).collect()
+}
+
+/// What a sound effect is attached to
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SoundAnchor {
+    /// Fixed position in the world, in pixels
+    World { x: f32, y: f32 },
+    /// Moves with the player
+    Player,
+    /// Moves with the crate that has this id, also while it is carried
+    Crate(u32),
+}
+
+impl SoundAnchor {
+    /// Current position of the anchor in the world. None if the crate doesn't exist anymore
+    pub fn position(&self, player_position: &PlayerSpritePosition, crates: &[CratePosition]) -> Option<(f32, f32)> {
+        match *self {
+            SoundAnchor::World { x, y } => Some((x, y)),
+            SoundAnchor::Player => Some((player_position.x + player_position.width / 2.0,
+                                         player_position.y + player_position.height / 2.0)),
+            SoundAnchor::Crate(id) => crates.iter().find(|c| c.id == id).map(|c| (c.x + c.width / 2.0, c.y + c.height / 2.0)),
+        }
+    }
+}
+
+/// A sound effect that should be played
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SfxTrigger {
+    pub id: SfxId,
+    pub volume: f32,
+    /// Playback speed, 1.0 = original pitch
+    pub pitch: f32,
+    pub anchor: SoundAnchor,
+}
+
+impl SfxTrigger {
+    /// How long the sound plays, with the pitch applied
+    pub fn duration(&self) -> Duration {
+        let seconds = self.id.params().duration() / self.pitch.max(0.1);
+        Duration::new(seconds as u64, (seconds.fract() * 1_000_000_000.0) as u32)
+    }
+}
+
+/// Which sound a world event makes
+pub fn sound_for_event(event: &WorldEvent) -> Option<SfxTrigger> {
+    let trigger = |id, anchor| SfxTrigger { id: id, volume: 1.0, pitch: 1.0, anchor: anchor };
+
+    match *event {
+        WorldEvent::PlayerJumped { .. } => Some(trigger(SfxId::Jump, SoundAnchor::Player)),
+        WorldEvent::CratePickedUp { crate_id, .. } => Some(trigger(SfxId::Pickup, SoundAnchor::Crate(crate_id))),
+        WorldEvent::CrateDropped { crate_id, .. } => Some(trigger(SfxId::Drop, SoundAnchor::Crate(crate_id))),
+        WorldEvent::CrateLanded { impact_velocity, crate_id, .. } => {
+            // harder impacts are louder and lower
+            let strength = (impact_velocity / CRATE_LANDING_REFERENCE_VELOCITY).max(0.1).min(2.0);
+            Some(SfxTrigger {
+                id: SfxId::CrateLanding,
+                volume: (0.4 + strength * 0.3).min(1.0),
+                pitch: 1.5 - strength * 0.5,
+                anchor: SoundAnchor::Crate(crate_id),
+            })
+        },
+        WorldEvent::CrateBroken { x, y } => Some(trigger(SfxId::Collapse, SoundAnchor::World { x: x, y: y })),
+        WorldEvent::NewHighscore { x, y, .. } => Some(trigger(SfxId::NewHighscore, SoundAnchor::World { x: x, y: y })),
+    }
+}
+
+/// A playing sound that moves with the player or a crate
+#[derive(Debug, Copy, Clone)]
+struct AttachedSound {
+    handle: SoundHandle,
+    anchor: SoundAnchor,
+    /// Position that was last sent to the audio thread
+    position: (f32, f32),
+    /// When the sound has finished playing
+    ends_at: Instant,
+}
+
+/// Plays the sound effects of the world events at their position and keeps the
+/// listener and the sounds that follow the player or a crate up to date
+#[derive(Debug, Clone, Default)]
+pub struct SpatialAudio {
+    /// The listener that was last sent to the audio thread
+    listener: Option<Listener>,
+    attached_sounds: Vec<AttachedSound>,
+}
+
+impl SpatialAudio {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call once per frame, with the crates and the events of the frame
+    pub fn update(&mut self, audio_context: &AudioContext, camera: &Camera,
+                  player_position: &PlayerSpritePosition, crates: &[CratePosition], events: &[WorldEvent])
+    {
+        let send = |command| {
+            audio_context.send_msg(command)
+            .map_err(|e| { println!("could not send sound effect: {:}", e); })
+            .unwrap_or(());
+        };
+
+        // -- the listener is in the center of the screen
+        let listener = Listener {
+            x: camera.x + camera.screen_width / 2.0,
+            y: camera.y + camera.screen_height / 2.0,
+            half_width: camera.screen_width / 2.0,
+            half_height: camera.screen_height / 2.0,
+        };
+
+        if self.listener != Some(listener) {
+            send(AudioCommand::SetListener(listener));
+            self.listener = Some(listener);
+        }
+
+        // -- move the sounds that follow the player or a crate. If the crate
+        // was destroyed, the sound stays where the crate was last seen
+        let now = Instant::now();
+
+        self.attached_sounds.retain(|sound| sound.ends_at > now);
+        for sound in self.attached_sounds.iter_mut() {
+            if let Some(position) = sound.anchor.position(player_position, crates) {
+                if position != sound.position {
+                    send(AudioCommand::MoveSfx { handle: sound.handle, x: position.0, y: position.1 });
+                    sound.position = position;
+                }
+            }
+        }
+
+        // -- start the new sounds
+        for (trigger, event) in events.iter().filter_map(|e| sound_for_event(e).map(|t| (t, e))) {
+            let position = trigger.anchor.position(player_position, crates).unwrap_or_else(|| event_position(event));
+
+            let handle = match trigger.anchor {
+                SoundAnchor::World { .. } => None,
+                SoundAnchor::Player | SoundAnchor::Crate(_) => {
+                    let handle = audio_context.new_sound_handle();
+                    self.attached_sounds.push(AttachedSound {
+                        handle: handle,
+                        anchor: trigger.anchor,
+                        position: position,
+                        ends_at: now + trigger.duration(),
+                    });
+                    Some(handle)
+                },
+            };
+
+            send(AudioCommand::PlaySfxAt {
+                id: trigger.id,
+                volume: trigger.volume,
+                pitch: trigger.pitch,
+                x: position.0,
+                y: position.1,
+                handle: handle,
+            });
+        }
+    }
+}
+
+/// Where the event happened
+fn event_position(event: &WorldEvent) -> (f32, f32) {
+    match *event {
+        WorldEvent::CrateLanded { x, y, .. } |
+        WorldEvent::CrateBroken { x, y } |
+        WorldEvent::NewHighscore { x, y, .. } |
+        WorldEvent::PlayerJumped { x, y } |
+        WorldEvent::CratePickedUp { x, y, .. } |
+        WorldEvent::CrateDropped { x, y, .. } => (x, y),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn synthesis_is_deterministic() {
+        // the noise generator always starts with the same seed
+        for id in SfxId::ALL.iter() {
+            assert_eq!(synthesize(&id.params(), SFX_SAMPLE_RATE), synthesize(&id.params(), SFX_SAMPLE_RATE), "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn sound_is_as_long_as_its_envelope() {
+        for id in SfxId::ALL.iter() {
+            let params = id.params();
+            let samples = synthesize(&params, SFX_SAMPLE_RATE);
+            assert_eq!(samples.len(), (params.duration() * SFX_SAMPLE_RATE as f32) as usize, "{:?}", id);
+        }
+
+        // at half the sample rate, there are half the samples
+        let samples = synthesize(&JUMP, SFX_SAMPLE_RATE / 2);
+        assert_eq!(samples.len(), (JUMP.duration() * (SFX_SAMPLE_RATE / 2) as f32) as usize);
+    }
+
+    #[test]
+    fn sound_stops_when_the_pitch_slides_below_the_minimum() {
+        let params = SfxParams { min_frequency: DROP.base_frequency * 0.75, .. DROP };
+        // -6 octaves per second, so a quarter lower after log2(4/3) / 6 seconds
+        let expected = ((4.0_f32 / 3.0).log2() / 6.0 * SFX_SAMPLE_RATE as f32) as usize;
+        let samples = synthesize(&params, SFX_SAMPLE_RATE);
+        assert!(samples.len() < (params.duration() * SFX_SAMPLE_RATE as f32) as usize);
+        assert!((samples.len() as i64 - expected as i64).abs() <= 1, "{} samples", samples.len());
+    }
+
+    fn landing(impact_velocity: f32) -> SfxTrigger {
+        sound_for_event(&WorldEvent::CrateLanded { x: 0.0, y: 0.0, impact_velocity: impact_velocity, crate_id: 3 }).unwrap()
+    }
+
+    #[test]
+    fn harder_landings_are_lower_and_louder() {
+        let soft = landing(CRATE_LANDING_REFERENCE_VELOCITY * 0.5);
+        let normal = landing(CRATE_LANDING_REFERENCE_VELOCITY);
+        let hard = landing(CRATE_LANDING_REFERENCE_VELOCITY * 2.0);
+
+        assert_eq!(normal.pitch, 1.0);
+        assert!(soft.pitch > normal.pitch && normal.pitch > hard.pitch);
+        assert!(soft.volume < normal.volume && normal.volume < hard.volume);
+        assert_eq!(hard.anchor, SoundAnchor::Crate(3));
+
+        // the pitch doesn't go to zero or below for very hard impacts
+        assert_eq!(landing(CRATE_LANDING_REFERENCE_VELOCITY * 100.0).pitch, hard.pitch);
+    }
+
+    #[test]
+    fn crate_sounds_follow_their_crate() {
+        let player = PlayerSpritePosition { x: 0.0, y: 0.0, width: 100.0, height: 100.0 };
+        let mut crates = vec![
+            CratePosition { x: 10.0, y: 20.0, width: 32.0, height: 32.0, rotation: 0.0, palette_variant: 0, id: 7 },
+        ];
+
+        let anchor = sound_for_event(&WorldEvent::CratePickedUp { x: 26.0, y: 36.0, crate_id: 7 }).unwrap().anchor;
+        assert_eq!(anchor.position(&player, &crates), Some((26.0, 36.0)));
+
+        crates[0].x += 100.0;
+        assert_eq!(anchor.position(&player, &crates), Some((126.0, 36.0)));
+
+        // the crate is gone, the sound stays where it was
+        assert_eq!(anchor.position(&player, &[]), None);
+    }
+}
@@ -0,0 +1,132 @@
+//! Recompiles shaders when their files change, so that effects can be tweaked
+//! without restarting the game. Only active in debug builds
+//!
+//! If a shader doesn't compile, the last working program stays in use and the
+//! error is shown on the screen until the file is fixed.
+
+use glium::Program;
+use glium::backend::Facade;
+
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+use context::SHADER_DEFINITIONS;
+use {FastHashMap, ShaderHashMap};
+
+/// Directory of the shader files (the source directory, not the directory of the executable)
+pub const SHADER_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/shaders");
+
+/// How often the shader files are checked for changes
+pub const SHADER_POLL_INTERVAL_MS: u64 = 500;
+
+pub struct ShaderReloader {
+    /// Last modification time of each shader file
+    modified_times: FastHashMap<&'static str, SystemTime>,
+    last_poll: Instant,
+    /// Compile errors, by shader ID. Removed once the shader compiles again
+    pub errors: FastHashMap<&'static str, String>,
+}
+
+impl ShaderReloader {
+
+    pub fn new() -> Self {
+        let mut reloader = Self {
+            modified_times: FastHashMap::<&'static str, SystemTime>::default(),
+            last_poll: Instant::now(),
+            errors: FastHashMap::<&'static str, String>::default(),
+        };
+
+        if cfg!(debug_assertions) {
+            reloader.changed_files();
+        }
+
+        reloader
+    }
+
+    /// Checks the shader files for changes and recompiles all programs that use a changed file.
+    /// Does nothing in release builds
+    pub fn reload_changed<F>(&mut self, display: &F, shader_programs: &mut ShaderHashMap) where F: Facade {
+
+        if !cfg!(debug_assertions) || self.last_poll.elapsed() < Duration::from_millis(SHADER_POLL_INTERVAL_MS) {
+            return;
+        }
+        self.last_poll = Instant::now();
+
+        let changed_files = self.changed_files();
+        if changed_files.is_empty() {
+            return;
+        }
+
+        for shader in SHADER_DEFINITIONS {
+            if !changed_files.contains(&shader.vert_file) && !changed_files.contains(&shader.frag_file) {
+                continue;
+            }
+
+            let sources = read_shader_file(shader.vert_file).and_then(|vert| {
+                read_shader_file(shader.frag_file).map(|frag| (vert, frag))
+            });
+
+            let program = sources.and_then(|(vert, frag)| {
+                Program::from_source(display, &vert, &frag, None).map_err(|e| format!("{}", e))
+            });
+
+            match program {
+                Ok(program) => {
+                    println!("reloaded shader {}", shader.id);
+                    shader_programs.insert(shader.id, program);
+                    self.errors.remove(shader.id);
+                },
+                Err(e) => {
+                    println!("could not reload shader {}: {}", shader.id, e);
+                    self.errors.insert(shader.id, e);
+                },
+            }
+        }
+    }
+
+    /// All current compile errors in one message, for showing them on the screen
+    pub fn error_message(&self) -> Option<String> {
+        if self.errors.is_empty() {
+            return None;
+        }
+
+        let mut ids: Vec<&'static str> = self.errors.keys().cloned().collect();
+        ids.sort();
+
+        let lines: Vec<String> = ids.iter().map(|id| format!("{}: {}", id, self.errors[id])).collect();
+        Some(lines.join("\n"))
+    }
+
+    /// Updates the stored modification times, returns the files that changed since the last call
+    fn changed_files(&mut self) -> Vec<&'static str> {
+        let mut changed = Vec::new();
+
+        for shader in SHADER_DEFINITIONS {
+            for file in [shader.vert_file, shader.frag_file].iter() {
+                let path = format!("{}/{}", SHADER_DIRECTORY, file);
+                let modified = match fs::metadata(&path).and_then(|m| m.modified()) {
+                    Ok(modified) => modified,
+                    Err(_) => continue,
+                };
+
+                let previous = self.modified_times.insert(*file, modified);
+                if previous.is_some() && previous != Some(modified) && !changed.contains(file) {
+                    changed.push(*file);
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+fn read_shader_file(file: &str) -> Result<String, String> {
+    use std::io::Read;
+
+    let path = format!("{}/{}", SHADER_DIRECTORY, file);
+    let mut source = String::new();
+    fs::File::open(&path)
+        .and_then(|mut f| f.read_to_string(&mut source))
+        .map_err(|e| format!("could not read {}: {}", path, e))?;
+    Ok(source)
+}
@@ -0,0 +1,203 @@
+//! Immediate-mode drawing of untextured shapes
+//!
+//! Shapes are tessellated into colored triangles on the CPU and collected in a
+//! `ShapeBatch`, so that many shapes can be drawn with one draw call.
+//! Coordinates are pixels, origin at the bottom left.
+
+use color::Color;
+use std::f32::consts::PI;
+
+/// One corner of a triangle in a `ShapeBatch`
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ShapeVertex {
+    pub position: [f32;2],
+    /// r, g, b, a in the range of 0.0 - 1.0
+    pub color: [f32;4],
+}
+
+implement_vertex!(ShapeVertex, position, color);
+
+/// How the ends of a line look
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LineCap {
+    /// The line ends exactly at the end point
+    Butt,
+    /// The line is extended by half the line width
+    Square,
+    /// A half circle around the end point
+    Round,
+}
+
+/// A list of triangles (three vertices each), ready to be drawn
+#[derive(Debug, Clone, Default)]
+pub struct ShapeBatch {
+    pub vertices: Vec<ShapeVertex>,
+}
+
+impl ShapeBatch {
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn triangle(&mut self, a: (f32, f32), b: (f32, f32), c: (f32, f32), color: Color) {
+        let color = color.to_f32_array();
+        for &(x, y) in [a, b, c].iter() {
+            self.vertices.push(ShapeVertex { position: [x, y], color: color });
+        }
+    }
+
+    /// Quad from four corners, in clockwise or counter-clockwise order
+    pub fn quad(&mut self, a: (f32, f32), b: (f32, f32), c: (f32, f32), d: (f32, f32), color: Color) {
+        self.triangle(a, b, c, color);
+        self.triangle(a, c, d, color);
+    }
+
+    /// Rectangle with the bottom left corner at (x, y)
+    pub fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        self.quad((x, y), (x + width, y), (x + width, y + height), (x, y + height), color);
+    }
+
+    /// Outline of a rectangle, the border is drawn on the inside of the rectangle
+    pub fn stroke_rect(&mut self, x: f32, y: f32, width: f32, height: f32, line_width: f32, color: Color) {
+        let line_width = line_width.min(width / 2.0).min(height / 2.0);
+        let inner_height = height - 2.0 * line_width;
+
+        self.fill_rect(x, y, width, line_width, color);
+        self.fill_rect(x, y + height - line_width, width, line_width, color);
+        self.fill_rect(x, y + line_width, line_width, inner_height, color);
+        self.fill_rect(x + width - line_width, y + line_width, line_width, inner_height, color);
+    }
+
+    pub fn fill_circle(&mut self, center_x: f32, center_y: f32, radius: f32, color: Color) {
+        self.fill_arc(center_x, center_y, radius, 0.0, 2.0 * PI, color);
+    }
+
+    /// Outline of a circle, centered on the radius
+    pub fn stroke_circle(&mut self, center_x: f32, center_y: f32, radius: f32, line_width: f32, color: Color) {
+        let segments = circle_segments(radius + line_width / 2.0);
+        let inner = (radius - line_width / 2.0).max(0.0);
+        let outer = radius + line_width / 2.0;
+
+        for i in 0..segments {
+            let a0 = i as f32 / segments as f32 * 2.0 * PI;
+            let a1 = (i + 1) as f32 / segments as f32 * 2.0 * PI;
+            self.quad(
+                (center_x + a0.cos() * inner, center_y + a0.sin() * inner),
+                (center_x + a0.cos() * outer, center_y + a0.sin() * outer),
+                (center_x + a1.cos() * outer, center_y + a1.sin() * outer),
+                (center_x + a1.cos() * inner, center_y + a1.sin() * inner),
+                color);
+        }
+    }
+
+    /// Pie slice from `start_angle` to `end_angle` (radians, counter-clockwise, 0 = right)
+    pub fn fill_arc(&mut self, center_x: f32, center_y: f32, radius: f32, start_angle: f32, end_angle: f32, color: Color) {
+        let full_segments = circle_segments(radius);
+        let segments = ((full_segments as f32 * (end_angle - start_angle).abs() / (2.0 * PI)).ceil() as u32).max(1);
+
+        for i in 0..segments {
+            let a0 = start_angle + (end_angle - start_angle) * i as f32 / segments as f32;
+            let a1 = start_angle + (end_angle - start_angle) * (i + 1) as f32 / segments as f32;
+            self.triangle(
+                (center_x, center_y),
+                (center_x + a0.cos() * radius, center_y + a0.sin() * radius),
+                (center_x + a1.cos() * radius, center_y + a1.sin() * radius),
+                color);
+        }
+    }
+
+    /// Single line from (x0, y0) to (x1, y1)
+    pub fn line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, line_width: f32, cap: LineCap, color: Color) {
+        self.polyline(&[(x0, y0), (x1, y1)], line_width, cap, color);
+    }
+
+    /// Connected line segments. Corners are rounded, `cap` is used for the first and last point
+    pub fn polyline(&mut self, points: &[(f32, f32)], line_width: f32, cap: LineCap, color: Color) {
+        if points.len() < 2 {
+            return;
+        }
+
+        let half_width = line_width / 2.0;
+        let last_segment = points.len() - 2;
+
+        for (idx, segment) in points.windows(2).enumerate() {
+            let (mut x0, mut y0) = segment[0];
+            let (mut x1, mut y1) = segment[1];
+
+            let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+            if length == 0.0 {
+                continue;
+            }
+
+            // unit vector along the segment and its normal
+            let (dir_x, dir_y) = ((x1 - x0) / length, (y1 - y0) / length);
+            let (normal_x, normal_y) = (-dir_y * half_width, dir_x * half_width);
+
+            if cap == LineCap::Square {
+                if idx == 0 {
+                    x0 -= dir_x * half_width;
+                    y0 -= dir_y * half_width;
+                }
+                if idx == last_segment {
+                    x1 += dir_x * half_width;
+                    y1 += dir_y * half_width;
+                }
+            }
+
+            self.quad(
+                (x0 + normal_x, y0 + normal_y),
+                (x0 - normal_x, y0 - normal_y),
+                (x1 - normal_x, y1 - normal_y),
+                (x1 + normal_x, y1 + normal_y),
+                color);
+
+            // round join, so that there is no gap between two segments
+            if idx != last_segment {
+                self.fill_circle(x1, y1, half_width, color);
+            }
+        }
+
+        if cap == LineCap::Round {
+            let (first_x, first_y) = points[0];
+            let (last_x, last_y) = points[points.len() - 1];
+            self.fill_circle(first_x, first_y, half_width, color);
+            self.fill_circle(last_x, last_y, half_width, color);
+        }
+    }
+
+    /// Line made of dashes of `dash_length` pixels, separated by `gap_length` pixels.
+    /// The first dash starts at (x0, y0)
+    pub fn dashed_line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, dash_length: f32, gap_length: f32,
+                       line_width: f32, cap: LineCap, color: Color)
+    {
+        let length = ((x1 - x0).powi(2) + (y1 - y0).powi(2)).sqrt();
+        if length == 0.0 || dash_length <= 0.0 {
+            return;
+        }
+
+        let (dir_x, dir_y) = ((x1 - x0) / length, (y1 - y0) / length);
+        let mut distance = 0.0;
+
+        while distance < length {
+            let dash_end = (distance + dash_length).min(length);
+            self.line(x0 + dir_x * distance, y0 + dir_y * distance,
+                      x0 + dir_x * dash_end, y0 + dir_y * dash_end,
+                      line_width, cap, color);
+            distance += dash_length + gap_length.max(0.0);
+        }
+    }
+}
+
+/// Number of triangles for a circle, so that large circles still look round
+fn circle_segments(radius: f32) -> u32 {
+    ((radius * 0.75) as u32).max(8).min(64)
+}
@@ -0,0 +1,370 @@
+//! CPU rasterizer that draws into an RGBA buffer instead of a window
+//!
+//! Used for rendering the game screens without a display (tests, CI).
+//! Coordinates are the same as in the OpenGL renderer: pixels, origin at the bottom left.
+
+use image::{self, RgbaImage, Rgba};
+use rusttype::{Font, FontCollection, Scale, point};
+use std::path::Path;
+use std::io;
+
+use color::Color;
+use font::{FontInstanceId, Text};
+use texture::{TextureId, TextureInstanceId, TextureDrawOptions, SpriteDrawParameters};
+use render_backend::{RenderBackend, AssetLoader};
+use shapes::ShapeBatch;
+use lighting::{LightingFrame, light_falloff};
+use {FastHashMap, FontInstanceIdMap, TextureInstanceIdMap};
+
+pub struct SoftwareRenderer {
+    pub width: u32,
+    pub height: u32,
+    /// The color buffer, top row first (same layout as a PNG file)
+    pub buffer: RgbaImage,
+    /// Decoded images, top row first
+    pub textures: FastHashMap<TextureId, RgbaImage>,
+    /// Parsed fonts by name, rasterized at the size of the `FontInstanceId`
+    pub fonts: FastHashMap<&'static str, Font<'static>>,
+    pub font_ids: FontInstanceIdMap,
+    pub texture_ids: TextureInstanceIdMap,
+}
+
+impl SoftwareRenderer {
+
+    /// Creates a new, black buffer. Does not add any fonts or textures
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width: width,
+            height: height,
+            buffer: RgbaImage::new(width, height),
+            textures: FastHashMap::<TextureId, RgbaImage>::default(),
+            fonts: FastHashMap::<&'static str, Font<'static>>::default(),
+            font_ids: FontInstanceIdMap::default(),
+            texture_ids: TextureInstanceIdMap::default(),
+        }
+    }
+
+    /// Creates a new buffer with the same fonts and textures as the game
+    pub fn with_game_assets(width: u32, height: u32) -> Self {
+        let mut renderer = Self::new(width, height);
+        let (font_ids, texture_ids) = ::render_backend::load_game_assets(&mut renderer);
+        renderer.font_ids = font_ids;
+        renderer.texture_ids = texture_ids;
+        renderer
+    }
+
+    /// Saves the current buffer as a PNG file
+    pub fn save_png<P>(&self, path: P) -> io::Result<()> where P: AsRef<Path> {
+        self.buffer.save(path)
+    }
+
+    /// Compares the buffer with a reference image, returns how many pixels differ
+    /// by more than `tolerance` in any channel. Returns None if the sizes don't match
+    pub fn count_differing_pixels(&self, golden_image: &RgbaImage, tolerance: u8) -> Option<usize> {
+        if golden_image.dimensions() != self.buffer.dimensions() {
+            return None;
+        }
+
+        let differing = self.buffer.pixels().zip(golden_image.pixels()).filter(|&(a, b)| {
+            a.data.iter().zip(b.data.iter()).any(|(ca, cb)| (*ca as i16 - *cb as i16).abs() > tolerance as i16)
+        }).count();
+
+        Some(differing)
+    }
+
+    /// Alpha-blends a color (r, g, b, a in 0.0 - 1.0) onto the pixel at x / y (from the bottom left)
+    fn blend_pixel(&mut self, x: i32, y: i32, color: [f32;4]) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let image_y = self.height - 1 - y as u32;
+        let dst = self.buffer.get_pixel_mut(x as u32, image_y);
+        let src_alpha = color[3].max(0.0).min(1.0);
+
+        for channel in 0..3 {
+            let dst_color = dst.data[channel] as f32 / 255.0;
+            let blended = color[channel] * src_alpha + dst_color * (1.0 - src_alpha);
+            dst.data[channel] = (blended * 255.0).round().max(0.0).min(255.0) as u8;
+        }
+
+        let dst_alpha = dst.data[3] as f32 / 255.0;
+        let blended_alpha = src_alpha + dst_alpha * (1.0 - src_alpha);
+        dst.data[3] = (blended_alpha * 255.0).round() as u8;
+    }
+}
+
+/// Fetches a texel, x / y are counted from the bottom left of the image (like OpenGL texture coordinates)
+fn get_texel(image: &RgbaImage, x: i32, y: i32) -> [f32;4] {
+    let (w, h) = image.dimensions();
+    let x = x.max(0).min(w as i32 - 1) as u32;
+    let y = y.max(0).min(h as i32 - 1) as u32;
+    let pixel = image.get_pixel(x, h - 1 - y);
+    [pixel.data[0] as f32 / 255.0,
+     pixel.data[1] as f32 / 255.0,
+     pixel.data[2] as f32 / 255.0,
+     pixel.data[3] as f32 / 255.0]
+}
+
+/// Samples the image at a texel position (in texels, not normalized)
+fn sample_texture(image: &RgbaImage, x: f32, y: f32, draw_options: &TextureDrawOptions) -> [f32;4] {
+    match *draw_options {
+        TextureDrawOptions::PixelPerfect => {
+            get_texel(image, x.floor() as i32, y.floor() as i32)
+        },
+        TextureDrawOptions::InterpolateTexture => {
+            // bilinear filtering between the four nearest texel centers
+            let x = x - 0.5;
+            let y = y - 0.5;
+            let x0 = x.floor();
+            let y0 = y.floor();
+            let fx = x - x0;
+            let fy = y - y0;
+
+            let a = get_texel(image, x0 as i32,     y0 as i32);
+            let b = get_texel(image, x0 as i32 + 1, y0 as i32);
+            let c = get_texel(image, x0 as i32,     y0 as i32 + 1);
+            let d = get_texel(image, x0 as i32 + 1, y0 as i32 + 1);
+
+            let mut result = [0.0; 4];
+            for i in 0..4 {
+                let bottom = a[i] + (b[i] - a[i]) * fx;
+                let top = c[i] + (d[i] - c[i]) * fx;
+                result[i] = bottom + (top - bottom) * fy;
+            }
+            result
+        },
+    }
+}
+
+/// Twice the signed area of the triangle (a, b, p), positive if p is left of the edge a -> b
+fn edge_function(a: [f32;2], b: [f32;2], px: f32, py: f32) -> f32 {
+    (b[0] - a[0]) * (py - a[1]) - (b[1] - a[1]) * (px - a[0])
+}
+
+impl RenderBackend for SoftwareRenderer {
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn clear_screen(&mut self, color: Color) {
+        let pixel = Rgba { data: [color.r, color.g, color.b, color.a] };
+        for p in self.buffer.pixels_mut() {
+            *p = pixel;
+        }
+    }
+
+    fn get_font(&self, id: &'static str) -> FontInstanceId {
+        *self.font_ids.get(id).unwrap()
+    }
+
+    fn get_texture(&self, id: &'static str) -> TextureId {
+        *self.texture_ids.get(id).unwrap()
+    }
+
+    fn calculate_font_width(&self, id: &FontInstanceId, text: &str) -> f32 {
+        let font = self.fonts.get(id.font_name).unwrap();
+        let scale = Scale::uniform(id.font_size as f32);
+        font.layout(text, scale, point(0.0, 0.0)).last().map(|glyph| {
+            glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+        }).unwrap_or(0.0)
+    }
+
+    fn draw_font(&mut self, text: &Text, color: Color) {
+        let color = color.to_f32_array();
+        let height = self.height as i32;
+
+        // the text position is the baseline, rusttype counts y from the top
+        let coverage = {
+            let font = self.fonts.get(text.font.font_name).unwrap();
+            let scale = Scale::uniform(text.font.font_size as f32);
+            let baseline = point(text.screen_x as f32, self.height as f32 - text.screen_y as f32);
+
+            let mut coverage = Vec::<(i32, i32, f32)>::new();
+            for glyph in font.layout(text.text, scale, baseline) {
+                if let Some(bounding_box) = glyph.pixel_bounding_box() {
+                    glyph.draw(|x, y, v| {
+                        let pixel_x = bounding_box.min.x + x as i32;
+                        let pixel_y = height - 1 - (bounding_box.min.y + y as i32);
+                        coverage.push((pixel_x, pixel_y, v));
+                    });
+                }
+            }
+            coverage
+        };
+
+        for (x, y, v) in coverage {
+            self.blend_pixel(x, y, [color[0], color[1], color[2], color[3] * v]);
+        }
+    }
+
+    fn draw_texture(&mut self, texture_id: &TextureInstanceId, sprite_parameters: &SpriteDrawParameters, options: TextureDrawOptions) {
+        let source_tr = texture_id.source_texture_region.region;
+        let target_tr = texture_id.target_texture_region;
+
+        if target_tr.screen_width == 0 || target_tr.screen_height == 0 {
+            return;
+        }
+
+        // bounding box of the (possibly rotated and scaled) sprite on the screen
+        let corners = [
+            sprite_parameters.transform_point(&target_tr, 0.0, 0.0),
+            sprite_parameters.transform_point(&target_tr, 0.0, 1.0),
+            sprite_parameters.transform_point(&target_tr, 1.0, 0.0),
+            sprite_parameters.transform_point(&target_tr, 1.0, 1.0),
+        ];
+
+        let min_x = corners.iter().map(|c| c.0).fold(::std::f32::MAX, f32::min).floor().max(0.0) as i32;
+        let max_x = corners.iter().map(|c| c.0).fold(::std::f32::MIN, f32::max).ceil().min(self.width as f32) as i32;
+        let min_y = corners.iter().map(|c| c.1).fold(::std::f32::MAX, f32::min).floor().max(0.0) as i32;
+        let max_y = corners.iter().map(|c| c.1).fold(::std::f32::MIN, f32::max).ceil().min(self.height as f32) as i32;
+
+        let tint = sprite_parameters.tint.to_f32_array();
+        let flash = sprite_parameters.flash.to_f32_array();
+        let mut pixels = Vec::new();
+
+        {
+            let image = self.textures.get(&texture_id.source_texture_region.texture_id).unwrap();
+
+            for py in min_y..max_y {
+                for px in min_x..max_x {
+                    let (mut u, mut v) = sprite_parameters.inverse_transform_point(&target_tr, px as f32 + 0.5, py as f32 + 0.5);
+                    if u < 0.0 || u >= 1.0 || v < 0.0 || v >= 1.0 {
+                        continue;
+                    }
+
+                    if sprite_parameters.flip_x { u = 1.0 - u; }
+                    if sprite_parameters.flip_y { v = 1.0 - v; }
+
+                    let source_x = source_tr.bottom_x as f32 + u * source_tr.width as f32;
+                    let source_y = source_tr.bottom_y as f32 + v * source_tr.height as f32;
+
+                    // same as in pixel_to_screen_space.frag.glsl
+                    let mut texel = sample_texture(image, source_x, source_y, &options);
+                    if let Some(ref palette_swap) = sprite_parameters.palette_swap {
+                        let rgb = palette_swap.remap([texel[0], texel[1], texel[2]]);
+                        texel[0] = rgb[0];
+                        texel[1] = rgb[1];
+                        texel[2] = rgb[2];
+                    }
+                    for i in 0..4 {
+                        texel[i] *= tint[i];
+                    }
+                    for i in 0..3 {
+                        texel[i] += (flash[i] - texel[i]) * flash[3];
+                    }
+                    texel[3] *= sprite_parameters.transparency;
+
+                    pixels.push((px, py, texel));
+                }
+            }
+        }
+
+        for (x, y, texel) in pixels {
+            self.blend_pixel(x, y, texel);
+        }
+    }
+
+    fn draw_shapes(&mut self, shapes: &ShapeBatch) {
+        for triangle in shapes.vertices.chunks(3) {
+            if triangle.len() < 3 {
+                continue;
+            }
+
+            let (a, b, c) = (triangle[0].position, triangle[1].position, triangle[2].position);
+            let area = edge_function(a, b, c[0], c[1]);
+            if area == 0.0 {
+                continue;
+            }
+
+            let min_x = a[0].min(b[0]).min(c[0]).floor().max(0.0) as i32;
+            let max_x = a[0].max(b[0]).max(c[0]).ceil().min(self.width as f32) as i32;
+            let min_y = a[1].min(b[1]).min(c[1]).floor().max(0.0) as i32;
+            let max_y = a[1].max(b[1]).max(c[1]).ceil().min(self.height as f32) as i32;
+
+            for y in min_y..max_y {
+                for x in min_x..max_x {
+                    let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+
+                    // barycentric weights, all of them have the same sign as the area if p is inside
+                    let w0 = edge_function(b, c, px, py) / area;
+                    let w1 = edge_function(c, a, px, py) / area;
+                    let w2 = edge_function(a, b, px, py) / area;
+                    if w0 < 0.0 || w1 < 0.0 || w2 < 0.0 {
+                        continue;
+                    }
+
+                    let mut color = [0.0; 4];
+                    for i in 0..4 {
+                        color[i] = triangle[0].color[i] * w0 + triangle[1].color[i] * w1 + triangle[2].color[i] * w2;
+                    }
+                    self.blend_pixel(x, y, color);
+                }
+            }
+        }
+    }
+
+    fn draw_lighting(&mut self, lighting: &LightingFrame) {
+        let ambient = lighting.ambient.to_f32_array();
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (px, py) = (x as f32 + 0.5, y as f32 + 0.5);
+                let mut light = [ambient[0], ambient[1], ambient[2]];
+
+                for point_light in lighting.lights.iter() {
+                    let distance = ((px - point_light.x).powi(2) + (py - point_light.y).powi(2)).sqrt();
+                    if distance >= point_light.radius {
+                        continue;
+                    }
+
+                    // same as the shadow quads in the OpenGL renderer
+                    let in_shadow = lighting.occluders.iter().any(|occluder| {
+                        !occluder.contains(point_light.x, point_light.y) &&
+                        occluder.intersects_segment(point_light.x, point_light.y, px, py) &&
+                        !occluder.contains(px, py)
+                    });
+                    if in_shadow {
+                        continue;
+                    }
+
+                    let brightness = light_falloff(distance, point_light.radius) * point_light.intensity;
+                    let color = point_light.color.to_f32_array();
+                    for i in 0..3 {
+                        light[i] += color[i] * brightness;
+                    }
+                }
+
+                // the buffer is stored top row first
+                let pixel = self.buffer.get_pixel_mut(x, self.height - 1 - y);
+                for i in 0..3 {
+                    let lit = pixel.data[i] as f32 * light[i].min(1.0);
+                    pixel.data[i] = lit.round().max(0.0).min(255.0) as u8;
+                }
+            }
+        }
+    }
+}
+
+impl AssetLoader for SoftwareRenderer {
+
+    fn load_font(&mut self, id: &'static str, data: &'static [u8]) {
+        let font = FontCollection::from_bytes(data).into_font().unwrap();
+        self.fonts.insert(id, font);
+    }
+
+    fn load_texture_png(&mut self, id: &'static str, data: &'static [u8]) -> TextureId {
+        let image = image::load(::std::io::Cursor::new(data), image::PNG).unwrap().to_rgba();
+        let id = TextureId { texture_id: id };
+        self.textures.insert(id, image);
+        id
+    }
+
+    fn load_texture_rgba(&mut self, id: &'static str, width: u32, height: u32, pixels: Vec<u8>) -> TextureId {
+        let image = RgbaImage::from_raw(width, height, pixels).unwrap();
+        let id = TextureId { texture_id: id };
+        self.textures.insert(id, image);
+        id
+    }
+}
@@ -1,13 +1,15 @@
 use FastHashMap;
 use std::io::{BufRead, Seek};
-use glium::texture::CompressedSrgbTexture2d;
+use glium::texture::{CompressedSrgbTexture2d, SrgbTexture2d};
 use glium::backend::{Context, Facade};
 use glium::texture::RawImage2d;
 use glium::{DrawParameters, VertexBuffer, Program, Frame};
 use ui::UiRect;
 use std::rc::Rc;
 use ShaderHashMap;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
+use color::Color;
+use image::RgbaImage;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct TextureId {
@@ -15,20 +17,39 @@ pub struct TextureId {
 }
 
 pub struct TextureSystem {
-    // Images used by the renderer
+    // Images used by the renderer (atlas pages and images that were too big for an atlas)
     pub textures: FastHashMap<TextureId, CompressedSrgbTexture2d>,
+    /// Where the loaded images are located inside the atlas pages
+    pub atlas_regions: FastHashMap<TextureId, AtlasRegion>,
+    /// Decoded images that haven't been packed into an atlas yet, see `build_atlases`
+    pub pending_images: Vec<(TextureId, RgbaImage)>,
     pub highest_texture: Cell<f32>,
+    /// Palettes for recoloring sprites, uploaded when they are first used (by palette name)
+    pub palette_textures: RefCell<FastHashMap<&'static str, SrgbTexture2d>>,
 }
 
 impl Default for TextureSystem {
     fn default() -> Self {
         Self {
             textures: FastHashMap::<TextureId, CompressedSrgbTexture2d>::default(),
+            atlas_regions: FastHashMap::<TextureId, AtlasRegion>::default(),
+            pending_images: Vec::new(),
             highest_texture: Cell::new(0.99),
+            palette_textures: RefCell::new(FastHashMap::<&'static str, SrgbTexture2d>::default()),
         }
     }
 }
 
+/// Location of an image inside an atlas page
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AtlasRegion {
+    /// ID of the atlas page in `TextureSystem::textures`
+    pub atlas_id: TextureId,
+    /// Bottom left corner of the image in the atlas (in texture coordinates, from the bottom)
+    pub offset_x: u32,
+    pub offset_y: u32,
+}
+
 /// Width, height and offsets into the texture
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct SourcePixelRegion {
@@ -61,12 +82,108 @@ pub struct TextureInstanceId {
     pub target_texture_region: TargetPixelRegion,
 }
 
+/// Border sizes of a nine-slice image, in pixels of the source image
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NineSliceInsets {
+    pub left: u32,
+    pub right: u32,
+    pub top: u32,
+    pub bottom: u32,
+}
+
+/// A texture region that can be drawn at any size: the corners keep their size,
+/// the edges are only stretched along the edge, the center is stretched in both directions
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NineSliceRegion {
+    pub source_texture_region: SourceTextureRegion,
+    pub insets: NineSliceInsets,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NineSliceInstance {
+    pub nine_slice: NineSliceRegion,
+    pub target_texture_region: TargetPixelRegion,
+}
+
+impl NineSliceInstance {
+
+    /// Splits the image into (up to) nine regular textures. If the target is smaller than
+    /// the borders, the borders are shrunk proportionally
+    pub fn slices(&self) -> Vec<TextureInstanceId> {
+        let source = &self.nine_slice.source_texture_region.region;
+        let insets = &self.nine_slice.insets;
+        let target = &self.target_texture_region;
+
+        // (source start, source size, target start, target size) for the three columns and rows
+        let columns = split_nine_slice_axis(source.bottom_x, source.width, insets.left, insets.right,
+                                            target.screen_bottom_x, target.screen_width);
+        let rows = split_nine_slice_axis(source.bottom_y, source.height, insets.bottom, insets.top,
+                                         target.screen_bottom_y, target.screen_height);
+
+        let mut slices = Vec::with_capacity(9);
+
+        for &(source_y, source_height, target_y, target_height) in rows.iter() {
+            for &(source_x, source_width, target_x, target_width) in columns.iter() {
+                if source_width == 0 || source_height == 0 || target_width == 0 || target_height == 0 {
+                    continue;
+                }
+
+                slices.push(TextureInstanceId {
+                    source_texture_region: SourceTextureRegion {
+                        texture_id: self.nine_slice.source_texture_region.texture_id,
+                        region: SourcePixelRegion {
+                            bottom_x: source_x,
+                            bottom_y: source_y,
+                            width: source_width,
+                            height: source_height,
+                        },
+                    },
+                    target_texture_region: TargetPixelRegion {
+                        screen_bottom_x: target_x,
+                        screen_bottom_y: target_y,
+                        screen_width: target_width,
+                        screen_height: target_height,
+                    },
+                });
+            }
+        }
+
+        slices
+    }
+}
+
+/// Splits one axis of a nine-slice image into start border, center and end border
+fn split_nine_slice_axis(source_start: u32, source_size: u32, inset_start: u32, inset_end: u32,
+                         target_start: u32, target_size: u32) -> [(u32, u32, u32, u32);3]
+{
+    let inset_start = inset_start.min(source_size);
+    let inset_end = inset_end.min(source_size - inset_start);
+    let source_center = source_size - inset_start - inset_end;
+
+    let (target_inset_start, target_inset_end) = if inset_start + inset_end > target_size {
+        let scale = target_size as f32 / (inset_start + inset_end) as f32;
+        let scaled_start = (inset_start as f32 * scale) as u32;
+        (scaled_start, target_size - scaled_start)
+    } else {
+        (inset_start, inset_end)
+    };
+
+    let target_center = target_size - target_inset_start - target_inset_end;
+
+    [
+        (source_start, inset_start, target_start, target_inset_start),
+        (source_start + inset_start, source_center, target_start + target_inset_start, target_center),
+        (source_start + inset_start + source_center, inset_end, target_start + target_inset_start + target_center, target_inset_end),
+    ]
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct PixelScreenVert {
     pub position: [f32;3],
     pub tex_coords: [f32;2],
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum TextureDrawOptions {
     PixelPerfect,
     InterpolateTexture,
@@ -78,84 +195,303 @@ impl Default for TextureDrawOptions {
     }
 }
 
+/// Maximum number of colors in a `Palette`, see `palette_swap.frag.glsl`
+pub const MAX_PALETTE_COLORS: usize = 16;
+
+/// Distance (in RGB, 0.0 - 1.0) up to which a texture color counts as a palette color
+pub const PALETTE_MATCH_THRESHOLD: f32 = 0.15;
+
+/// Colors of a sprite that can be replaced, for drawing variants of the same sprite without new images
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Palette {
+    /// Name of the palette, used for caching the palette texture
+    pub name: &'static str,
+    /// Colors as they are in the texture (the alpha is ignored)
+    pub source_colors: &'static [Color],
+    /// Replacement colors, one list for each variant, in the same order as `source_colors`
+    pub variants: &'static [&'static [Color]],
+}
+
+impl Palette {
+    /// Number of variants, including the original colors
+    pub fn variant_count(&self) -> usize {
+        self.variants.len() + 1
+    }
+
+    /// Palette swap for the variant. 0 is the original sprite (no swap), wraps around
+    pub fn variant(&'static self, index: usize) -> Option<PaletteSwap> {
+        match index % self.variant_count() {
+            0 => None,
+            variant => Some(PaletteSwap { palette: self, variant: variant - 1 }),
+        }
+    }
+
+    /// RGBA pixels of the palette texture, bottom row first: row 0 are the source colors,
+    /// every variant is one row above that
+    pub fn to_texture_pixels(&self) -> Vec<u8> {
+        let mut pixels = Vec::with_capacity(self.source_colors.len() * self.variant_count() * 4);
+        for row in Some(self.source_colors).into_iter().chain(self.variants.iter().cloned()) {
+            for color in row.iter().take(self.source_colors.len()) {
+                pixels.extend_from_slice(&[color.r, color.g, color.b, 255]);
+            }
+        }
+        pixels
+    }
+}
+
+/// Which palette variant a sprite should be drawn with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PaletteSwap {
+    pub palette: &'static Palette,
+    /// Index into `Palette::variants`
+    pub variant: usize,
+}
+
+impl PaletteSwap {
+    /// Replaces a color (r, g, b in 0.0 - 1.0) like `palette_swap.frag.glsl` does
+    pub fn remap(&self, rgb: [f32;3]) -> [f32;3] {
+        let mut best_match = None;
+        let mut best_distance = PALETTE_MATCH_THRESHOLD;
+
+        for (idx, source) in self.palette.source_colors.iter().take(MAX_PALETTE_COLORS).enumerate() {
+            let source = source.to_f32_array();
+            let distance = ((source[0] - rgb[0]).powi(2) + (source[1] - rgb[1]).powi(2) + (source[2] - rgb[2]).powi(2)).sqrt();
+            if distance < best_distance {
+                best_distance = distance;
+                best_match = Some(idx);
+            }
+        }
+
+        match best_match {
+            Some(idx) => {
+                let target = self.palette.variants[self.variant][idx].to_f32_array();
+                [target[0], target[1], target[2]]
+            },
+            None => rgb,
+        }
+    }
+}
+
+/// How a sprite should be drawn, in addition to its target region
+#[derive(Debug, Copy, Clone)]
+pub struct SpriteDrawParameters {
+    /// 0.0 = invisible, 1.0 = opaque
+    pub transparency: f32,
+    /// Mirror the sprite horizontally (for sprites facing the other direction)
+    pub flip_x: bool,
+    /// Mirror the sprite vertically
+    pub flip_y: bool,
+    /// Multiplied with the texture color, white leaves the texture unchanged
+    pub tint: Color,
+    /// Mixed into the texture color, the alpha is the strength (for hit flashes)
+    pub flash: Color,
+    /// Counter-clockwise rotation in radians
+    pub rotation: f32,
+    /// Point to rotate and scale around, relative to the target region (0.5, 0.5 is the center)
+    pub pivot: (f32, f32),
+    /// Scale in x and y direction, around the pivot
+    pub scale: (f32, f32),
+    /// Replaces colors of the sprite (i.e. for player skins), None = original colors
+    pub palette_swap: Option<PaletteSwap>,
+}
+
+impl Default for SpriteDrawParameters {
+    fn default() -> Self {
+        Self {
+            transparency: 1.0,
+            flip_x: false,
+            flip_y: false,
+            tint: Color::white(),
+            flash: Color { r: 255, g: 255, b: 255, a: 0 },
+            rotation: 0.0,
+            pivot: (0.5, 0.5),
+            scale: (1.0, 1.0),
+            palette_swap: None,
+        }
+    }
+}
+
+impl SpriteDrawParameters {
+
+    /// Transforms a point of the target region (0.0 - 1.0 in x and y) into screen pixels
+    pub fn transform_point(&self, target: &TargetPixelRegion, local_x: f32, local_y: f32) -> (f32, f32) {
+        let (pivot_x, pivot_y) = self.pivot_in_pixels(target);
+        let dx = (target.screen_bottom_x as f32 + local_x * target.screen_width as f32 - pivot_x) * self.scale.0;
+        let dy = (target.screen_bottom_y as f32 + local_y * target.screen_height as f32 - pivot_y) * self.scale.1;
+        let (sin, cos) = self.rotation.sin_cos();
+        (pivot_x + dx * cos - dy * sin, pivot_y + dx * sin + dy * cos)
+    }
+
+    /// Inverse of `transform_point`: returns where a screen pixel lies in the target region
+    /// (0.0 - 1.0 if the pixel is covered by the sprite)
+    pub fn inverse_transform_point(&self, target: &TargetPixelRegion, x: f32, y: f32) -> (f32, f32) {
+        let (pivot_x, pivot_y) = self.pivot_in_pixels(target);
+        let (sin, cos) = self.rotation.sin_cos();
+        let dx = x - pivot_x;
+        let dy = y - pivot_y;
+        let unrotated_x = (dx * cos + dy * sin) / self.scale.0;
+        let unrotated_y = (-dx * sin + dy * cos) / self.scale.1;
+        ((pivot_x + unrotated_x - target.screen_bottom_x as f32) / target.screen_width as f32,
+         (pivot_y + unrotated_y - target.screen_bottom_y as f32) / target.screen_height as f32)
+    }
+
+    /// Screen position of the pivot point
+    pub fn pivot_in_pixels(&self, target: &TargetPixelRegion) -> (f32, f32) {
+        (target.screen_bottom_x as f32 + self.pivot.0 * target.screen_width as f32,
+         target.screen_bottom_y as f32 + self.pivot.1 * target.screen_height as f32)
+    }
+}
+
 implement_vertex!(PixelScreenVert, position, tex_coords);
 
+/// Vertex of `TextureSystem::draw_texture_batch`, carries the parameters of its sprite
+#[derive(Debug, Copy, Clone)]
+pub struct SpriteBatchVert {
+    pub position: [f32;3],
+    pub tex_coords: [f32;2],
+    /// Tint of the sprite, the alpha is multiplied with the transparency
+    pub tint: [f32;4],
+    pub flash: [f32;4],
+    /// Row of the palette texture that replaces the colors, 0 = original colors
+    pub palette_row: f32,
+}
+
+implement_vertex!(SpriteBatchVert, position, tex_coords, tint, flash, palette_row);
+
 impl TextureSystem {
 
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn add_png_texture<R, F>(&mut self, id: &'static str, source: R, display: &F)
+    /// Decodes a PNG image. The image is uploaded to the GPU in `build_atlases`
+    pub fn add_png_texture<R>(&mut self, id: &'static str, source: R)
         -> TextureId
-        where R: BufRead + Seek, F: Facade
+        where R: BufRead + Seek
     {
         use image;
         let image = image::load(source, image::PNG).unwrap().to_rgba();
-        let image_dimensions = image.dimensions();
-        let image = RawImage2d::from_raw_rgba_reversed(&image.into_raw(), image_dimensions);
-        let opengl_texture = CompressedSrgbTexture2d::new(display, image).unwrap();
 
         let id = TextureId { texture_id: id };
-        self.textures.insert(id.clone(), opengl_texture);
+        self.pending_images.push((id, image));
         id
     }
 
-    // TODO: group textures by texture_id.source_texture_region.texture_id
-    pub fn draw_texture(&self, frame: &mut Frame, display: &Rc<Context>,
-                        texture_id: &TextureInstanceId, transparency: f32,
-                        shaders: &ShaderHashMap, draw_options: TextureDrawOptions)
+    /// Adds a texture from raw RGBA pixels, top row first. The image is uploaded to the GPU in `build_atlases`
+    pub fn add_rgba_texture(&mut self, id: &'static str, width: u32, height: u32, pixels: Vec<u8>)
+        -> TextureId
     {
-        use glium::{Surface, Blend, Depth};
-        use glium::draw_parameters::DepthTest;
-        use glium::draw_parameters::DepthClamp;
+        let image = RgbaImage::from_raw(width, height, pixels).unwrap();
 
-        let shader = shaders.get(::context::PIXEL_TO_SCREEN_SHADER_ID).unwrap();
-        let texture = self.textures.get(&texture_id.source_texture_region.texture_id).unwrap();
+        let id = TextureId { texture_id: id };
+        self.pending_images.push((id, image));
+        id
+    }
+
+    /// Packs all pending images into atlas pages and uploads them, so that all sprites
+    /// can be drawn from the same texture. Images that are too big get their own texture
+    pub fn build_atlases<F>(&mut self, display: &F) where F: Facade {
+        use atlas::{pack_images, ATLAS_PAGE_IDS};
+
+        let pending_images = ::std::mem::replace(&mut self.pending_images, Vec::new());
+
+        let (pages, placements) = {
+            let images: Vec<&RgbaImage> = pending_images.iter().map(|&(_, ref image)| image).collect();
+            pack_images(&images)
+        };
+
+        for (page_idx, page) in pages.into_iter().enumerate() {
+            let page_id = TextureId { texture_id: ATLAS_PAGE_IDS[page_idx] };
+            let page_dimensions = page.dimensions();
+            let raw_image = RawImage2d::from_raw_rgba_reversed(&page.into_raw(), page_dimensions);
+            self.textures.insert(page_id, CompressedSrgbTexture2d::new(display, raw_image).unwrap());
+        }
+
+        for ((id, image), placement) in pending_images.into_iter().zip(placements.into_iter()) {
+            match placement {
+                Some(placement) => {
+                    // the packer counts from the top, texture coordinates from the bottom
+                    let offset_y = ::atlas::ATLAS_PAGE_SIZE - placement.y - image.height();
+                    self.atlas_regions.insert(id, AtlasRegion {
+                        atlas_id: TextureId { texture_id: ATLAS_PAGE_IDS[placement.page] },
+                        offset_x: placement.x,
+                        offset_y: offset_y,
+                    });
+                },
+                None => {
+                    let image_dimensions = image.dimensions();
+                    let raw_image = RawImage2d::from_raw_rgba_reversed(&image.into_raw(), image_dimensions);
+                    self.textures.insert(id, CompressedSrgbTexture2d::new(display, raw_image).unwrap());
+                },
+            }
+        }
+    }
+
+    /// Translates a region of a loaded image into the region of the atlas page it was packed into
+    pub fn resolve_region(&self, source: &SourceTextureRegion) -> SourceTextureRegion {
+        match self.atlas_regions.get(&source.texture_id) {
+            Some(atlas_region) => SourceTextureRegion {
+                texture_id: atlas_region.atlas_id,
+                region: SourcePixelRegion {
+                    bottom_x: atlas_region.offset_x + source.region.bottom_x,
+                    bottom_y: atlas_region.offset_y + source.region.bottom_y,
+                    width: source.region.width,
+                    height: source.region.height,
+                },
+            },
+            None => *source,
+        }
+    }
+
+    /// Corners of the sprite on the screen, with the texture coordinates in the texture it is
+    /// drawn from (bottom left, top left, bottom right, top right). Returns that texture, too
+    fn sprite_corners(&self, texture_id: &TextureInstanceId, sprite_parameters: &SpriteDrawParameters, z: f32)
+        -> (TextureId, [PixelScreenVert;4])
+    {
+        let source_texture_region = self.resolve_region(&texture_id.source_texture_region);
+        let texture = self.textures.get(&source_texture_region.texture_id).unwrap();
         let (t_w, t_h) = texture.dimensions();
-        let source_tr = &texture_id.source_texture_region.region;
+        let source_tr = &source_texture_region.region;
         let target_tr = &texture_id.target_texture_region;
 
-        let z = self.highest_texture.get();
-        let top_left = PixelScreenVert {
-            position:   [ target_tr.screen_bottom_x as f32,
-                         target_tr.screen_bottom_y as f32 + target_tr.screen_height as f32,
-                         z],
-            tex_coords: [
-                        (( source_tr.bottom_x as f32 / t_w as f32)),
-                        (((source_tr.bottom_y + source_tr.height) as f32 / t_h as f32))
-                        ],
-        };
+        // flipping swaps the texture coordinates of the opposite edges
+        let mut tex_left = source_tr.bottom_x as f32 / t_w as f32;
+        let mut tex_right = (source_tr.bottom_x + source_tr.width) as f32 / t_w as f32;
+        let mut tex_bottom = source_tr.bottom_y as f32 / t_h as f32;
+        let mut tex_top = (source_tr.bottom_y + source_tr.height) as f32 / t_h as f32;
 
-        let top_right = PixelScreenVert {
-            position:   [target_tr.screen_bottom_x as f32 + target_tr.screen_width as f32,
-                         target_tr.screen_bottom_y as f32 + target_tr.screen_height as f32,
-                         z],
-            tex_coords: [
-                        (((source_tr.bottom_x + source_tr.width) as f32 / t_w as f32)),
-                        (((source_tr.bottom_y + source_tr.height) as f32 / t_h as f32))
-                        ],
-        };
+        if sprite_parameters.flip_x { ::std::mem::swap(&mut tex_left, &mut tex_right); }
+        if sprite_parameters.flip_y { ::std::mem::swap(&mut tex_bottom, &mut tex_top); }
 
-        let bottom_left = PixelScreenVert {
-            position:   [target_tr.screen_bottom_x as f32,
-                         target_tr.screen_bottom_y as f32,
-                         z],
-            tex_coords: [
-                        (( source_tr.bottom_x as f32 / t_w as f32)),
-                        (( source_tr.bottom_y as f32 / t_h as f32))
-                        ],
+        // scaling and rotation happen around the pivot point
+        let corner = |local_x: f32, local_y: f32, tex_x: f32, tex_y: f32| {
+            let (x, y) = sprite_parameters.transform_point(target_tr, local_x, local_y);
+            PixelScreenVert {
+                position: [x, y, z],
+                tex_coords: [tex_x, tex_y],
+            }
         };
 
-        let bottom_right = PixelScreenVert {
-            position:   [target_tr.screen_bottom_x as f32 + target_tr.screen_width as f32,
-                         target_tr.screen_bottom_y as f32,
-                         z],
-            tex_coords: [
-                        (((source_tr.bottom_x + source_tr.width) as f32 / t_w as f32)),
-                        (( source_tr.bottom_y as f32 / t_h as f32))
-                        ],
-        };
+        (source_texture_region.texture_id, [
+            corner(0.0, 0.0, tex_left, tex_bottom),
+            corner(0.0, 1.0, tex_left, tex_top),
+            corner(1.0, 0.0, tex_right, tex_bottom),
+            corner(1.0, 1.0, tex_right, tex_top),
+        ])
+    }
+
+    pub fn draw_texture(&self, frame: &mut Frame, display: &Rc<Context>,
+                        texture_id: &TextureInstanceId, sprite_parameters: &SpriteDrawParameters,
+                        shaders: &ShaderHashMap, draw_options: TextureDrawOptions)
+    {
+        use glium::{Surface, Blend, Depth};
+        use glium::draw_parameters::DepthTest;
+        use glium::draw_parameters::DepthClamp;
+
+        let shader = shaders.get(::context::PIXEL_TO_SCREEN_SHADER_ID).unwrap();
+        let z = self.highest_texture.get();
+        let (atlas_id, vertex_buf) = self.sprite_corners(texture_id, sprite_parameters, z);
+        let texture = self.textures.get(&atlas_id).unwrap();
 
         let (w, h) = frame.get_dimensions();
 
@@ -164,13 +500,6 @@ impl TextureSystem {
             TextureDrawOptions::PixelPerfect => texture.sampled().magnify_filter(::glium::uniforms::MagnifySamplerFilter::Nearest),
         };
 
-        let uniforms = uniform!(
-            window_width: w as f32,
-            window_height: h as f32,
-            transparency: transparency,
-            tex: cur_tex,
-        );
-
         let draw_parameters = DrawParameters {
             blend: Blend::alpha_blending(),
             depth: Depth {
@@ -182,10 +511,187 @@ impl TextureSystem {
             .. Default::default()
         };
 
-        let vertex_buf = [bottom_left, top_left, bottom_right, top_right];
         let vbuf = VertexBuffer::new(display, &vertex_buf).unwrap();
-        frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE, shader, &uniforms, &draw_parameters).unwrap();
+
+        match sprite_parameters.palette_swap {
+            None => {
+                let uniforms = uniform!(
+                    window_width: w as f32,
+                    window_height: h as f32,
+                    transparency: sprite_parameters.transparency,
+                    tint: sprite_parameters.tint.to_f32_array(),
+                    flash: sprite_parameters.flash.to_f32_array(),
+                    tex: cur_tex,
+                );
+                frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE, shader, &uniforms, &draw_parameters).unwrap();
+            },
+            Some(palette_swap) => {
+                use glium::uniforms::MinifySamplerFilter;
+
+                self.ensure_palette_texture(display, palette_swap.palette);
+                let palette_textures = self.palette_textures.borrow();
+                let palette_texture = &palette_textures[palette_swap.palette.name];
+
+                let uniforms = uniform!(
+                    window_width: w as f32,
+                    window_height: h as f32,
+                    transparency: sprite_parameters.transparency,
+                    tint: sprite_parameters.tint.to_f32_array(),
+                    flash: sprite_parameters.flash.to_f32_array(),
+                    tex: cur_tex,
+                    palette: palette_texture.sampled()
+                        .magnify_filter(::glium::uniforms::MagnifySamplerFilter::Nearest)
+                        .minify_filter(MinifySamplerFilter::Nearest),
+                    palette_size: palette_swap.palette.source_colors.len().min(MAX_PALETTE_COLORS) as i32,
+                    palette_row: (palette_swap.variant + 1) as i32,
+                );
+                let palette_shader = shaders.get(::context::PALETTE_SWAP_SHADER_ID).unwrap();
+                frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE, palette_shader, &uniforms, &draw_parameters).unwrap();
+            },
+        }
+
         self.highest_texture.set(z - 0.001);
     }
+
+    /// Draws the sprites in the given order. Sprites that are next to each other in the list and
+    /// come from the same atlas page (and use the same palette, if any) are drawn in one draw call
+    pub fn draw_texture_batch(&self, frame: &mut Frame, display: &Rc<Context>,
+                              sprites: &[(TextureInstanceId, SpriteDrawParameters)],
+                              shaders: &ShaderHashMap, draw_options: TextureDrawOptions)
+    {
+        let mut vertices = Vec::<SpriteBatchVert>::with_capacity(sprites.len() * 6);
+        let mut batch_texture: Option<TextureId> = None;
+        let mut batch_palette: Option<&'static Palette> = None;
+
+        for &(ref texture_id, ref sprite_parameters) in sprites {
+            let z = self.highest_texture.get();
+            let (atlas_id, corners) = self.sprite_corners(texture_id, sprite_parameters, z);
+            self.highest_texture.set(z - 0.001);
+
+            let palette = sprite_parameters.palette_swap.map(|swap| swap.palette);
+            let same_texture = batch_texture.map(|t| t == atlas_id).unwrap_or(true);
+            let same_palette = match (batch_palette, palette) {
+                (Some(a), Some(b)) => a.name == b.name,
+                _ => true,
+            };
+
+            if !same_texture || !same_palette {
+                if let Some(texture) = batch_texture {
+                    self.draw_sprite_vertices(frame, display, &vertices, texture, batch_palette, shaders, draw_options);
+                }
+                vertices.clear();
+                batch_palette = None;
+            }
+
+            batch_texture = Some(atlas_id);
+            batch_palette = batch_palette.or(palette);
+
+            // transparency only changes the alpha, so it can be multiplied into the tint
+            let mut tint = sprite_parameters.tint.to_f32_array();
+            tint[3] *= sprite_parameters.transparency;
+            let flash = sprite_parameters.flash.to_f32_array();
+            let palette_row = sprite_parameters.palette_swap.map(|swap| (swap.variant + 1) as f32).unwrap_or(0.0);
+
+            // two triangles: bottom left, top left, bottom right and top left, bottom right, top right
+            for &idx in [0, 1, 2, 1, 2, 3].iter() {
+                vertices.push(SpriteBatchVert {
+                    position: corners[idx].position,
+                    tex_coords: corners[idx].tex_coords,
+                    tint: tint,
+                    flash: flash,
+                    palette_row: palette_row,
+                });
+            }
+        }
+
+        if let Some(texture) = batch_texture {
+            self.draw_sprite_vertices(frame, display, &vertices, texture, batch_palette, shaders, draw_options);
+        }
+    }
+
+    /// One draw call of `draw_texture_batch`
+    fn draw_sprite_vertices(&self, frame: &mut Frame, display: &Rc<Context>, vertices: &[SpriteBatchVert],
+                            texture_id: TextureId, palette: Option<&'static Palette>,
+                            shaders: &ShaderHashMap, draw_options: TextureDrawOptions)
+    {
+        use glium::{Surface, Blend, Depth};
+        use glium::draw_parameters::{DepthTest, DepthClamp};
+        use glium::uniforms::{MagnifySamplerFilter, MinifySamplerFilter};
+
+        if vertices.is_empty() {
+            return;
+        }
+
+        let texture = self.textures.get(&texture_id).unwrap();
+        let (w, h) = frame.get_dimensions();
+
+        let cur_tex = match draw_options {
+            TextureDrawOptions::InterpolateTexture => texture.sampled().magnify_filter(MagnifySamplerFilter::Linear),
+            TextureDrawOptions::PixelPerfect => texture.sampled().magnify_filter(MagnifySamplerFilter::Nearest),
+        };
+
+        let draw_parameters = DrawParameters {
+            blend: Blend::alpha_blending(),
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: true,
+                range: (0.0, 1.0),
+                clamp: DepthClamp::Clamp,
+            },
+            .. Default::default()
+        };
+
+        let vbuf = VertexBuffer::new(display, vertices).unwrap();
+        let shader = shaders.get(::context::SPRITE_BATCH_SHADER_ID).unwrap();
+
+        match palette {
+            None => {
+                let uniforms = uniform!(
+                    window_width: w as f32,
+                    window_height: h as f32,
+                    tex: cur_tex,
+                    palette_size: 0_i32,
+                );
+                frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE_LIST, shader, &uniforms, &draw_parameters).unwrap();
+            },
+            Some(palette) => {
+                self.ensure_palette_texture(display, palette);
+                let palette_textures = self.palette_textures.borrow();
+                let palette_texture = &palette_textures[palette.name];
+
+                let uniforms = uniform!(
+                    window_width: w as f32,
+                    window_height: h as f32,
+                    tex: cur_tex,
+                    palette: palette_texture.sampled()
+                        .magnify_filter(MagnifySamplerFilter::Nearest)
+                        .minify_filter(MinifySamplerFilter::Nearest),
+                    palette_size: palette.source_colors.len().min(MAX_PALETTE_COLORS) as i32,
+                );
+                frame.draw(&vbuf, ::context::NO_INDICES_BUFFER_TRIANGLE_LIST, shader, &uniforms, &draw_parameters).unwrap();
+            },
+        }
+    }
+
+    /// Uploads the palette, if it hasn't been used before
+    fn ensure_palette_texture<F>(&self, display: &F, palette: &Palette) where F: Facade {
+        use std::borrow::Cow;
+        use glium::texture::ClientFormat;
+
+        let mut palette_textures = self.palette_textures.borrow_mut();
+        if palette_textures.contains_key(palette.name) {
+            return;
+        }
+
+        // not using from_raw_rgba_reversed, the first row has to stay at the bottom
+        let raw_image = RawImage2d {
+            data: Cow::Owned(palette.to_texture_pixels()),
+            width: palette.source_colors.len() as u32,
+            height: palette.variant_count() as u32,
+            format: ClientFormat::U8U8U8U8,
+        };
+
+        palette_textures.insert(palette.name, SrgbTexture2d::new(display, raw_image).unwrap());
+    }
 }
 
@@ -6,7 +6,7 @@ use font::FontInstanceId;
 use input::WindowState;
 use std::rc::Rc;
 use std::cell::RefCell;
-use texture::TextureInstanceId;
+use texture::{TextureInstanceId, NineSliceInstance};
 
 #[derive(Clone)]
 pub struct UiRect<T: Clone> {
@@ -38,6 +38,8 @@ pub struct UiRendererData
     pub color: Option<Color>,
     /// Image type (png, tiff, raw)
     pub image: Option<TextureInstanceId>,
+    /// Scalable image (for buttons and panels), drawn after `image`
+    pub nine_slice_image: Option<NineSliceInstance>,
     /// Optional text, font-face, size + position
     pub text: Option<(String, FontInstanceId)>,
     /// Actions associated with this rectangle